@@ -2,7 +2,7 @@ pub mod cagen;
 pub mod proxy;
 
 use anyhow::Context;
-use std::{fs, net::SocketAddr, path::PathBuf};
+use std::{fs, net::SocketAddr, path::PathBuf, time::Duration};
 use typed_builder::TypedBuilder;
 
 use crate::proxy::{handler::HttpHandler, CertificateAuthority};
@@ -12,6 +12,9 @@ use log::info;
 pub struct Builder<T: HttpHandler + Clone> {
     bind: SocketAddr,
     upstream_proxy: Option<String>,
+    upstream_timeout: Duration,
+    #[builder(default)]
+    concurrency: Option<usize>,
     cert: PathBuf,
     key: PathBuf,
     graceful_shutdown: tokio::sync::mpsc::Receiver<()>,
@@ -49,6 +52,8 @@ impl<T: HttpHandler + Clone> Builder<T> {
             .ca(ca.clone())
             .listen_addr(self.bind)
             .upstream_proxy(self.upstream_proxy)
+            .upstream_timeout(self.upstream_timeout)
+            .concurrency(self.concurrency)
             .mitm_filters(self.mitm_filters)
             .handler(self.handler)
             .graceful_shutdown(self.graceful_shutdown)
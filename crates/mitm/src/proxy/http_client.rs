@@ -1,6 +1,7 @@
 use http::{response::Builder, Request, Response};
 use hyper::{body, Body};
 use reqwest::impersonate::Impersonate;
+use std::time::Duration;
 
 use super::error::Error;
 
@@ -10,7 +11,7 @@ pub struct HttpClient {
 }
 
 impl HttpClient {
-    pub fn new(proxy: Option<String>) -> Self {
+    pub fn new(proxy: Option<String>, timeout: Duration) -> Self {
         let mut builder = reqwest::Client::builder();
         if let Some(p) = proxy {
             builder = builder.proxy(reqwest::Proxy::all(p).expect("faild build proxy"));
@@ -19,6 +20,8 @@ impl HttpClient {
             .impersonate(Impersonate::Chrome99)
             .http1_title_case_headers()
             .danger_accept_invalid_certs(true)
+            .connect_timeout(timeout)
+            .timeout(timeout)
             .build()
             .expect("faild build reqwest client");
         Self { inner }
@@ -17,6 +17,7 @@ use tokio::{
     io::{AsyncRead, AsyncWrite},
     net::TcpStream,
     pin,
+    sync::OwnedSemaphorePermit,
 };
 use tokio_rustls::TlsAcceptor;
 
@@ -38,6 +39,9 @@ where
 
     pub http_handler: Arc<H>,
     pub mitm_filter: Arc<MitmFilter>,
+    /// The concurrency permit of the connection, shared by the upgraded tunnels
+    /// so it is released once the last of them ends.
+    pub permit: Option<Arc<OwnedSemaphorePermit>>,
 }
 
 impl<H> MitmProxy<H>
@@ -1,8 +1,9 @@
 use error::Error;
 use handler::{HttpHandler, MitmFilter};
 use mitm::MitmProxy;
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
 use typed_builder::TypedBuilder;
 
 pub use ca::CertificateAuthority;
@@ -32,6 +33,11 @@ where
     /// The certificate authority to use.
     pub ca: CertificateAuthority,
     pub upstream_proxy: Option<String>,
+    /// The upstream connect and request timeout.
+    pub upstream_timeout: Duration,
+    /// The maximum simultaneous intercepted connections, `None` is unlimited.
+    #[builder(default)]
+    pub concurrency: Option<usize>,
     pub mitm_filters: Vec<String>,
     pub handler: H,
     graceful_shutdown: tokio::sync::mpsc::Receiver<()>,
@@ -42,13 +48,29 @@ where
     H: HttpHandler,
 {
     pub async fn start_proxy(mut self) -> Result<(), Error> {
-        let client = HttpClient::new(self.upstream_proxy);
+        let client = HttpClient::new(self.upstream_proxy, self.upstream_timeout);
         let ca = Arc::new(self.ca);
         let http_handler = Arc::new(self.handler);
         let mitm_filter = Arc::new(MitmFilter::new(self.mitm_filters));
 
+        let permits = self
+            .concurrency
+            .map(|permits| Arc::new(Semaphore::new(permits.clamp(1, Semaphore::MAX_PERMITS))));
+
         let tcp_listener = TcpListener::bind(self.listen_addr).await?;
         loop {
+            // at capacity, stop accepting, the new connections wait in the accept backlog
+            let permit = match permits.as_ref() {
+                Some(permits) => tokio::select! {
+                    _ = self.graceful_shutdown.recv() => {
+                        info!("PreAuth Http MITM Proxy shutdown");
+                        return Ok(());
+                    }
+                    permit = permits.clone().acquire_owned() => permit.ok(),
+                },
+                None => None,
+            };
+
             let client = client.clone();
             let ca = Arc::clone(&ca);
             let http_handler = Arc::clone(&http_handler);
@@ -62,11 +84,13 @@ where
 
                 Ok((tcp_stream, _)) = tcp_listener.accept() => {
                     tokio::spawn(async move {
+                        // released once the connection and its CONNECT tunnels are served
                         let mitm_proxy = MitmProxy {
                             ca: ca.clone(),
                             client: client.clone(),
                             http_handler: Arc::clone(&http_handler),
                             mitm_filter: Arc::clone(&mitm_filter),
+                            permit: permit.map(Arc::new),
                         };
 
                         let mut tls_content_type = [0; 1];
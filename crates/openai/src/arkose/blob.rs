@@ -1,16 +1,25 @@
 use super::Type;
-use crate::with_context;
+use reqwest::Client;
 use serde::Deserialize;
 
-/// Get arkose blob payload
-pub async fn get_blob(typed: Type, identifier: Option<String>) -> anyhow::Result<Option<String>> {
+/// Check if the token of the type is bound to the identifier by the blob payload
+pub fn is_bound(typed: Type, identifier: Option<&str>) -> bool {
+    identifier.is_some() && matches!(typed, Type::GPT4 | Type::SignUp)
+}
+
+/// Get arkose blob payload with the client of the solve
+pub async fn get_blob(
+    client: &Client,
+    typed: Type,
+    identifier: Option<String>,
+) -> anyhow::Result<Option<String>> {
     match (typed, identifier) {
         (Type::GPT4, Some(identifier)) => {
             #[derive(Deserialize)]
             struct Blob {
                 data: String,
             }
-            let resp = with_context!(arkose_client)
+            let resp = client
                 .post("https://chat.openai.com/backend-api/sentinel/arkose/dx")
                 .bearer_auth(identifier)
                 .send()
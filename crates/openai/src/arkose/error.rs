@@ -14,6 +14,8 @@ pub enum ArkoseError {
     InvalidPublicKey(String),
     #[error("No solver available or solver is invalid")]
     NoSolverAvailable,
+    #[error("No arkose solver configured and the {0} HAR is unavailable, please configure a solver or refresh the HAR file")]
+    SolverRequired(super::Type),
     #[error("Solver task error: {0}")]
     SolverTaskError(String),
     #[error("Error creating arkose session error ({0:?})")]
@@ -0,0 +1,103 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::Mutex;
+
+/// Result of a single-flight call
+#[derive(Debug, PartialEq)]
+pub enum Flight<R, T> {
+    /// The call ran
+    Ran(R),
+    /// The call is skipped, the reusable result of the call finished while queueing is shared
+    Shared(T),
+}
+
+/// Single-flight, one call is in flight and the concurrent calls queue behind it. The reusable
+/// result of a call is shared with the calls queued behind it, otherwise they run in turn
+pub struct SingleFlight<T> {
+    /// Finished calls
+    finished: AtomicU64,
+    /// Held by the in-flight call, the reusable result of the last finished call
+    last: Mutex<Option<T>>,
+}
+
+impl<T: Clone> Default for SingleFlight<T> {
+    fn default() -> Self {
+        Self {
+            finished: AtomicU64::new(0),
+            last: Mutex::new(None),
+        }
+    }
+}
+
+impl<T: Clone> SingleFlight<T> {
+    /// Run the call behind the in-flight one, the call returns its result and the reusable part
+    /// shared with the queued calls, `None` if not reusable
+    pub async fn run<R, Fut>(&self, call: Fut) -> Flight<R, T>
+    where
+        Fut: Future<Output = (R, Option<T>)>,
+    {
+        let arrived = self.finished.load(Ordering::Acquire);
+        let mut last = self.last.lock().await;
+
+        // a call finished while queueing, share its result if reusable
+        if self.finished.load(Ordering::Acquire) != arrived {
+            if let Some(shared) = last.as_ref() {
+                return Flight::Shared(shared.clone());
+            }
+        }
+
+        let (result, reusable) = call.await;
+        *last = reusable;
+        self.finished.fetch_add(1, Ordering::Release);
+        Flight::Ran(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Flight, SingleFlight};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_single_flight() {
+        let flight = Arc::new(SingleFlight::<&str>::default());
+        let (running, calls) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+
+        let run = |reusable: Option<&'static str>| {
+            let (flight, running, calls) = (flight.clone(), running.clone(), calls.clone());
+            tokio::spawn(async move {
+                flight
+                    .run(async {
+                        // a single call is in flight
+                        assert_eq!(running.fetch_add(1, Ordering::SeqCst), 0);
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        ((), reusable)
+                    })
+                    .await
+            })
+        };
+
+        // The calls without a reusable result queue and run in turn
+        let results = futures::future::join_all((0..3).map(|_| run(None))).await;
+        assert!(results.into_iter().all(|r| r.unwrap() == Flight::Ran(())));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // The reusable result is shared with the queued calls
+        let leader = run(Some("failed"));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let queued = futures::future::join_all((0..3).map(|_| run(None))).await;
+        assert_eq!(leader.await.unwrap(), Flight::Ran(()));
+        assert!(queued
+            .into_iter()
+            .all(|r| r.unwrap() == Flight::Shared("failed")));
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        // A later call is not served the stale result
+        assert_eq!(run(None).await.unwrap(), Flight::Ran(()));
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
+}
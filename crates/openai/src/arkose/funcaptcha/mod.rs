@@ -7,6 +7,7 @@ use super::{crypto, ArkoseSolverContext};
 use crate::arkose::error::ArkoseError;
 use crate::arkose::funcaptcha::model::{SubmitChallenge, TGuessResp};
 use crate::context::arkose::version::ArkoseVersion;
+use crate::context::metrics::Latency;
 use crate::{debug, now_duration, warn, with_context};
 use base64::{engine::general_purpose, Engine as _};
 use reqwest::header;
@@ -408,7 +409,7 @@ impl Session {
                 .await?
                 .bytes()
                 .await?;
-            let b64 = general_purpose::STANDARD.encode(bytes);
+            let b64 = solver_work(move || general_purpose::STANDARD.encode(bytes)).await?;
             b64_imgs.push(b64);
         }
 
@@ -477,3 +478,40 @@ fn get_time_stamp() -> FunResult<String> {
     let since_the_epoch = now_duration()?;
     Ok(since_the_epoch.as_millis().to_string())
 }
+
+/// Run the solver local work (the challenge image base64 encoding, the solving itself is remote),
+/// offloaded to the blocking pool bounded by `solver_workers`, otherwise run inline on the request worker. The wait for a worker is bounded by `solver_queue_timeout`
+async fn solver_work<F, R>(f: F) -> FunResult<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    match with_context!(solver_workers) {
+        Some(semaphore) => {
+            let metrics = with_context!(metrics);
+            let queued = metrics.start_solver_work_wait();
+            let start = std::time::Instant::now();
+            let permit = match with_context!(solver_queue_timeout) {
+                Some(timeout) => match tokio::time::timeout(timeout, semaphore.acquire()).await {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        metrics.record_solver_work_rejection();
+                        return Err(anyhow::anyhow!(
+                            "Solver worker wait timed out after {}s",
+                            timeout.as_secs()
+                        )
+                        .into());
+                    }
+                },
+                None => semaphore.acquire().await,
+            };
+            let _permit = permit.map_err(anyhow::Error::from)?;
+            drop(queued);
+            metrics.record_latency(Latency::SolverWorkQueue, start.elapsed());
+            Ok(tokio::task::spawn_blocking(f)
+                .await
+                .map_err(anyhow::Error::from)?)
+        }
+        None => Ok(f()),
+    }
+}
@@ -1,11 +1,16 @@
 use std::str::FromStr;
 
+use hmac::{digest::KeyInit, Hmac, Mac};
 use hyper::header;
 use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Sha512};
 use typed_builder::TypedBuilder;
 
 use crate::{arkose::error::ArkoseError, with_context};
 
+/// Solver request signature header
+const SIGNATURE_HEADER: &str = "x-signature";
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Solver {
@@ -33,12 +38,12 @@ impl FromStr for Solver {
     }
 }
 
-impl ToString for Solver {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for Solver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Yescaptcha => "yescaptcha".to_string(),
-            Self::Capsolver => "capsolver".to_string(),
-            Self::Fcsrv => "fcsrv".to_string(),
+            Self::Yescaptcha => write!(f, "yescaptcha"),
+            Self::Capsolver => write!(f, "capsolver"),
+            Self::Fcsrv => write!(f, "fcsrv"),
         }
     }
 }
@@ -69,6 +74,117 @@ impl ArkoseSolver {
             limit,
         }
     }
+
+    /// Solver endpoint
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+}
+
+/// Solver request signature algorithm
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignAlgorithm {
+    #[default]
+    HmacSha256,
+    HmacSha512,
+}
+
+impl FromStr for SignAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hmac-sha256" => Ok(Self::HmacSha256),
+            "hmac-sha512" => Ok(Self::HmacSha512),
+            _ => anyhow::bail!("Only support `hmac-sha256` / `hmac-sha512` signature algorithm"),
+        }
+    }
+}
+
+impl ToString for SignAlgorithm {
+    fn to_string(&self) -> String {
+        match self {
+            Self::HmacSha256 => "hmac-sha256".to_string(),
+            Self::HmacSha512 => "hmac-sha512".to_string(),
+        }
+    }
+}
+
+/// Sign the solver requests with the shared key, the signature is sent in the `X-Signature`
+/// header as `<algorithm>=<hex digest>` of the request body
+#[derive(Clone, Debug)]
+pub struct SolverSigning {
+    pub key: String,
+    pub algorithm: SignAlgorithm,
+}
+
+impl SolverSigning {
+    /// Hex encoded signature of the payload
+    pub fn sign(&self, payload: &[u8]) -> String {
+        fn digest<M: Mac + KeyInit>(key: &[u8], payload: &[u8]) -> Vec<u8> {
+            let mut mac = <M as KeyInit>::new_from_slice(key).expect("HMAC accepts any key length");
+            mac.update(payload);
+            mac.finalize().into_bytes().to_vec()
+        }
+
+        let bytes = match self.algorithm {
+            SignAlgorithm::HmacSha256 => digest::<Hmac<Sha256>>(self.key.as_bytes(), payload),
+            SignAlgorithm::HmacSha512 => digest::<Hmac<Sha512>>(self.key.as_bytes(), payload),
+        };
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// `X-Signature` header value of the payload
+    fn header_value(&self, payload: &[u8]) -> String {
+        format!("{}={}", self.algorithm.to_string(), self.sign(payload))
+    }
+}
+
+/// Challenge difficulty, by the number of waves
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Difficulty {
+    Easy,
+    Hard,
+}
+
+impl std::fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Easy => write!(f, "easy"),
+            Self::Hard => write!(f, "hard"),
+        }
+    }
+}
+
+/// Route the easy challenges of a type to a solver of the chain, the hard ones keep the chain order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DifficultyRoute {
+    /// Challenges with at most `max_waves` waves are easy
+    pub max_waves: usize,
+    /// Index of the solver in the chain for the easy challenges, the primary solver is `0`
+    pub solver_index: usize,
+}
+
+impl DifficultyRoute {
+    /// Difficulty of the challenge with the waves
+    pub fn difficulty(&self, waves: usize) -> Difficulty {
+        if waves <= self.max_waves {
+            Difficulty::Easy
+        } else {
+            Difficulty::Hard
+        }
+    }
+
+    /// Solver order of the chain for the difficulty, the routed solver first and the others as the fallback
+    pub fn order(&self, difficulty: Difficulty, len: usize) -> Vec<usize> {
+        match difficulty {
+            Difficulty::Easy if self.solver_index < len => std::iter::once(self.solver_index)
+                .chain((0..len).filter(|index| *index != self.solver_index))
+                .collect(),
+            _ => (0..len).collect(),
+        }
+    }
 }
 
 #[derive(Deserialize, Default, Debug)]
@@ -133,6 +249,8 @@ struct ReqTask0<'a> {
 #[derive(TypedBuilder)]
 pub struct SubmitSolver<'a> {
     arkose_solver: &'a ArkoseSolver,
+    /// Client of the solve
+    client: &'a reqwest::Client,
     #[builder(setter(into), default)]
     image: Option<&'a String>,
     #[builder(setter(into), default)]
@@ -180,12 +298,14 @@ pub async fn submit_task(submit_task: SubmitSolver<'_>) -> anyhow::Result<Vec<i3
         }
     };
 
-    let resp = with_context!(arkose_client)
+    let mut builder = submit_task
+        .client
         .post(&submit_task.arkose_solver.endpoint)
-        .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
-        .body(body)
-        .send()
-        .await?;
+        .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref());
+    if let Some(signing) = with_context!(arkose_solver_signing) {
+        builder = builder.header(SIGNATURE_HEADER, signing.header_value(body.as_bytes()));
+    }
+    let resp = builder.body(body).send().await?;
 
     match resp.error_for_status_ref() {
         Ok(_) => {
@@ -217,3 +337,49 @@ pub async fn submit_task(submit_task: SubmitSolver<'_>) -> anyhow::Result<Vec<i3
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Difficulty, DifficultyRoute, SignAlgorithm, SolverSigning};
+
+    #[test]
+    fn test_solver_signing() {
+        // RFC 4231 test case 2
+        let signing = SolverSigning {
+            key: "Jefe".to_owned(),
+            algorithm: SignAlgorithm::HmacSha256,
+        };
+        assert_eq!(
+            signing.sign(b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            signing.header_value(b"what do ya want for nothing?"),
+            "hmac-sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        let signing = SolverSigning {
+            key: "Jefe".to_owned(),
+            algorithm: SignAlgorithm::HmacSha512,
+        };
+        assert!(signing
+            .sign(b"what do ya want for nothing?")
+            .starts_with("164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554"));
+    }
+
+    #[test]
+    fn test_difficulty_route() {
+        let route = DifficultyRoute {
+            max_waves: 2,
+            solver_index: 2,
+        };
+        assert_eq!(route.difficulty(1), Difficulty::Easy);
+        assert_eq!(route.difficulty(2), Difficulty::Easy);
+        assert_eq!(route.difficulty(3), Difficulty::Hard);
+
+        assert_eq!(route.order(Difficulty::Easy, 3), vec![2, 0, 1]);
+        assert_eq!(route.order(Difficulty::Hard, 3), vec![0, 1, 2]);
+        // The routed solver is out of the chain
+        assert_eq!(route.order(Difficulty::Easy, 2), vec![0, 1]);
+    }
+}
@@ -1,13 +1,14 @@
 mod blob;
 pub mod crypto;
-mod error;
+pub(crate) mod error;
+pub mod flight;
 pub mod funcaptcha;
 pub mod murmur;
 
 use base64::engine::general_purpose;
 use rand::thread_rng;
 use reqwest::Client;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use typed_builder::TypedBuilder;
 
@@ -18,10 +19,14 @@ use reqwest::Method;
 use serde::Deserialize;
 use tokio::sync::OnceCell;
 
+use self::flight::Flight;
 use self::funcaptcha::solver::ArkoseSolver;
 use self::funcaptcha::solver::Solver;
 use self::funcaptcha::solver::SubmitSolver;
 use crate::context::arkose::har;
+use crate::context::arkose::warm::WarmPool;
+use crate::context::decision;
+use crate::debug;
 use crate::generate_random_string;
 use crate::gpt_model::GPTModel;
 use crate::now_duration;
@@ -46,7 +51,8 @@ async fn get_or_init_regex() -> &'static Regex {
         .await
 }
 
-#[derive(Hash, PartialEq, Eq, Debug, Clone, Copy)]
+#[derive(Hash, PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Type {
     GPT3,
     GPT4,
@@ -130,15 +136,29 @@ impl FromStr for Type {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
-            "gpt3" => Ok(Type::GPT3),
-            "gpt4" => Ok(Type::GPT4),
-            "auth" => Ok(Type::Auth),
+            "gpt3" | "chat3" => Ok(Type::GPT3),
+            "gpt4" | "chat4" => Ok(Type::GPT4),
+            "auth" | "auth0" => Ok(Type::Auth),
             "platform" => Ok(Type::Platform),
+            "signup" => Ok(Type::SignUp),
             _ => anyhow::bail!(ArkoseError::InvalidPlatformType(s.to_owned())),
         }
     }
 }
 
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Type::GPT3 => "gpt3",
+            Type::GPT4 => "gpt4",
+            Type::Auth => "auth",
+            Type::Platform => "platform",
+            Type::SignUp => "signup",
+        };
+        f.write_str(s)
+    }
+}
+
 #[derive(TypedBuilder, Clone)]
 pub struct ArkoseContext {
     #[builder(setter(into), default)]
@@ -281,7 +301,8 @@ impl ArkoseToken {
         ];
 
         // If identifier is not empty, get blob
-        if let Ok(Some(blob)) = blob::get_blob(ctx.typed, ctx.identifier.clone()).await {
+        if let Ok(Some(blob)) = blob::get_blob(&ctx.client, ctx.typed, ctx.identifier.clone()).await
+        {
             form.push(("data[blob]", blob));
         }
 
@@ -365,7 +386,9 @@ impl ArkoseToken {
         ));
 
         // If identifier is not empty, get blob
-        if let Ok(Some(blob)) = blob::get_blob(entry.typed, ctx.identifier.clone()).await {
+        if let Ok(Some(blob)) =
+            blob::get_blob(&ctx.client, entry.typed, ctx.identifier.clone()).await
+        {
             entry.body.push_str(&format!("&data[blob]={blob}"));
         }
 
@@ -402,7 +425,23 @@ impl ArkoseToken {
 
     /// Get ArkoseLabs token from context (Support ChatGPT, Platform, Auth)
     #[inline]
-    pub async fn new_from_context(mut ctx: ArkoseContext) -> anyhow::Result<Self> {
+    pub async fn new_from_context(ctx: ArkoseContext) -> anyhow::Result<Self> {
+        decision::record(|d| d.arkose_type = Some(ctx.typed));
+
+        // Take a pre-solved token of the warm pool, the blob bound tokens are solved per request
+        if !blob::is_bound(ctx.typed, ctx.identifier.as_deref()) {
+            if let Some(arkose_token) =
+                with_context!(arkose_warm_pool, ctx.typed).and_then(WarmPool::take)
+            {
+                return Ok(arkose_token);
+            }
+        }
+
+        Self::solve(ctx).await
+    }
+
+    /// Solve ArkoseLabs token from context, bypassing the warm pool
+    pub(crate) async fn solve(mut ctx: ArkoseContext) -> anyhow::Result<Self> {
         // If enable gpt3 arkoselabs experiment
         if ctx.typed.eq(&Type::GPT3)
             && with_context!(arkose_gpt3_experiment)
@@ -428,8 +467,8 @@ impl ArkoseToken {
             return Ok(ArkoseToken::from(fake_token));
         }
 
-        // Get arkose solver
-        let arkose_solver = with_context!(arkose_solver);
+        // Get arkose solver chain
+        let arkose_solver = with_context!(arkose_solver_chain);
         let typed = ctx.typed;
 
         // If har path is not empty, use har file
@@ -440,27 +479,31 @@ impl ArkoseToken {
                 .arkose_token(arkose_token)
                 .client(ctx.client)
                 .build();
-            return Ok(valid_arkose_token(arkose_solver, solver_context).await);
+            return Ok(valid_arkose_token(&arkose_solver, solver_context).await);
         }
 
-        // If arkose solver is not empty, use bx
-        if arkose_solver.is_some() {
-            let arkose_token = ArkoseToken::new(&mut ctx).await?;
-            let solver_context = ArkoseSolverContext::builder()
-                .user_agent(ctx.user_agent)
-                .typed(typed)
-                .arkose_token(arkose_token)
-                .client(ctx.client)
-                .build();
-            return Ok(valid_arkose_token(arkose_solver, solver_context).await);
+        // If arkose solver is empty, the HAR is the only source
+        if arkose_solver.is_empty() {
+            if !SOLVER_MISSING_LOGGED.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                warn!("No arkose solver configured, the {typed} HAR is unavailable, please configure a solver or refresh the HAR file");
+            }
+            return Err(ArkoseError::SolverRequired(typed).into());
         }
 
-        Err(ArkoseError::NoSolverAvailable.into())
+        // Arkose solver is not empty, use bx
+        let arkose_token = ArkoseToken::new(&mut ctx).await?;
+        let solver_context = ArkoseSolverContext::builder()
+            .user_agent(ctx.user_agent)
+            .typed(typed)
+            .arkose_token(arkose_token)
+            .client(ctx.client)
+            .build();
+        Ok(valid_arkose_token(&arkose_solver, solver_context).await)
     }
 
-    /// Callback to arkose
+    /// Callback to arkose with the client of the solve
     #[inline]
-    pub async fn callback(&self) -> ArkoseResult<()> {
+    pub async fn callback(&self, client: &Client) -> ArkoseResult<()> {
         // Split the data string by the "|" delimiter
         let elements: Vec<&str> = self.token.split('|').collect();
 
@@ -497,7 +540,7 @@ impl ArkoseToken {
             callback_data.push(format!("data[site]={}", typed.site_url()));
             let callback_query = callback_data.join("&");
 
-            let result = with_context!(arkose_client)
+            let result = client
                 .get(format!("{}/fc/a/?{callback_query}", typed.origin_url()))
                 .timeout(std::time::Duration::from_secs(5))
                 .send()
@@ -513,43 +556,158 @@ impl ArkoseToken {
     }
 }
 
+/// Log the missing solver once
+static SOLVER_MISSING_LOGGED: std::sync::atomic::AtomicBool =
+    std::sync::atomic::AtomicBool::new(false);
+
 async fn valid_arkose_token(
-    arkose_solver: Option<&'static ArkoseSolver>,
+    arkose_solver_chain: &[ArkoseSolver],
     ctx: ArkoseSolverContext,
 ) -> ArkoseToken {
     // If success, return token
     if ctx.arkose_token.success() {
         // Submit token to funcaptcha callback
-        let _ = ctx.arkose_token.callback().await;
+        let _ = ctx.arkose_token.callback(&ctx.client).await;
+        return ctx.arkose_token;
+    }
+
+    if arkose_solver_chain.is_empty() {
+        warn!(
+            "Funcaptcha solver error: {}",
+            ArkoseError::NoSolverAvailable
+        );
         return ctx.arkose_token;
     }
 
-    // If arkose solver is not empty, use solver
-    match submit_funcaptcha(arkose_solver, &ctx).await {
-        Ok(arkose_token) => {
-            return arkose_token;
+    // Queue behind the in-flight solve of the same type, the failure is shared with the queued solves
+    let solved = match with_context!(solve_flight, ctx.typed) {
+        Some(flight) => {
+            let solve = async {
+                let solved = solve_with_chain(arkose_solver_chain, &ctx).await;
+                let failed = solved.is_none().then_some(());
+                (solved, failed)
+            };
+            match flight.run(solve).await {
+                Flight::Ran(solved) => solved,
+                Flight::Shared(()) => {
+                    debug!(
+                        "Funcaptcha {} solve in flight failed, not solved again",
+                        ctx.typed
+                    );
+                    None
+                }
+            }
         }
-        Err(err) => {
-            warn!("Funcaptcha solver error: {err}");
-            return ctx.arkose_token;
+        None => solve_with_chain(arkose_solver_chain, &ctx).await,
+    };
+
+    solved.unwrap_or(ctx.arkose_token)
+}
+
+/// Solve with the solver chain, `None` if every solver failed
+async fn solve_with_chain(
+    arkose_solver_chain: &[ArkoseSolver],
+    ctx: &ArkoseSolverContext,
+) -> Option<ArkoseToken> {
+    // Route by the challenge difficulty if configured for the type, the challenge is started once
+    // to count its waves and handed to the first solver
+    let len = arkose_solver_chain.len();
+    let (order, mut session, difficulty) = match with_context!(arkose_solver_route, ctx.typed) {
+        Some(route) => match funcaptcha::start_challenge(ctx).await {
+            Ok(session) => {
+                let waves = session.funcaptcha().map(Vec::len).unwrap_or_default();
+                let difficulty = route.difficulty(waves);
+                debug!(
+                    "Funcaptcha {} challenge has {waves} waves ({difficulty})",
+                    ctx.typed
+                );
+                (
+                    route.order(difficulty, len),
+                    Some(session),
+                    Some(difficulty),
+                )
+            }
+            Err(err) => {
+                warn!("Funcaptcha {} start challenge error: {err}", ctx.typed);
+                ((0..len).collect(), None, None)
+            }
+        },
+        None => ((0..len).collect(), None, None),
+    };
+
+    // Try the solvers in order, the first success wins
+    for index in order {
+        let arkose_solver = &arkose_solver_chain[index];
+        match submit_funcaptcha(arkose_solver, ctx, session.take()).await {
+            Ok((arkose_token, solved)) => {
+                if solved {
+                    with_context!().solver_stats().record_solved_by(
+                        index,
+                        arkose_solver,
+                        difficulty,
+                    );
+                }
+                decision::record(|d| d.solver = Some(format!("{}#{index}", arkose_solver.solver)));
+                return Some(arkose_token);
+            }
+            Err(err) => {
+                warn!(
+                    "Funcaptcha solver #{index} ({:?}) error: {err}",
+                    arkose_solver.solver
+                );
+            }
         }
     }
+
+    None
 }
 
 async fn submit_funcaptcha(
-    arkose_solver: Option<&'static ArkoseSolver>,
+    arkose_solver: &ArkoseSolver,
     ctx: &ArkoseSolverContext,
-) -> ArkoseResult<ArkoseToken> {
-    // Try get arkose solver
-    let arkose_solver = arkose_solver.ok_or_else(|| ArkoseError::NoSolverAvailable)?;
-
-    // Start challenge, return session
-    let session = funcaptcha::start_challenge(&ctx).await?;
+    session: Option<funcaptcha::Session>,
+) -> ArkoseResult<(ArkoseToken, bool)> {
+    // Start challenge unless already started, return session
+    let session = match session {
+        Some(session) => session,
+        None => funcaptcha::start_challenge(&ctx).await?,
+    };
 
     let funs = session
         .funcaptcha()
         .ok_or_else(|| ArkoseError::InvalidFunCaptcha)?;
 
+    // Only the solver call and the answer check count in the solver stats, the challenge
+    // without images is passed without a solver
+    let solved = !funs.is_empty();
+    let result = async {
+        let answers = solve_funcaptcha(arkose_solver, &ctx.client, funs).await?;
+        session.submit_answer(answers.as_slice()).await?;
+        Ok::<_, ArkoseError>(answers)
+    }
+    .await;
+    if solved {
+        with_context!()
+            .solver_stats()
+            .record(ctx.typed, result.is_ok());
+    }
+    let answers = result?;
+
+    // Store funcaptcha solved image
+    if let Some(dir) = with_context!(arkose_solver_image_dir) {
+        tokio::spawn(session.save_funcaptcha_to_dir(dir, answers));
+    }
+
+    let new_token = ctx.arkose_token.value().replace("at=40", "at=40|sup=1");
+    Ok((ArkoseToken::from(new_token), solved))
+}
+
+/// Solve the funcaptcha images with the solver, return the answers
+async fn solve_funcaptcha(
+    arkose_solver: &ArkoseSolver,
+    client: &Client,
+    funs: &[funcaptcha::model::FunCaptcha],
+) -> ArkoseResult<Vec<i32>> {
     let mut answers = Vec::new();
 
     match arkose_solver.solver {
@@ -557,6 +715,7 @@ async fn submit_funcaptcha(
             for (_, fun) in funs.iter().enumerate() {
                 let submit_task = SubmitSolver::builder()
                     .arkose_solver(arkose_solver)
+                    .client(client)
                     .question(&fun.instructions)
                     .image(&fun.image)
                     .build();
@@ -588,6 +747,7 @@ async fn submit_funcaptcha(
                 for (_, images) in images_chunks.into_iter().enumerate() {
                     let submit_task = SubmitSolver::builder()
                         .arkose_solver(arkose_solver)
+                        .client(client)
                         .question(&data.0)
                         .images(images)
                         .build();
@@ -597,14 +757,33 @@ async fn submit_funcaptcha(
         }
     };
 
-    // Submit answers
-    let _ = session.submit_answer(answers.as_slice()).await?;
+    Ok(answers)
+}
 
-    // Store funcaptcha solved image
-    if let Some(dir) = with_context!(arkose_solver_image_dir) {
-        tokio::spawn(session.save_funcaptcha_to_dir(dir, answers));
+#[cfg(test)]
+mod tests {
+    use super::Type;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_type_round_trip() {
+        for t in [
+            Type::GPT3,
+            Type::GPT4,
+            Type::Auth,
+            Type::Platform,
+            Type::SignUp,
+        ] {
+            assert_eq!(Type::from_str(&t.to_string()).unwrap(), t);
+        }
     }
 
-    let new_token = ctx.arkose_token.value().replace("at=40", "at=40|sup=1");
-    Ok(ArkoseToken::from(new_token))
+    #[test]
+    fn test_type_from_str_alias() {
+        assert_eq!(Type::from_str("chat3").unwrap(), Type::GPT3);
+        assert_eq!(Type::from_str("Chat4").unwrap(), Type::GPT4);
+        assert_eq!(Type::from_str("auth0").unwrap(), Type::Auth);
+        assert_eq!(Type::from_str("PLATFORM").unwrap(), Type::Platform);
+        assert!(Type::from_str("unknown").is_err());
+    }
 }
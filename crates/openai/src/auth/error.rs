@@ -3,6 +3,8 @@ pub enum AuthError {
     /// Request Error
     #[error(transparent)]
     FailedRequest(#[from] reqwest::Error),
+    #[error("{0}")]
+    ClientAtCapacity(#[from] crate::client::ClientAtCapacity),
     #[error("Bad request (error {0})")]
     BadRequest(String),
     #[error("Too many requests ({0})")]
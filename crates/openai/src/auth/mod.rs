@@ -5,7 +5,7 @@ pub mod provide;
 extern crate regex;
 
 use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -53,6 +53,11 @@ pub struct AuthClient {
 }
 
 impl AuthClient {
+    /// Get the inner reqwest client
+    pub(crate) fn inner(&self) -> &Client {
+        &self.inner
+    }
+
     pub async fn refresh_session(&self, session: &str) -> AuthResult<model::AccessToken> {
         let resp = self
             .inner
@@ -400,6 +405,12 @@ impl AuthClientBuilder {
         self
     }
 
+    /// Only use HTTP/1.
+    pub fn http1_only(mut self) -> Self {
+        self.0 = self.0.http1_only();
+        self
+    }
+
     /// Set that all sockets have `SO_KEEPALIVE` set with the supplied duration.
     ///
     /// If `None`, the option will not be set.
@@ -449,6 +460,18 @@ impl AuthClientBuilder {
         self
     }
 
+    /// Override DNS resolution for the domain to the address.
+    pub fn resolve(mut self, domain: &str, addr: SocketAddr) -> Self {
+        self.0 = self.0.resolve(domain, addr);
+        self
+    }
+
+    /// Add a custom root certificate.
+    pub fn add_root_certificate(mut self, cert: reqwest::Certificate) -> Self {
+        self.0 = self.0.add_root_certificate(cert);
+        self
+    }
+
     /// Controls the use of certificate validation.
     pub fn danger_accept_invalid_certs(mut self, enable: bool) -> Self {
         self.0 = self.0.danger_accept_invalid_certs(enable);
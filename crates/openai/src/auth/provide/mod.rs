@@ -112,14 +112,17 @@ impl<'a> RequestContext<'a> {
     async fn load_arkose_token(&mut self) -> AuthResult<()> {
         let arkose_token = match self.account.arkose_token.as_deref() {
             Some(arkose_token) => ArkoseToken::from(arkose_token),
-            None => arkose::ArkoseToken::new_from_context(
-                ArkoseContext::builder()
-                    .client(with_context!(arkose_client))
-                    .typed(Type::Auth)
-                    .build(),
-            )
-            .await
-            .map_err(AuthError::InvalidArkoseToken)?,
+            None => {
+                let arkose_client = with_context!(arkose_client).await?;
+                arkose::ArkoseToken::new_from_context(
+                    ArkoseContext::builder()
+                        .client(arkose_client.client())
+                        .typed(Type::Auth)
+                        .build(),
+                )
+                .await
+                .map_err(AuthError::InvalidArkoseToken)?
+            }
         };
 
         self.cookie
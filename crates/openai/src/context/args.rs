@@ -1,21 +1,46 @@
-use crate::{arkose::funcaptcha::solver::ArkoseSolver, proxy};
+use crate::{
+    arkose::{
+        self,
+        funcaptcha::solver::{ArkoseSolver, DifficultyRoute, SolverSigning},
+    },
+    client::{BalanceStrategy, StickyKey},
+    context::{event::EventCallback, preauth::PreauthPushPolicy},
+    proxy,
+};
 use reqwest::impersonate::Impersonate;
 use std::{
+    collections::HashMap,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     path::PathBuf,
+    sync::Arc,
 };
 use typed_builder::TypedBuilder;
 
+/// Rebuild the args from the config source
+pub type ConfigReloader = Arc<dyn Fn() -> anyhow::Result<Args> + Send + Sync>;
+
 #[derive(TypedBuilder, Clone, Default)]
 pub struct Args {
-    /// Server bind address
-    #[builder(setter(into), default = Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 7999)))]
-    pub(crate) bind: Option<SocketAddr>,
+    /// Server bind addresses
+    #[builder(setter(into), default = vec![DEFAULT_BIND])]
+    pub(crate) bind: Vec<SocketAddr>,
+
+    /// Admin endpoints bind address, shares the server bind addresses if `None`
+    #[builder(setter(into), default)]
+    pub(crate) admin_bind: Option<SocketAddr>,
 
     /// Server concurrent limit (Enforces a limit on the concurrent number of requests the underlying)
     #[builder(setter(into), default = 65535)]
     pub(crate) concurrent_limit: usize,
 
+    /// Server request queue depth of the requests over the concurrent limit, `None` is unbounded
+    #[builder(setter(into), default)]
+    pub(crate) queue_depth: Option<usize>,
+
+    /// Server request queue timeout (second), `None` waits until the server timeout
+    #[builder(setter(into), default)]
+    pub(crate) queue_timeout: Option<u64>,
+
     /// Enabled Cookie Store
     #[builder(default = false)]
     pub(crate) cookie_store: bool,
@@ -24,18 +49,50 @@ pub struct Args {
     #[builder(default = false)]
     pub(crate) fastest_dns: bool,
 
+    /// Client static DNS overrides (host, ip)
+    #[builder(setter(into), default)]
+    pub(crate) dns_overrides: Vec<(String, IpAddr)>,
+
     /// Server/Client TCP keepalive (second)
     #[builder(setter(into), default = 75)]
     pub(crate) tcp_keepalive: usize,
 
+    /// Server shutdown grace period (second)
+    #[builder(setter(into), default = 30)]
+    pub(crate) shutdown_grace_secs: u64,
+
     /// Disable Http Client Keepalive
     #[builder(default = false)]
     pub(crate) no_keepalive: bool,
 
+    /// Client opens a fresh connection per request, no connection is reused
+    #[builder(default = false)]
+    pub(crate) disable_connection_reuse: bool,
+
+    /// Client follows the upstream redirects, otherwise the 3xx responses are returned as is
+    #[builder(default = true)]
+    pub(crate) follow_redirects: bool,
+
+    /// Client maximum redirects followed per request
+    #[builder(setter(into), default = 10)]
+    pub(crate) max_redirects: usize,
+
     /// Keep the client alive on an idle socket with an optional timeout set
     #[builder(setter(into), default = 90)]
     pub(crate) pool_idle_timeout: usize,
 
+    /// Server end-to-end request pipeline limit (including the arkose solve step)
+    #[builder(setter(into), default)]
+    pub(crate) pipeline_limit: Option<usize>,
+
+    /// Server active connections limit per client ip
+    #[builder(setter(into), default)]
+    pub(crate) max_conns_per_ip: Option<usize>,
+
+    /// Random jitter (milliseconds) added to the Retry-After of the 429 rejections
+    #[builder(setter(into), default)]
+    pub(crate) retry_after_jitter_ms: u64,
+
     /// Server/Client timeout
     #[builder(setter(into), default = 600)]
     pub(crate) timeout: usize,
@@ -44,6 +101,30 @@ pub struct Args {
     #[builder(setter(into), default = 60)]
     pub(crate) connect_timeout: usize,
 
+    /// Proxy response body read idle timeout
+    #[builder(setter(into), default)]
+    pub(crate) read_idle_timeout: Option<usize>,
+
+    /// Proxy response body size limit (bytes)
+    #[builder(setter(into), default)]
+    pub(crate) max_upstream_body_size: Option<u64>,
+
+    /// Proxy request SLO deadline (milliseconds)
+    #[builder(setter(into), default)]
+    pub(crate) slo_ms: Option<u64>,
+
+    /// Proxy request budget (milliseconds)
+    #[builder(setter(into), default)]
+    pub(crate) request_budget_ms: Option<u64>,
+
+    /// Upstream error cache cooldown (seconds)
+    #[builder(setter(into), default)]
+    pub(crate) error_cache_ttl: Option<u64>,
+
+    /// Consecutive upstream failures to start the error cache cooldown
+    #[builder(setter(into), default = 5)]
+    pub(crate) error_cache_threshold: usize,
+
     /// Disable direct connection
     #[builder(default = false)]
     pub(crate) enable_direct: bool,
@@ -52,10 +133,90 @@ pub struct Args {
     #[builder(setter(into), default)]
     pub(crate) proxies: Vec<proxy::Proxy>,
 
+    /// Auth client proxies, override the shared proxies for the auth client
+    #[builder(setter(into), default)]
+    pub(crate) auth_proxies: Option<Vec<proxy::Proxy>>,
+
+    /// Auth client bound interface, overrides the shared interfaces for the auth client
+    #[builder(setter(into), default)]
+    pub(crate) auth_interface: Option<IpAddr>,
+
+    /// Auth client timeout, overrides the shared timeout for the auth client
+    #[builder(setter(into), default)]
+    pub(crate) auth_timeout: Option<usize>,
+
+    /// Client proxies health check url
+    #[builder(setter(into), default)]
+    pub(crate) proxy_healthcheck_url: Option<String>,
+
+    /// Pre-check the proxy of the client reachable before the proxy request
+    #[builder(setter(into), default = false)]
+    pub(crate) precheck_proxy: bool,
+
+    /// Proxy pre-check timeout (second)
+    #[builder(setter(into), default = 1)]
+    pub(crate) precheck_timeout: u64,
+
+    /// Maximum clients built per pool
+    #[builder(setter(into), default)]
+    pub(crate) max_clients: Option<usize>,
+
+    /// Maximum simultaneous connection establishment per pool, `None` is unlimited
+    #[builder(setter(into), default)]
+    pub(crate) connect_concurrency: Option<usize>,
+
+    /// Client selection strategy
+    #[builder(setter(into), default)]
+    pub(crate) balance_strategy: BalanceStrategy,
+
+    /// Sticky sessions by the session key
+    #[builder(setter(into), default = false)]
+    pub(crate) sticky_sessions: bool,
+
+    /// Sticky session key source
+    #[builder(setter(into), default)]
+    pub(crate) sticky_key: StickyKey,
+
+    /// Rotate the IPv6 subnet bound address every N requests
+    #[builder(setter(into), default)]
+    pub(crate) ipv6_rotate_every: u64,
+
+    /// Rotate the IPv6 subnet bound address every N seconds
+    #[builder(setter(into), default)]
+    pub(crate) ipv6_rotate_interval: u64,
+
+    /// Client proxies health check interval (second)
+    #[builder(setter(into), default = 60)]
+    pub(crate) proxy_healthcheck_interval: u64,
+
+    /// Client upstream extra root certificates (PEM bundle)
+    #[builder(setter(into), default)]
+    pub(crate) upstream_ca_bundle: Option<PathBuf>,
+
+    /// Client accepts invalid upstream certificates
+    #[builder(setter(into), default = false)]
+    pub(crate) danger_accept_invalid_certs: bool,
+
     /// Random User-Agent
     #[builder(setter(into), default = Some(vec![Impersonate::OkHttp4_9]))]
     pub(crate) impersonate_uas: Option<Vec<Impersonate>>,
 
+    /// Admin actions audit log file path
+    #[builder(setter(into), default)]
+    pub(crate) audit_log_path: Option<PathBuf>,
+
+    /// Fallback model list file path
+    #[builder(setter(into), default)]
+    pub(crate) fallback_models_file: Option<PathBuf>,
+
+    /// Shutdown report file path
+    #[builder(setter(into), default)]
+    pub(crate) shutdown_report_path: Option<PathBuf>,
+
+    /// Re-exec on SIGUSR2 passing the listening sockets
+    #[builder(setter(into), default = false)]
+    pub(crate) graceful_restart: bool,
+
     /// TLS cert
     #[builder(setter(into), default)]
     pub(crate) tls_cert: Option<PathBuf>,
@@ -64,22 +225,82 @@ pub struct Args {
     #[builder(setter(into), default)]
     pub(crate) tls_key: Option<PathBuf>,
 
+    /// TLS ALPN protocols, empty keeps the default (h2, http/1.1)
+    #[builder(setter(into), default)]
+    pub(crate) tls_alpn: Vec<String>,
+
+    /// Admin and HAR management endpoints ip allowlist
+    #[builder(setter(into), default)]
+    pub(crate) admin_ip_allowlist: Vec<cidr::IpCidr>,
+
+    /// Enabled admin endpoints, `None` enables all
+    #[builder(setter(into), default)]
+    pub(crate) admin_endpoints_enabled: Option<Vec<String>>,
+
+    /// Trusted reverse proxies, the forwarded headers are only trusted from them
+    #[builder(setter(into), default)]
+    pub(crate) trusted_proxies: Vec<cidr::IpCidr>,
+
     /// Visitor email whitelist
     #[builder(setter(into), default)]
     pub(super) visitor_email_whitelist: Option<Vec<String>>,
 
     /// Login auth key
     #[builder(setter(into), default)]
-    pub(super) auth_key: Option<String>,
+    pub(crate) auth_key: Option<String>,
+
+    /// HAR upload keys scoped to a single type (key -> type)
+    #[builder(setter(into), default)]
+    pub(crate) har_upload_keys: HashMap<String, arkose::Type>,
+
+    /// Strip response headers
+    #[builder(setter(into), default)]
+    pub(crate) strip_response_headers: Vec<String>,
+
+    /// Rewrite response headers
+    #[builder(setter(into), default)]
+    pub(crate) rewrite_response_headers: Vec<(String, String)>,
+
+    /// OpenTelemetry OTLP traces exporter endpoint
+    #[builder(setter(into), default)]
+    pub(crate) otlp_endpoint: Option<String>,
+
+    /// Fraction of the requests logging the access lines
+    #[builder(setter(into), default = 1.0)]
+    pub(crate) log_sample_rate: f64,
+
+    /// Slow request threshold (milliseconds)
+    #[builder(setter(into), default)]
+    pub(crate) slow_request_threshold_ms: Option<u64>,
+
+    /// Rewrite the OpenAI API upstream base url
+    #[builder(setter(into), default)]
+    pub(crate) upstream_base: Option<String>,
+
+    /// Rewrite the upstream path prefix
+    #[builder(setter(into), default)]
+    pub(crate) upstream_path_map: Vec<(String, String)>,
 
     /// Enable webui
     #[builder(setter(into), default = false)]
     pub(crate) enable_webui: bool,
 
+    /// Response of the root path
+    #[builder(setter(into), default)]
+    pub(crate) root_response: RootResponse,
+
     /// Enable file proxy
     #[builder(setter(into), default = false)]
     pub(crate) enable_file_proxy: bool,
 
+    /// Enable HTTP CONNECT proxy
+    #[builder(setter(into), default = false)]
+    pub(crate) enable_connect_proxy: bool,
+
+    /// HTTP CONNECT proxy host allowlist, empty is the openai/arkose domains
+    #[builder(setter(into), default)]
+    pub(crate) connect_proxy_allowlist: Vec<String>,
+
     /// Get arkose token proxy
     #[builder(default = false)]
     pub(crate) enable_arkose_proxy: bool,
@@ -92,14 +313,74 @@ pub struct Args {
     #[builder(setter(into), default)]
     pub(crate) cf_secret_key: Option<String>,
 
+    /// Cloudflare turnstile siteverify timeout (seconds)
+    #[builder(setter(into), default = 10)]
+    pub(crate) cf_verify_timeout: u64,
+
+    /// Cloudflare turnstile siteverify retries
+    #[builder(setter(into), default = 2)]
+    pub(crate) cf_verify_retries: u32,
+
     /// Arkose endpoint
     #[builder(setter(into), default)]
     pub(crate) arkose_endpoint: Option<String>,
 
+    /// Arkose endpoint of each type, fallback to `arkose_endpoint`
+    #[builder(setter(into), default)]
+    pub(crate) arkose_endpoints: HashMap<arkose::Type, String>,
+
+    /// Model to arkose type map, consulted before the default model mapping
+    #[builder(setter(into), default)]
+    pub(crate) model_arkose_map: HashMap<String, arkose::Type>,
+
+    /// Handling of a model mapped to no arkose type
+    #[builder(setter(into), default)]
+    pub(crate) unknown_arkose_policy: UnknownArkosePolicy,
+
     /// Auth Arkoselabs HAR record file path
     #[builder(setter(into), default)]
     pub(crate) arkose_har_dir: Option<PathBuf>,
 
+    /// Do not create the missing default HAR directory
+    #[builder(setter(into), default = false)]
+    pub(crate) har_no_create: bool,
+
+    /// Disable the HAR directory watching of the types
+    #[builder(setter(into), default)]
+    pub(crate) har_no_watch: Vec<arkose::Type>,
+
+    /// HAR files parsing concurrency on startup
+    #[builder(setter(into), default = 4)]
+    pub(crate) har_parse_concurrency: usize,
+
+    /// Primary node url, HAR writes are forwarded to the primary
+    #[builder(setter(into), default)]
+    pub(crate) primary_url: Option<String>,
+
+    /// Config reloader, re-read the proxies and the solver config on SIGUSR1
+    #[builder(setter(into), default)]
+    pub(crate) config_reloader: Option<ConfigReloader>,
+
+    /// Request lifecycle event callback, for the embedders
+    #[builder(setter(into), default)]
+    pub(crate) on_event: Option<EventCallback>,
+
+    /// Maximum HAR file size (MB), 0 disables the limit
+    #[builder(setter(into), default = 100)]
+    pub(crate) max_har_size: u64,
+
+    /// Maximum HAR age (seconds) since the capture
+    #[builder(setter(into), default)]
+    pub(crate) har_max_age: Option<u64>,
+
+    /// Maximum HAR capture age (seconds) accepted on upload, refresh and install
+    #[builder(setter(into), default)]
+    pub(crate) har_max_capture_age: Option<u64>,
+
+    /// Pin the HAR of the pool by the client IP
+    #[builder(setter(into), default = false)]
+    pub(crate) har_affinity: bool,
+
     /// Enable Arkose GPT-3.5 experiment
     #[builder(setter(into), default = false)]
     pub(crate) arkose_gpt3_experiment: bool,
@@ -112,6 +393,26 @@ pub struct Args {
     #[builder(setter(into), default)]
     pub(crate) arkose_solver: Option<ArkoseSolver>,
 
+    /// arkoselabs solver fallback chain, tried in order after the primary solver
+    #[builder(setter(into), default)]
+    pub(crate) arkose_solver_chain: Vec<ArkoseSolver>,
+
+    /// arkoselabs solver routes of the easy challenges of each type
+    #[builder(setter(into), default)]
+    pub(crate) arkose_solver_difficulty: HashMap<arkose::Type, DifficultyRoute>,
+
+    /// arkoselabs pre-solved token pool size of each type
+    #[builder(setter(into), default)]
+    pub(crate) arkose_warm_pool_size: HashMap<arkose::Type, usize>,
+
+    /// arkoselabs pre-solved token assumed TTL (seconds) of each type
+    #[builder(setter(into), default)]
+    pub(crate) arkose_token_ttl: HashMap<arkose::Type, u64>,
+
+    /// Solver request signing by ArkoseLabs
+    #[builder(setter(into), default)]
+    pub(crate) arkose_solver_signing: Option<SolverSigning>,
+
     /// About the solver tguess endpoint by ArkoseLabs
     #[builder(setter(into), default)]
     pub(crate) arkose_solver_tguess_endpoint: Option<String>,
@@ -120,6 +421,38 @@ pub struct Args {
     #[builder(setter(into), default)]
     pub(crate) arkose_solver_image_dir: Option<PathBuf>,
 
+    /// About the simultaneous challenge image encodings on the blocking pool by ArkoseLabs
+    #[builder(setter(into), default)]
+    pub(crate) solver_workers: Option<usize>,
+
+    /// Solver local work queue timeout (second), `None` waits for a worker without bound
+    #[builder(setter(into), default)]
+    pub(crate) solver_queue_timeout: Option<u64>,
+
+    /// Coalesce the concurrent solves of the same type by ArkoseLabs
+    #[builder(setter(into), default = false)]
+    pub(crate) arkose_solve_coalesce: bool,
+
+    /// Require the arkoselabs solver
+    #[builder(setter(into), default = false)]
+    pub(crate) require_solver: bool,
+
+    /// Refuse to start if a gated arkose type has neither a usable HAR nor a solver
+    #[builder(setter(into), default = false)]
+    pub(crate) strict_arkose: bool,
+
+    /// Run the startup validation report
+    #[builder(setter(into), default = false)]
+    pub(crate) startup_report: bool,
+
+    /// Emit the request routing decisions in the debug response headers
+    #[builder(setter(into), default = false)]
+    pub(crate) debug_headers: bool,
+
+    /// Emit the phase timings in the Server-Timing response header
+    #[builder(setter(into), default = false)]
+    pub(crate) server_timing: bool,
+
     /// Enable Tokenbucket
     #[cfg(feature = "limit")]
     #[builder(setter(into), default = false)]
@@ -140,6 +473,16 @@ pub struct Args {
     #[builder(setter(into), default = 1)]
     pub(crate) tb_fill_rate: u32,
 
+    /// Tokenbucket burst capacity, enable a burst bucket in series
+    #[cfg(feature = "limit")]
+    #[builder(setter(into), default)]
+    pub(crate) tb_burst_capacity: Option<u32>,
+
+    /// Tokenbucket burst fill rate
+    #[cfg(feature = "limit")]
+    #[builder(setter(into), default = 1)]
+    pub(crate) tb_burst_rate: u32,
+
     /// Tokenbucket expired (second)
     #[cfg(feature = "limit")]
     #[builder(setter(into), default = 86400)]
@@ -155,6 +498,16 @@ pub struct Args {
     #[builder(setter(into), default)]
     pub(crate) pupstream: Option<String>,
 
+    /// Preauth MITM server upstream timeout (seconds)
+    #[cfg(feature = "preauth")]
+    #[builder(setter(into), default = 30)]
+    pub(crate) preauth_timeout: u64,
+
+    /// Preauth MITM server simultaneous intercepted connections
+    #[cfg(feature = "preauth")]
+    #[builder(setter(into), default)]
+    pub(crate) preauth_concurrency: Option<usize>,
+
     /// crate MITM server CA certificate file path
     #[cfg(feature = "preauth")]
     #[builder(setter(into), default)]
@@ -164,4 +517,142 @@ pub struct Args {
     #[cfg(feature = "preauth")]
     #[builder(setter(into), default)]
     pub(crate) pkey: PathBuf,
+
+    /// Include the cookie value in preauth capture events
+    #[cfg(feature = "preauth")]
+    #[builder(setter(into), default = false)]
+    pub(crate) preauth_event_value: bool,
+
+    /// Fraction (0.0-1.0) of the Apple logins taking the preauth path
+    #[cfg(feature = "preauth")]
+    #[builder(setter(into), default = 1.0)]
+    pub(crate) preauth_ratio: f64,
+
+    /// Preauth cookie push policy of a cached device id
+    #[cfg(feature = "preauth")]
+    #[builder(setter(into), default)]
+    pub(crate) preauth_push_policy: PreauthPushPolicy,
+}
+
+/// Handling of a model mapped to no arkose type
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownArkosePolicy {
+    /// Proxy the request without the arkose token
+    Passthrough,
+    /// Reject the request with a descriptive error
+    #[default]
+    Reject,
+}
+
+impl std::str::FromStr for UnknownArkosePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "passthrough" => Ok(Self::Passthrough),
+            "reject" => Ok(Self::Reject),
+            _ => anyhow::bail!("Only support `passthrough` / `reject` unknown arkose policy"),
+        }
+    }
+}
+
+impl std::fmt::Display for UnknownArkosePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Passthrough => write!(f, "passthrough"),
+            Self::Reject => write!(f, "reject"),
+        }
+    }
+}
+
+/// Response of the root path `/`
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum RootResponse {
+    /// The WebUI chat page if enabled, otherwise 404
+    #[default]
+    Default,
+    /// 404 Not Found
+    NotFound,
+    /// Temporary redirect to the url
+    Redirect(String),
+    /// Custom JSON body
+    Json(serde_json::Value),
+}
+
+impl std::str::FromStr for RootResponse {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Self::Default),
+            "404" | "not_found" => Ok(Self::NotFound),
+            _ => match s.split_once(':') {
+                Some(("redirect", url)) if !url.is_empty() => Ok(Self::Redirect(url.to_owned())),
+                Some(("json", json)) => Ok(Self::Json(serde_json::from_str(json)?)),
+                _ => anyhow::bail!(
+                    "Only support `default` / `404` / `redirect:<url>` / `json:<json>` root response"
+                ),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for RootResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::NotFound => write!(f, "404"),
+            Self::Redirect(url) => write!(f, "redirect:{url}"),
+            Self::Json(value) => write!(f, "json:{value}"),
+        }
+    }
+}
+
+impl TryFrom<String> for RootResponse {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<RootResponse> for String {
+    fn from(response: RootResponse) -> Self {
+        response.to_string()
+    }
+}
+
+/// Server bind address without the `NINJA_BIND` and `PORT` env
+const DEFAULT_BIND: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 7999);
+
+/// Default server bind addresses, consult the `NINJA_BIND` and `PORT` env before falling back to `0.0.0.0:7999`.
+/// An invalid env value is an error rather than a silent fallback to another address
+pub fn default_bind() -> anyhow::Result<Vec<SocketAddr>> {
+    if let Ok(bind) = std::env::var("NINJA_BIND") {
+        let addrs = bind
+            .split(',')
+            .map(str::trim)
+            .filter(|addr| !addr.is_empty())
+            .map(|addr| {
+                addr.parse::<SocketAddr>()
+                    .map_err(|err| anyhow::anyhow!("Invalid NINJA_BIND address `{addr}`: {err}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if !addrs.is_empty() {
+            return Ok(addrs);
+        }
+    }
+
+    match std::env::var("PORT") {
+        Ok(port) if !port.trim().is_empty() => {
+            let port = port
+                .trim()
+                .parse::<u16>()
+                .map_err(|err| anyhow::anyhow!("Invalid PORT `{port}`: {err}"))?;
+            Ok(vec![SocketAddr::new(DEFAULT_BIND.ip(), port)])
+        }
+        _ => Ok(vec![DEFAULT_BIND]),
+    }
 }
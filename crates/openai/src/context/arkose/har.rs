@@ -1,18 +1,24 @@
 use crate::{
     arkose::{self, Type},
-    context::WORKER_DIR,
+    context::{decision, reload::ReloadSource, WORKER_DIR},
+    debug,
     homedir::home_dir,
-    info, warn,
+    info, now_duration, warn, with_context,
 };
 use anyhow::anyhow;
 use hotwatch::{Event, EventKind, Hotwatch};
 use std::{
+    collections::hash_map::DefaultHasher,
     collections::HashMap,
+    future::Future,
+    hash::{Hash, Hasher},
+    net::IpAddr,
     path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        OnceLock, RwLock,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock, RwLock,
     },
+    time::Duration,
 };
 use tokio::fs::ReadDir;
 
@@ -21,24 +27,257 @@ use crate::urldecoding;
 use anyhow::Result;
 use base64::Engine;
 use moka::sync::Cache;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use time::format_description::well_known::Rfc3339;
 
 pub static HAR: OnceLock<RwLock<HashMap<arkose::Type, HarProvider>>> = OnceLock::new();
 
-struct HarPath {
-    dir: PathBuf,
-    filepath: Option<PathBuf>,
+tokio::task_local! {
+    /// Client IP hash of the current request, pins the HAR of the pool
+    static AFFINITY: u64;
 }
 
-#[derive(Debug)]
-pub struct HarProvider {
+/// Initial backoff of re-watching the disappeared HAR directory
+const REWATCH_BACKOFF_MIN: Duration = Duration::from_secs(1);
+/// Max backoff of re-watching the disappeared HAR directory
+const REWATCH_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// Key prefix of the HAR installed from memory, never read from the store
+const MEMORY_KEY_PREFIX: &str = "memory:";
+
+/// HAR store change callback, invoked with the changed key
+pub type WatchCallback = Arc<dyn Fn(&str) + Send + Sync + 'static>;
+
+/// HAR file storage, the filesystem store is used by default
+pub trait HarStore: Send + Sync {
+    /// List the HAR file keys
+    fn list(&self) -> Result<Vec<String>>;
+
+    /// Read the HAR file content of the key
+    fn read(&self, key: &str) -> Result<Vec<u8>>;
+
+    /// Watch the store, the callback is invoked with the changed key
+    fn watch(&mut self, callback: WatchCallback) -> Result<()>;
+
+    /// Local directory of the store, required by the HAR management endpoints
+    fn local_dir(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Filesystem HAR store
+pub struct FsHarStore {
     /// HAR dir path
     dir: PathBuf,
     /// File Hotwatch
-    hotwatch: Hotwatch,
+    hotwatch: Arc<Mutex<Option<Hotwatch>>>,
+    /// Maximum HAR file size (bytes)
+    max_size: Option<u64>,
+}
+
+impl FsHarStore {
+    /// Create a filesystem store, the missing directory is created unless `no_create`,
+    /// the files larger than `max_size` (bytes) are refused
+    pub fn new(dir: PathBuf, no_create: bool, max_size: Option<u64>) -> Self {
+        if !no_create {
+            init_directory(&dir);
+        } else if !dir.exists() {
+            info!(
+                "HAR directory does not exist, skip creating: {}",
+                dir.display()
+            );
+        }
+        Self {
+            dir,
+            hotwatch: Arc::new(Mutex::new(None)),
+            max_size,
+        }
+    }
+}
+
+impl HarStore for FsHarStore {
+    fn list(&self) -> Result<Vec<String>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let keys = std::fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|file_path| {
+                file_path
+                    .extension()
+                    .map(|ext| ext == "har")
+                    .unwrap_or(false)
+            })
+            .filter_map(|file_path| {
+                file_path
+                    .file_stem()
+                    .map(|file_name| format!("{}.har", file_name.to_string_lossy()))
+            })
+            .collect();
+        Ok(keys)
+    }
+
+    fn read(&self, key: &str) -> Result<Vec<u8>> {
+        // The directory may live on a network mount that temporarily disappears
+        if !self.dir.is_dir() {
+            anyhow::bail!("HAR directory is unavailable: {}", self.dir.display());
+        }
+        let path = self.dir.join(key);
+        // Check if the path is a file
+        path.is_file()
+            .then(|| ())
+            .ok_or_else(|| anyhow!("{} not a file", path.display()))?;
+        // Check the size before reading, a huge HAR file must not be loaded into memory
+        if let Some(max_size) = self.max_size {
+            let size = std::fs::metadata(&path)?.len();
+            if size > max_size {
+                anyhow::bail!(
+                    "HAR file {} is {size} bytes, exceeds the limit of {max_size} bytes",
+                    path.display()
+                );
+            }
+        }
+        Ok(std::fs::read(path)?)
+    }
+
+    fn watch(&mut self, callback: WatchCallback) -> Result<()> {
+        if !self.dir.exists() {
+            return Ok(());
+        }
+
+        DirWatcher {
+            dir: self.dir.clone(),
+            callback,
+            hotwatch: self.hotwatch.clone(),
+            rewatching: Arc::new(AtomicBool::new(false)),
+        }
+        .watch()
+    }
+
+    fn local_dir(&self) -> Option<&Path> {
+        Some(&self.dir)
+    }
+}
+
+impl Drop for FsHarStore {
+    fn drop(&mut self) {
+        let hotwatch = self
+            .hotwatch
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .take();
+        if let Some(mut hotwatch) = hotwatch {
+            if let Some(err) = hotwatch.unwatch(self.dir.as_path()).err() {
+                warn!("hotwatch stop error: {err}")
+            }
+        }
+    }
+}
+
+/// HAR directory watcher, once the directory disappears (e.g. an unmounted network mount)
+/// the last-known state is kept and the directory is re-watched with backoff
+#[derive(Clone)]
+struct DirWatcher {
+    dir: PathBuf,
+    callback: WatchCallback,
+    hotwatch: Arc<Mutex<Option<Hotwatch>>>,
+    rewatching: Arc<AtomicBool>,
+}
+
+impl DirWatcher {
+    fn watch(&self) -> Result<()> {
+        let mut hotwatch = Hotwatch::new()?;
+        let watch_path = self.dir.display().to_string();
+        info!("Start watching HAR directory: {}", watch_path);
+        let watcher = self.clone();
+        hotwatch.watch(self.dir.as_path(), move |event: Event| {
+            if !watcher.dir.is_dir() {
+                watcher.rewatch();
+                return;
+            }
+            match event.kind {
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {
+                    event.paths.iter().for_each(|path| {
+                        info!(
+                            "HAR directory: {watch_path} changes observed: {}",
+                            path.display()
+                        );
+                        if let Some(file_name) = path.file_name() {
+                            (watcher.callback)(&file_name.to_string_lossy());
+                        }
+                    });
+                }
+                _ => {}
+            }
+        })?;
+        *self.hotwatch.lock().unwrap_or_else(|err| err.into_inner()) = Some(hotwatch);
+        Ok(())
+    }
+
+    fn rewatch(&self) {
+        if self.rewatching.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        warn!(
+            "HAR directory disappeared, keep the last-known state and re-watch: {}",
+            self.dir.display()
+        );
+        let watcher = self.clone();
+        std::thread::spawn(move || {
+            let mut backoff = REWATCH_BACKOFF_MIN;
+            loop {
+                std::thread::sleep(backoff);
+                if watcher.dir.is_dir() {
+                    match watcher.watch() {
+                        Ok(_) => break,
+                        Err(err) => warn!(
+                            "Failed to re-watch HAR directory {}: {err}",
+                            watcher.dir.display()
+                        ),
+                    }
+                }
+                backoff = (backoff * 2).min(REWATCH_BACKOFF_MAX);
+            }
+
+            // Refresh the state, the files may have changed while the directory was gone
+            info!("HAR directory is back: {}", watcher.dir.display());
+            if let Ok(entries) = std::fs::read_dir(&watcher.dir) {
+                entries.filter_map(|entry| entry.ok()).for_each(|entry| {
+                    (watcher.callback)(&entry.file_name().to_string_lossy());
+                });
+            }
+            watcher.rewatching.store(false, Ordering::SeqCst);
+        });
+    }
+}
+
+pub struct HarProvider {
+    /// HAR store
+    store: Arc<dyn HarStore>,
     /// HAR file pool
     pool: (AtomicUsize, Vec<String>),
+    /// Keys of the HAR installed from memory, the entries live in the cache only
+    memory: Vec<String>,
+    /// Store is watched, otherwise reload on the API changes only
+    watching: bool,
+    /// HAR directory is specified by the user, otherwise defaulted to the home directory
+    specified: bool,
+    /// HAR entries served to the requests
+    served: AtomicU64,
+}
+
+/// Resolved HAR directory of a type
+#[derive(Serialize, Debug)]
+pub struct HarDir {
+    /// Local directory, `None` if the store is not on the filesystem
+    pub path: Option<PathBuf>,
+    /// `specified` by the user or `default` in the home directory
+    pub source: &'static str,
+    pub watching: bool,
+    /// HAR entries served to the requests
+    pub served: u64,
 }
 
 impl HarProvider {
@@ -46,6 +285,9 @@ impl HarProvider {
         _type: arkose::Type,
         dir_path: Option<&PathBuf>,
         default_dir_name: &str,
+        no_create: bool,
+        watch: bool,
+        max_size: Option<u64>,
     ) -> HarProvider {
         let dir = dir_path.cloned().unwrap_or(
             home_dir()
@@ -53,49 +295,69 @@ impl HarProvider {
                 .join(WORKER_DIR)
                 .join(default_dir_name),
         );
+        let specified = dir_path.is_some();
+        info!(
+            "{_type} HAR directory: {} ({})",
+            dir.display(),
+            if specified { "specified" } else { "default" }
+        );
 
-        init_directory(&dir);
-
-        let mut pool = Vec::new();
-        Self::init(&dir, &mut pool);
-
-        HarProvider {
-            pool: (AtomicUsize::new(0), pool),
-            hotwatch: watch_har_dir(_type, &dir),
-            dir,
-        }
+        let mut provider =
+            Self::with_store(_type, FsHarStore::new(dir, no_create, max_size), watch);
+        provider.specified = specified;
+        provider
     }
 
-    fn init(dir_path: impl AsRef<Path>, pool: &mut Vec<String>) {
-        std::fs::read_dir(dir_path.as_ref())
-            .expect("Failed to read har directory")
-            .filter_map(|entry| entry.ok().map(|e| e.path()))
-            .filter(|file_path| {
-                file_path
-                    .extension()
-                    .map(|ext| ext == "har")
-                    .unwrap_or(false)
-            })
-            .for_each(|file_path| {
-                if let Some(file_name) = file_path.file_stem() {
-                    pool.push(format!("{}.har", file_name.to_string_lossy()));
+    /// Create a HAR provider backed by the given store, the store is watched if `watch`
+    pub fn with_store(
+        _type: arkose::Type,
+        mut store: impl HarStore + 'static,
+        watch: bool,
+    ) -> HarProvider {
+        let watching = watch
+            && match store.watch(Arc::new(move |key| {
+                on_store_changed(_type, key, ReloadSource::Hotwatch)
+            })) {
+                Ok(_) => true,
+                Err(err) => {
+                    warn!("Failed to watch {_type} HAR store: {err}");
+                    false
                 }
-            });
-    }
+            };
+        if !watch {
+            info!("{_type} HAR store watching is disabled");
+        }
 
-    fn reset_pool(&mut self) {
-        self.pool.1.clear();
-        Self::init(&self.dir, &mut self.pool.1)
+        let mut provider = HarProvider {
+            store: Arc::new(store),
+            pool: (AtomicUsize::new(0), Vec::new()),
+            memory: Vec::new(),
+            watching,
+            specified: false,
+            served: AtomicU64::new(0),
+        };
+        let _ = provider.reset_pool();
+        provider
     }
 
-    fn pool(&self) -> HarPath {
-        let mut har_path = HarPath {
-            dir: self.dir.clone(),
-            filepath: None,
-        };
+    /// Reset the pool, keep the last-known pool on a transient store error
+    fn reset_pool(&mut self) -> Result<()> {
+        match self.store.list() {
+            Ok(keys) => {
+                self.pool.1 = keys.into_iter().chain(self.memory.clone()).collect();
+                Ok(())
+            }
+            Err(err) => {
+                warn!("Failed to list HAR store, keep the last-known pool: {err}");
+                Err(err)
+            }
+        }
+    }
 
+    /// Next HAR file key of the pool and its index
+    fn pool(&self) -> Option<(usize, &str)> {
         if self.pool.1.is_empty() {
-            return har_path;
+            return None;
         }
 
         let len = self.pool.1.len();
@@ -113,8 +375,16 @@ impl HarProvider {
             }
         }
 
-        har_path.filepath = Some(self.dir.join(&self.pool.1[new]));
-        har_path
+        Some((new, &self.pool.1[new]))
+    }
+
+    /// HAR file key of the pool pinned by the hash and its index
+    fn pinned(&self, hash: u64) -> Option<(usize, &str)> {
+        if self.pool.1.is_empty() {
+            return None;
+        }
+        let index = (hash % self.pool.1.len() as u64) as usize;
+        Some((index, &self.pool.1[index]))
     }
 }
 
@@ -123,47 +393,22 @@ fn init_directory(path: impl AsRef<Path>) {
 
     if !path.exists() {
         info!("Create default HAR directory: {}", path.display());
-        std::fs::create_dir_all(&path).expect("Failed to create har directory");
+        if let Some(err) = std::fs::create_dir_all(&path).err() {
+            warn!("Failed to create HAR directory {}: {err}", path.display());
+        }
     }
 }
 
-fn watch_har_dir(_type: arkose::Type, path: impl AsRef<Path>) -> Hotwatch {
-    let mut hotwatch = Hotwatch::new().expect("hotwatch failed to initialize!");
-    hotwatch
-        .watch(path.as_ref().display().to_string(), {
-            let _type = _type;
-            let watch_path = path.as_ref().display().to_string();
-            info!("Start watching HAR directory: {}", watch_path);
-            move |event: Event| match event.kind {
-                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {
-                    event.paths.iter().for_each(|path| {
-                        info!(
-                            "HAR directory: {watch_path} changes observed: {}",
-                            path.display()
-                        );
-                        let lock = HAR.get().unwrap();
-                        let mut har_map = lock.write().expect("Failed to get har map");
-                        if let Some(har) = har_map.get_mut(&_type) {
-                            // clear cache
-                            if let Some(path_str) = path.as_path().to_str() {
-                                get_or_init_cache().remove(path_str);
-                                har.reset_pool();
-                            }
-                        }
-                    });
-                }
-                _ => {}
-            }
-        })
-        .expect("failed to watch file!");
-    hotwatch
-}
-
-impl Drop for HarProvider {
-    fn drop(&mut self) {
-        if let Some(err) = self.hotwatch.unwatch(self.dir.as_path()).err() {
-            warn!("hotwatch stop error: {err}")
-        }
+fn on_store_changed(_type: arkose::Type, key: &str, source: ReloadSource) {
+    // clear cache
+    get_or_init_cache().remove(&cache_key(&_type, key));
+    let result = HAR.get().and_then(|lock| {
+        let mut har_map = lock.write().expect("Failed to get har map");
+        har_map.get_mut(&_type).map(|har| har.reset_pool())
+    });
+    // Record outside of the HAR lock
+    if let Some(result) = result {
+        with_context!(reload_status).record(format!("har:{_type}"), source, &result);
     }
 }
 
@@ -175,6 +420,11 @@ fn get_or_init_cache() -> &'static Cache<String, RequestEntry> {
     CACHE.get_or_init(|| Cache::new(u64::MAX))
 }
 
+#[inline]
+fn cache_key(_type: &Type, key: &str) -> String {
+    format!("{_type}/{key}")
+}
+
 // Arkose request entry
 #[derive(Clone)]
 pub struct RequestEntry {
@@ -185,19 +435,239 @@ pub struct RequestEntry {
     pub body: String,
     pub bx: String,
     pub bv: String,
+    /// HAR capture time (unix seconds), the request started date time
+    pub captured_at: i64,
+    /// HAR load time (unix seconds), renewed when the file changes
+    pub loaded_at: u64,
 }
 
-fn get_har_path(_type: &Type) -> anyhow::Result<HarPath> {
+impl RequestEntry {
+    /// Age of the HAR since the capture (seconds)
+    pub fn age(&self) -> u64 {
+        let now = now_duration().map(|d| d.as_secs()).unwrap_or_default();
+        now.saturating_sub(self.captured_at.max(0) as u64)
+    }
+
+    /// Check if the HAR is older than the max age
+    fn is_stale(&self, max_age: Option<u64>) -> bool {
+        max_age.map_or(false, |max_age| self.age() > max_age)
+    }
+
+    /// Check the capture time against `har_max_capture_age`, a freshly written file may hold a capture
+    /// whose arkose session is long dead
+    pub fn check_capture_age(&self) -> Result<()> {
+        let max_age = with_context!(har_max_capture_age);
+        if self.is_stale(max_age) {
+            anyhow::bail!(
+                "HAR captured {}s ago, exceeds the maximum capture age of {}s",
+                self.age(),
+                max_age.unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Age status of a HAR file
+#[derive(Serialize, Debug)]
+pub struct HarStatus {
+    pub key: String,
+    /// HAR capture time (unix seconds)
+    pub captured_at: i64,
+    /// HAR load time (unix seconds)
+    pub loaded_at: u64,
+    /// Age since the capture (seconds)
+    pub age: u64,
+    /// Older than `har_max_age`, skipped by the pool
+    pub stale: bool,
+}
+
+/// HAR store of the type and the HAR file key of the pool, pinned by the affinity hash if `Some`,
+/// otherwise the next one of the rotation
+fn get_har_store(
+    _type: &Type,
+    affinity: Option<u64>,
+) -> anyhow::Result<(Arc<dyn HarStore>, Option<(usize, String)>)> {
     let lock = HAR
         .get()
         .map(|s| s.read().ok())
         .flatten()
         .ok_or_else(|| anyhow!("Failed to get har lock"))?;
     lock.get(_type)
-        .map(|h| h.pool())
+        .map(|h| {
+            (
+                h.store.clone(),
+                match affinity {
+                    Some(hash) => h.pinned(hash),
+                    None => h.pool(),
+                }
+                .map(|(index, key)| (index, key.to_owned())),
+            )
+        })
         .ok_or_else(|| anyhow!("Failed to get har pool"))
 }
 
+/// Number of HAR files in the pool of the type
+fn pool_len(_type: &Type) -> usize {
+    HAR.get()
+        .and_then(|s| s.read().ok())
+        .and_then(|lock| lock.get(_type).map(|h| h.pool.1.len()))
+        .unwrap_or_default()
+}
+
+/// Re-fetch and re-validate the HAR files of the type from the store, the valid entries and the pool
+/// are swapped in at once, the last-known pool is kept if none is valid. Return the valid entry count
+pub fn refresh(_type: &Type) -> Result<usize> {
+    let (store, _) = get_har_store(_type, None)?;
+    let mut entries = Vec::new();
+    for key in store.list()? {
+        match store
+            .read(&key)
+            .and_then(|bytes| valid(&bytes))
+            .and_then(|entry| entry.check_capture_age().map(|_| entry))
+        {
+            Ok(entry) => entries.push((key, entry)),
+            Err(err) => warn!("Failed to refresh {_type} HAR {key}: {err}"),
+        }
+    }
+    if entries.is_empty() {
+        anyhow::bail!("No valid {_type} HAR in the store, keep the last-known pool");
+    }
+
+    let cache = get_or_init_cache();
+    let mut har_map = HAR
+        .get()
+        .and_then(|s| s.write().ok())
+        .ok_or_else(|| anyhow!("Failed to get har lock"))?;
+    let har = har_map
+        .get_mut(_type)
+        .ok_or_else(|| anyhow!("Failed to get har pool"))?;
+    for key in har.pool.1.iter().filter(|key| !har.memory.contains(key)) {
+        cache.remove(&cache_key(_type, key));
+    }
+    let count = entries.len();
+    har.pool.1 = entries
+        .into_iter()
+        .map(|(key, entry)| {
+            cache.insert(cache_key(_type, &key), entry);
+            key
+        })
+        .chain(har.memory.clone())
+        .collect();
+    info!("Refreshed {count} {_type} HAR entries from the store");
+    Ok(count)
+}
+
+/// Validate the HAR bytes and install it into the pool of the type from memory, bypassing the store.
+/// The same HAR is installed once, return its key
+pub fn install(_type: &Type, bytes: &[u8]) -> Result<String> {
+    let entry = valid(bytes)?;
+    entry.check_capture_age()?;
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    let key = format!("{MEMORY_KEY_PREFIX}{:016x}", hasher.finish());
+
+    let mut har_map = HAR
+        .get()
+        .and_then(|s| s.write().ok())
+        .ok_or_else(|| anyhow!("Failed to get har lock"))?;
+    let har = har_map
+        .get_mut(_type)
+        .ok_or_else(|| anyhow!("Failed to get har pool"))?;
+    get_or_init_cache().insert(cache_key(_type, &key), entry);
+    if !har.memory.contains(&key) {
+        har.memory.push(key.clone());
+        har.pool.1.push(key.clone());
+        info!("Installed {_type} HAR {key} from memory");
+    }
+    Ok(key)
+}
+
+/// Reload the changed key if the store is not watched
+fn reload_unwatched(_type: &Type, key: &str) {
+    let watching = HAR
+        .get()
+        .and_then(|s| s.read().ok())
+        .and_then(|lock| lock.get(_type).map(|h| h.watching))
+        .unwrap_or(true);
+    if !watching {
+        on_store_changed(*_type, key, ReloadSource::Api);
+    }
+}
+
+/// Parse all HAR files of the stores concurrently on startup, warm up the entry cache
+pub fn preload(concurrency: usize) {
+    let tasks = match HAR.get().and_then(|s| s.read().ok()) {
+        Some(lock) => lock
+            .iter()
+            .flat_map(|(_type, h)| {
+                h.pool
+                    .1
+                    .iter()
+                    .map(|key| (*_type, h.store.clone(), key.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>(),
+        None => return,
+    };
+    if tasks.is_empty() {
+        return;
+    }
+
+    let total = tasks.len();
+    let start = std::time::Instant::now();
+    let tasks = Mutex::new(tasks);
+    let failed = AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..concurrency.clamp(1, total) {
+            s.spawn(|| loop {
+                let task = tasks.lock().unwrap_or_else(|err| err.into_inner()).pop();
+                let Some((_type, store, key)) = task else {
+                    break;
+                };
+                if let Some(err) = parse_from_store(&_type, store.as_ref(), &key).err() {
+                    warn!("Failed to parse {_type} HAR file {key}: {err}");
+                    failed.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+
+    info!(
+        "Parsed {total} HAR files in {}ms ({} failed)",
+        start.elapsed().as_millis(),
+        failed.load(Ordering::Relaxed)
+    );
+}
+
+/// Resolved HAR directory of each type
+pub fn har_dirs() -> HashMap<String, HarDir> {
+    HAR.get()
+        .and_then(|s| s.read().ok())
+        .map(|lock| {
+            lock.iter()
+                .map(|(_type, h)| {
+                    let dir = HarDir {
+                        path: h.store.local_dir().map(Path::to_path_buf),
+                        source: if h.specified { "specified" } else { "default" },
+                        watching: h.watching,
+                        served: h.served.load(Ordering::Relaxed),
+                    };
+                    (_type.to_string(), dir)
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn get_har_dir(_type: &Type) -> anyhow::Result<PathBuf> {
+    let (store, _) = get_har_store(_type, None)?;
+    store
+        .local_dir()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| anyhow!("The {_type} HAR store has no local directory"))
+}
+
 // valid har data
 #[inline]
 pub fn valid(s: &[u8]) -> anyhow::Result<RequestEntry> {
@@ -205,21 +675,92 @@ pub fn valid(s: &[u8]) -> anyhow::Result<RequestEntry> {
     parse(har)
 }
 
-/// Get entry
+/// Run the future with the HAR of the pool pinned by the client IP
+pub async fn affinity_scope<F: Future>(ip: IpAddr, fut: F) -> F::Output {
+    let mut hasher = DefaultHasher::new();
+    ip.hash(&mut hasher);
+    AFFINITY.scope(hasher.finish(), fut).await
+}
+
+/// Get entry, the HAR files older than `har_max_age` are skipped. The HAR pinned by the client IP
+/// is preferred within an affinity scope, the rotation is the fallback when it is stale
 #[inline]
 pub fn get_entry(_type: &arkose::Type) -> anyhow::Result<RequestEntry> {
-    let path = get_har_path(_type)?;
-    if let Some(filepath) = path.filepath {
-        parse_from_file(filepath)
-    } else {
-        anyhow::bail!("Failed to get har file path")
+    let max_age = with_context!(har_max_age);
+    if let Ok(hash) = AFFINITY.try_with(|hash| *hash) {
+        if let (store, Some((index, key))) = get_har_store(_type, Some(hash))? {
+            let entry = parse_from_store(_type, store.as_ref(), &key)?;
+            if !entry.is_stale(max_age) {
+                decision::record(|d| d.har_index = Some(index));
+                record_served(_type);
+                return Ok(entry);
+            }
+            debug!("{_type} HAR {key} pinned by the client is stale, fall back to the rotation");
+        }
+    }
+
+    for _ in 0..pool_len(_type).max(1) {
+        let (store, key) = get_har_store(_type, None)?;
+        let (index, key) = key.ok_or_else(|| anyhow!("Failed to get har file path"))?;
+        let entry = parse_from_store(_type, store.as_ref(), &key)?;
+        if entry.is_stale(max_age) {
+            debug!("{_type} HAR {key} is stale ({}s old), skip", entry.age());
+            continue;
+        }
+        decision::record(|d| d.har_index = Some(index));
+        record_served(_type);
+        return Ok(entry);
+    }
+    anyhow::bail!("All {_type} HAR files are stale")
+}
+
+/// Count a HAR entry served of the type
+fn record_served(_type: &Type) {
+    if let Some(lock) = HAR.get().and_then(|s| s.read().ok()) {
+        if let Some(h) = lock.get(_type) {
+            h.served.fetch_add(1, Ordering::Relaxed);
+        }
     }
 }
 
+/// Age status of the HAR files of each type
+pub fn har_status() -> HashMap<String, Vec<HarStatus>> {
+    let max_age = with_context!(har_max_age);
+    let providers = HAR
+        .get()
+        .and_then(|s| s.read().ok())
+        .map(|lock| {
+            lock.iter()
+                .map(|(_type, h)| (*_type, h.store.clone(), h.pool.1.clone()))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    providers
+        .into_iter()
+        .map(|(_type, store, keys)| {
+            let status = keys
+                .into_iter()
+                .filter_map(|key| {
+                    let entry = parse_from_store(&_type, store.as_ref(), &key).ok()?;
+                    Some(HarStatus {
+                        captured_at: entry.captured_at,
+                        loaded_at: entry.loaded_at,
+                        age: entry.age(),
+                        stale: entry.is_stale(max_age),
+                        key,
+                    })
+                })
+                .collect();
+            (_type.to_string(), status)
+        })
+        .collect()
+}
+
 /// Read dir
 pub async fn read_dir(_type: &Type) -> Result<ReadDir> {
-    let path = get_har_path(_type)?;
-    Ok(tokio::fs::read_dir(path.dir).await?)
+    let dir = get_har_dir(_type)?;
+    Ok(tokio::fs::read_dir(dir).await?)
 }
 
 /// Write entry to file
@@ -229,29 +770,36 @@ pub async fn write_file(
     filename: &str,
     data: impl AsRef<[u8]>,
 ) -> Result<()> {
-    let filepath = get_har_path(_type)?.dir.join(filename);
+    let filepath = get_har_dir(_type)?.join(filename);
     // only accept har file
     check_file_extension(&filepath).map_err(|s| anyhow!(s))?;
-    Ok(tokio::fs::write(filepath, data).await?)
+    tokio::fs::write(filepath, data).await?;
+    reload_unwatched(_type, filename);
+    Ok(())
 }
 
 /// Rename file
 pub async fn rename_file(_type: &Type, filename: &str, new_filename: &str) -> Result<()> {
-    let dir = get_har_path(_type)?.dir;
+    let dir = get_har_dir(_type)?;
     let old_file = PathBuf::from(&dir).join(filename);
     let new_file = PathBuf::from(&dir).join(new_filename);
     // only accept har file
     check_file_extension(&new_file).map_err(|s| anyhow!(s))?;
-    Ok(tokio::fs::rename(old_file, new_file).await?)
+    tokio::fs::rename(old_file, new_file).await?;
+    reload_unwatched(_type, filename);
+    reload_unwatched(_type, new_filename);
+    Ok(())
 }
 
 /// Delete file
 pub async fn delete_file(_type: &Type, filename: &str) -> Result<()> {
     // get the file path
-    let filepath = get_har_path(_type)?.dir.join(filename);
+    let filepath = get_har_dir(_type)?.join(filename);
     // only accept har file
     check_file_extension(&filepath).map_err(|s| anyhow!(s))?;
-    Ok(tokio::fs::remove_file(filepath).await?)
+    tokio::fs::remove_file(filepath).await?;
+    reload_unwatched(_type, filename);
+    Ok(())
 }
 
 fn check_file_extension(file: &PathBuf) -> Result<(), &'static str> {
@@ -263,24 +811,15 @@ fn check_file_extension(file: &PathBuf) -> Result<(), &'static str> {
     Ok(())
 }
 
-/// Parse file
+/// Parse HAR file from store
 #[inline]
-fn parse_from_file<P: AsRef<Path>>(path: P) -> Result<RequestEntry> {
-    // Check if the path is a file
-    path.as_ref()
-        .is_file()
-        .then(|| ())
-        .ok_or_else(|| anyhow!("{} not a file", path.as_ref().display()))?;
-
+fn parse_from_store(_type: &Type, store: &dyn HarStore, key: &str) -> Result<RequestEntry> {
     // Get the cache
     let cache = get_or_init_cache();
 
-    // Get the key from the path
-    let key = format!("{}", path.as_ref().display());
-
     // Try to get the value from the cache
-    let result = cache.try_get_with(key, || {
-        let bytes = std::fs::read(path)?;
+    let result = cache.try_get_with(cache_key(_type, key), || {
+        let bytes = store.read(key)?;
         let har = serde_json::from_slice::<Har>(&bytes)?;
         drop(bytes);
         parse(har)
@@ -372,6 +911,8 @@ fn parse(har: Har) -> Result<RequestEntry> {
                         .join("&"),
                     bx,
                     bv,
+                    captured_at: bt,
+                    loaded_at: now_duration().map(|d| d.as_secs()).unwrap_or_default(),
                 };
                 return Ok(entry);
             }
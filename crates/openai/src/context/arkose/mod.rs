@@ -1,5 +1,6 @@
 pub mod har;
 pub mod version;
+pub mod warm;
 
 use self::version::ArkoseVersion;
 use crate::arkose::Type;
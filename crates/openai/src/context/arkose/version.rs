@@ -41,7 +41,7 @@ impl ArkoseVersion {
 }
 
 pub(super) async fn latest_arkose_version(typed: Type) -> Result<ArkoseVersion> {
-    let client = with_context!(api_client);
+    let client = with_context!(api_client).await?;
     // Response content
     let content = client
         .get(format!("{}/v2/{}/api.js", typed.origin_url(), typed.pk()))
@@ -0,0 +1,110 @@
+use crate::arkose::{ArkoseContext, ArkoseToken, Type};
+use crate::{debug, info, warn, with_context};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Default assumed TTL of a pre-solved token, the tokens nearing expiry are discarded
+const DEFAULT_TTL: Duration = Duration::from_secs(90);
+/// Interval of discarding the expired tokens and retrying the failed refill
+const REFILL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Pool of the pre-solved arkose tokens of a type, refilled in the background
+pub struct WarmPool {
+    size: usize,
+    /// Assumed TTL of the tokens, discarded once reached
+    ttl: Duration,
+    tokens: Mutex<VecDeque<(Instant, ArkoseToken)>>,
+    /// Wake up the refill when a token is taken
+    refill: Notify,
+}
+
+impl WarmPool {
+    /// New pool of the size, the tokens are discarded after the TTL (90 seconds if `None`)
+    pub fn new(size: usize, ttl: Option<Duration>) -> Self {
+        Self {
+            size,
+            ttl: ttl.unwrap_or(DEFAULT_TTL),
+            tokens: Mutex::new(VecDeque::with_capacity(size)),
+            refill: Notify::new(),
+        }
+    }
+
+    /// Take the oldest fresh token, `None` if the pool is drained
+    pub fn take(&self) -> Option<ArkoseToken> {
+        let token = self.tokens.lock().ok().and_then(|mut tokens| {
+            discard_expired(&mut tokens, self.ttl);
+            tokens.pop_front().map(|(_, token)| token)
+        });
+        self.refill.notify_one();
+        token
+    }
+
+    /// Fresh token count
+    pub fn len(&self) -> usize {
+        self.tokens
+            .lock()
+            .map(|mut tokens| {
+                discard_expired(&mut tokens, self.ttl);
+                tokens.len()
+            })
+            .unwrap_or_default()
+    }
+
+    fn push(&self, token: ArkoseToken) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.push_back((Instant::now(), token));
+        }
+    }
+
+    /// Refill the pool up to its size, the failed solves are retried on the next interval
+    pub async fn periodic_refill(&self, typed: Type) {
+        info!(
+            "Arkose {typed} warm pool is running, size: {}, token ttl: {}s",
+            self.size,
+            self.ttl.as_secs()
+        );
+        loop {
+            while self.len() < self.size {
+                let arkose_client = match with_context!(arkose_client).await {
+                    Ok(arkose_client) => arkose_client,
+                    Err(err) => {
+                        warn!("Arkose {typed} warm pool refill error: {err}");
+                        break;
+                    }
+                };
+                let ctx = ArkoseContext::builder()
+                    .client(arkose_client.client())
+                    .typed(typed)
+                    .build();
+                match ArkoseToken::solve(ctx).await {
+                    Ok(token) if token.success() => self.push(token),
+                    Ok(_) => {
+                        warn!("Arkose {typed} warm pool refill got an unsolved token");
+                        break;
+                    }
+                    Err(err) => {
+                        warn!("Arkose {typed} warm pool refill error: {err}");
+                        break;
+                    }
+                }
+            }
+            debug!("Arkose {typed} warm pool has {} tokens", self.len());
+
+            tokio::select! {
+                _ = self.refill.notified() => {}
+                _ = tokio::time::sleep(REFILL_INTERVAL) => {}
+            }
+        }
+    }
+}
+
+fn discard_expired(tokens: &mut VecDeque<(Instant, ArkoseToken)>, ttl: Duration) {
+    while tokens
+        .front()
+        .is_some_and(|(solved_at, _)| solved_at.elapsed() >= ttl)
+    {
+        tokens.pop_front();
+    }
+}
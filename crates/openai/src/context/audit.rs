@@ -0,0 +1,89 @@
+use crate::{info, now_duration, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+};
+
+/// Audit log of the admin actions
+pub struct AuditLog {
+    /// Optional separate audit log file
+    file: Option<Mutex<File>>,
+}
+
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    timestamp: u64,
+    key: String,
+    action: &'a str,
+    params: serde_json::Value,
+    result: String,
+}
+
+impl AuditLog {
+    pub fn new(path: Option<&Path>) -> Self {
+        let file =
+            path.and_then(
+                |path| match OpenOptions::new().create(true).append(true).open(path) {
+                    Ok(file) => Some(Mutex::new(file)),
+                    Err(err) => {
+                        warn!("Failed to open audit log {}: {err}", path.display());
+                        None
+                    }
+                },
+            );
+        Self { file }
+    }
+
+    /// Record an admin action, the key is logged as a fingerprint
+    pub fn record<E: std::fmt::Display>(
+        &self,
+        key: Option<&str>,
+        action: &str,
+        params: serde_json::Value,
+        result: &Result<(), E>,
+    ) {
+        let entry = AuditEntry {
+            timestamp: now_duration().map(|d| d.as_secs()).unwrap_or_default(),
+            key: key
+                .map(fingerprint)
+                .unwrap_or_else(|| "anonymous".to_owned()),
+            action,
+            params,
+            result: match result {
+                Ok(_) => "ok".to_owned(),
+                Err(err) => format!("error: {err}"),
+            },
+        };
+
+        let line = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!("Serialize audit entry error: {err}");
+                return;
+            }
+        };
+
+        info!("[audit] {line}");
+
+        if let Some(file) = self.file.as_ref() {
+            if let Ok(mut file) = file.lock() {
+                if let Some(err) = writeln!(file, "{line}").err() {
+                    warn!("Write audit log error: {err}");
+                }
+            }
+        }
+    }
+}
+
+/// Key fingerprint, the first 8 bytes of the SHA-256 hex digest
+pub fn fingerprint(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .take(8)
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
@@ -0,0 +1,71 @@
+use crate::arkose;
+use crate::client::EgressKind;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+tokio::task_local! {
+    /// Routing decisions of the current request
+    static DECISIONS: Mutex<Decisions>;
+}
+
+/// Request routing decisions through the balancer, HAR pool and solver chain
+#[derive(Clone, Debug, Default)]
+pub struct Decisions {
+    /// Arkose type of the token
+    pub arkose_type: Option<arkose::Type>,
+    /// Index of the HAR file in the pool
+    pub har_index: Option<usize>,
+    /// Index of the client in the proxy pool
+    pub proxy_index: Option<usize>,
+    /// Egress kind of the upstream request
+    pub egress: Option<EgressKind>,
+    /// Solver that solved the funcaptcha, `solver#index` in the chain
+    pub solver: Option<String>,
+    /// Time spent in the arkose solve step
+    pub solve: Duration,
+    /// Time spent in the upstream step
+    pub upstream: Duration,
+}
+
+/// Run the future with the decisions recorded, return the output and the decisions.
+/// The decisions are passed on to the enclosing scope if any
+pub async fn scope<F: Future>(fut: F) -> (F::Output, Decisions) {
+    let (output, decisions) = DECISIONS
+        .scope(Mutex::new(Decisions::default()), async move {
+            let output = fut.await;
+            let decisions =
+                DECISIONS.with(|decisions| decisions.lock().map(|d| d.clone()).unwrap_or_default());
+            (output, decisions)
+        })
+        .await;
+    record(|d| *d = decisions.clone());
+    (output, decisions)
+}
+
+/// Record a decision of the current request, no-op outside of a scope
+pub fn record(f: impl FnOnce(&mut Decisions)) {
+    let _ = DECISIONS.try_with(|decisions| {
+        if let Ok(mut decisions) = decisions.lock() {
+            f(&mut decisions)
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{record, scope};
+
+    #[tokio::test]
+    async fn test_nested_scope() {
+        let ((_, inner), outer) = scope(async {
+            scope(async {
+                record(|d| d.proxy_index = Some(2));
+            })
+            .await
+        })
+        .await;
+        assert_eq!(inner.proxy_index, Some(2));
+        assert_eq!(outer.proxy_index, Some(2));
+    }
+}
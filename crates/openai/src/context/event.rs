@@ -0,0 +1,19 @@
+use std::{sync::Arc, time::Duration};
+
+/// Request lifecycle event callback, invoked inline on the request path and must not block
+pub type EventCallback = Arc<dyn Fn(Event) + Send + Sync>;
+
+/// Request lifecycle event
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// Request received
+    RequestReceived { method: String, path: String },
+    /// Arkose solve started
+    SolveStarted,
+    /// Arkose solve finished, timed out solves included
+    SolveFinished { elapsed: Duration },
+    /// Upstream response received, failed upstream requests included
+    UpstreamResponse { elapsed: Duration },
+    /// Request completed
+    RequestCompleted { status: u16, elapsed: Duration },
+}
@@ -1,14 +1,42 @@
 use super::{
     args::Args,
     arkose::{
-        har::{HarProvider, HAR},
+        har::{self, HarProvider, HAR},
+        warm::WarmPool,
         ArkoseVersionContext,
     },
+    audit::AuditLog,
     preauth::PreauthCookieProvider,
     CfTurnstile, Context, CTX,
 };
-use crate::{arkose, client::ClientRoundRobinBalancer, error};
-use std::{collections::HashMap, sync::RwLock};
+use crate::{arkose, arkose::flight::SingleFlight, client::ClientRoundRobinBalancer, error, warn};
+use reqwest::header::{HeaderName, HeaderValue};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{atomic::AtomicBool, Arc, RwLock},
+    time::Duration,
+};
+use tokio::sync::{Notify, Semaphore};
+
+/// Default HTTP CONNECT proxy host allowlist
+const DEFAULT_CONNECT_PROXY_ALLOWLIST: [&str; 6] = [
+    "openai.com",
+    "chatgpt.com",
+    "oaistatic.com",
+    "oaiusercontent.com",
+    "arkoselabs.com",
+    "funcaptcha.com",
+];
+
+/// Arkose HAR types and their default directory names, the single source of the initialized HAR providers
+pub(crate) const HAR_TYPES: [(arkose::Type, &str); 5] = [
+    (arkose::Type::GPT3, "gpt3"),
+    (arkose::Type::GPT4, "gpt4"),
+    (arkose::Type::Auth, "auth"),
+    (arkose::Type::Platform, "platform"),
+    (arkose::Type::SignUp, "signup"),
+];
 
 /// Use Once to guarantee initialization only once
 pub fn init(args: Args) {
@@ -16,9 +44,11 @@ pub fn init(args: Args) {
         error!("Failed to initialize context");
     };
 
+    let har_parse_concurrency = args.har_parse_concurrency;
     if let Some(_) = HAR.set(RwLock::new(init_har_provider(args))).err() {
         error!("Failed to initialize har provider");
     };
+    har::preload(har_parse_concurrency);
 }
 
 /// Get the program context
@@ -29,53 +59,183 @@ pub fn instance() -> &'static Context {
 /// Init the program context
 fn init_context(args: Args) -> Context {
     Context {
-        api_client: ClientRoundRobinBalancer::new_client(&args)
-            .expect("Failed to initialize the requesting client"),
-        auth_client: ClientRoundRobinBalancer::new_auth_client(&args)
-            .expect("Failed to initialize the requesting oauth client"),
-        arkose_client: ClientRoundRobinBalancer::new_arkose_client(&args)
-            .expect("Failed to initialize the requesting arkose client"),
-        preauth_provider: args.pbind.is_some().then(|| PreauthCookieProvider::new()),
+        api_client: RwLock::new(Arc::new(
+            ClientRoundRobinBalancer::new_client(&args)
+                .expect("Failed to initialize the requesting client"),
+        )),
+        auth_client: RwLock::new(Arc::new(
+            ClientRoundRobinBalancer::new_auth_client(&args)
+                .expect("Failed to initialize the requesting oauth client"),
+        )),
+        arkose_client: RwLock::new(Arc::new(
+            ClientRoundRobinBalancer::new_arkose_client(&args)
+                .expect("Failed to initialize the requesting arkose client"),
+        )),
+        client_reloaded: Notify::new(),
+        preauth_provider: args.pbind.is_some().then(|| {
+            PreauthCookieProvider::new(args.preauth_event_value, args.preauth_push_policy)
+        }),
+        preauth_ratio: args.preauth_ratio,
+        pipeline_semaphore: args
+            .pipeline_limit
+            .map(|limit| Arc::new(Semaphore::new(limit.max(1)))),
+        retry_after_jitter_ms: args.retry_after_jitter_ms,
+        read_idle_timeout: args
+            .read_idle_timeout
+            .map(|secs| Duration::from_secs(secs as u64)),
+        max_upstream_body_size: args.max_upstream_body_size,
+        slo: args.slo_ms.map(Duration::from_millis),
+        request_budget: args.request_budget_ms.map(Duration::from_millis),
+        shutdown_grace: Duration::from_secs(args.shutdown_grace_secs),
+        error_cache_ttl: args.error_cache_ttl.map(Duration::from_secs),
+        error_cache_threshold: args.error_cache_threshold,
         arkose_endpoint: args.arkose_endpoint,
+        arkose_endpoints: args.arkose_endpoints,
+        model_arkose_map: args.model_arkose_map,
+        unknown_arkose_policy: args.unknown_arkose_policy,
+        unmapped_models: Default::default(),
         arkose_context: ArkoseVersionContext::new(),
-        arkose_solver: args.arkose_solver,
+        arkose_solver_chain: RwLock::new(
+            args.arkose_solver
+                .into_iter()
+                .chain(args.arkose_solver_chain)
+                .collect(),
+        ),
+        arkose_solver_difficulty: args.arkose_solver_difficulty,
+        arkose_warm_pools: args
+            .arkose_warm_pool_size
+            .into_iter()
+            .filter(|(_, size)| *size > 0)
+            .map(|(typed, size)| {
+                let ttl = args
+                    .arkose_token_ttl
+                    .get(&typed)
+                    .map(|secs| Duration::from_secs(*secs));
+                (typed, WarmPool::new(size, ttl))
+            })
+            .collect(),
         arkose_gpt3_experiment: args.arkose_gpt3_experiment,
         arkose_gpt3_experiment_solver: args.arkose_gpt3_experiment_solver,
+        arkose_solver_signing: args.arkose_solver_signing,
         arkose_solver_tguess_endpoint: args.arkose_solver_tguess_endpoint,
         arkose_solver_image_dir: args.arkose_solver_image_dir,
+        solver_workers: args
+            .solver_workers
+            .map(|workers| Semaphore::new(workers.max(1))),
+        solver_queue_timeout: args.solver_queue_timeout.map(Duration::from_secs),
+        solve_flights: args.arkose_solve_coalesce.then(|| {
+            HAR_TYPES
+                .iter()
+                .map(|(typed, _)| (*typed, SingleFlight::default()))
+                .collect()
+        }),
+        max_har_size: max_har_size(args.max_har_size),
+        har_max_age: args.har_max_age,
+        har_max_capture_age: args.har_max_capture_age,
+        har_affinity: args.har_affinity,
+        primary_url: args.primary_url,
+        debug_headers: args.debug_headers,
+        server_timing: args.server_timing,
+        sticky_key: args.sticky_sessions.then(|| args.sticky_key.clone()),
+        log_sample_rate: args.log_sample_rate,
+        slow_request_threshold: args.slow_request_threshold_ms.map(Duration::from_millis),
         enable_file_proxy: args.enable_file_proxy,
+        enable_connect_proxy: args.enable_connect_proxy,
+        connect_proxy_allowlist: if args.connect_proxy_allowlist.is_empty() {
+            DEFAULT_CONNECT_PROXY_ALLOWLIST
+                .iter()
+                .map(|domain| domain.to_string())
+                .collect()
+        } else {
+            args.connect_proxy_allowlist
+                .iter()
+                .map(|domain| domain.trim().trim_start_matches('.').to_ascii_lowercase())
+                .collect()
+        },
         auth_key: args.auth_key,
+        har_upload_keys: args.har_upload_keys,
         visitor_email_whitelist: args.visitor_email_whitelist,
+        admin_ip_allowlist: args.admin_ip_allowlist,
+        trusted_proxies: args.trusted_proxies,
+        strip_response_headers: args
+            .strip_response_headers
+            .iter()
+            .filter_map(|name| match HeaderName::from_bytes(name.as_bytes()) {
+                Ok(name) => Some(name),
+                Err(err) => {
+                    warn!("Invalid strip response header: {name} ({err})");
+                    None
+                }
+            })
+            .collect(),
+        upstream_base: args
+            .upstream_base
+            .map(|base| base.trim_end_matches('/').to_owned()),
+        upstream_path_map: args.upstream_path_map,
+        rewrite_response_headers: args
+            .rewrite_response_headers
+            .iter()
+            .filter_map(|(name, value)| {
+                match (
+                    HeaderName::from_bytes(name.as_bytes()),
+                    HeaderValue::from_str(value),
+                ) {
+                    (Ok(name), Ok(value)) => Some((name, value)),
+                    _ => {
+                        warn!("Invalid rewrite response header: {name}: {value}");
+                        None
+                    }
+                }
+            })
+            .collect(),
+        concurrent_limit: args.concurrent_limit,
+        metrics: Default::default(),
+        on_event: args.on_event,
+        solver_stats: Default::default(),
+        audit_log: AuditLog::new(args.audit_log_path.as_deref()),
+        reload_status: Default::default(),
+        maintenance: RwLock::new(None),
+        drained: AtomicBool::new(false),
+        fallback_models: args.fallback_models_file.as_deref().map(|path| {
+            load_fallback_models(path).expect("Failed to load the fallback models file")
+        }),
         cf_turnstile: args.cf_site_key.and_then(|site_key| {
             args.cf_secret_key.map(|secret_key| CfTurnstile {
                 site_key,
                 secret_key,
+                verify_timeout: Duration::from_secs(args.cf_verify_timeout),
+                verify_retries: args.cf_verify_retries,
             })
         }),
     }
 }
 
-fn init_har_provider(args: Args) -> HashMap<arkose::Type, HarProvider> {
-    let gpt3_har_provider =
-        HarProvider::new(arkose::Type::GPT3, args.arkose_har_dir.as_ref(), "gpt3");
-    let gpt4_har_provider =
-        HarProvider::new(arkose::Type::GPT4, args.arkose_har_dir.as_ref(), "gpt4");
-    let auth_har_provider =
-        HarProvider::new(arkose::Type::Auth, args.arkose_har_dir.as_ref(), "auth");
-    let platform_har_provider = HarProvider::new(
-        arkose::Type::Platform,
-        args.arkose_har_dir.as_ref(),
-        "platform",
-    );
-    let signup_har_provider =
-        HarProvider::new(arkose::Type::SignUp, args.arkose_har_dir.as_ref(), "signup");
+/// Load the static model list (JSON)
+fn load_fallback_models(path: &Path) -> anyhow::Result<serde_json::Value> {
+    let bytes = std::fs::read(path)?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
 
-    let mut har_map = HashMap::with_capacity(5);
-    har_map.insert(arkose::Type::GPT3, gpt3_har_provider);
-    har_map.insert(arkose::Type::GPT4, gpt4_har_provider);
-    har_map.insert(arkose::Type::Auth, auth_har_provider);
-    har_map.insert(arkose::Type::Platform, platform_har_provider);
-    har_map.insert(arkose::Type::SignUp, signup_har_provider);
+/// Maximum HAR file size (bytes), 0 disables the limit
+fn max_har_size(mb: u64) -> Option<u64> {
+    (mb > 0).then(|| mb * 1024 * 1024)
+}
 
+fn init_har_provider(args: Args) -> HashMap<arkose::Type, HarProvider> {
+    let dir = args.arkose_har_dir.as_ref();
+    let mut har_map = HashMap::with_capacity(HAR_TYPES.len());
+    for (_type, default_dir_name) in HAR_TYPES {
+        har_map.insert(
+            _type,
+            HarProvider::new(
+                _type,
+                dir,
+                default_dir_name,
+                args.har_no_create,
+                !args.har_no_watch.contains(&_type),
+                max_har_size(args.max_har_size),
+            ),
+        );
+    }
     har_map
 }
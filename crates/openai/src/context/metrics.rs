@@ -0,0 +1,498 @@
+use crate::arkose::funcaptcha::solver::{ArkoseSolver, Difficulty};
+use crate::{arkose, info, warn};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+tokio::task_local! {
+    /// Solves of the current task are not recorded in the solver stats
+    static UNRECORDED: ();
+}
+
+/// Run the future without recording its solves in the solver stats, e.g. the admin probe
+pub async fn unrecorded_scope<F: Future>(f: F) -> F::Output {
+    UNRECORDED.scope((), f).await
+}
+
+fn is_unrecorded() -> bool {
+    UNRECORDED.try_with(|_| ()).is_ok()
+}
+
+/// Rolling window size of the solver results
+const SOLVER_WINDOW_SIZE: usize = 100;
+/// Minimum solver results before the success rate is reported
+const SOLVER_MIN_SAMPLES: usize = 10;
+/// Success rate below which the solver is considered degraded
+const SOLVER_DEGRADED_THRESHOLD: f64 = 0.5;
+/// Linear sub-buckets of each power of two range, ~12.5% relative precision
+const HISTOGRAM_SUB_BUCKETS: u64 = 8;
+/// Power of two ranges of the histogram, up to ~2^23ms (~2.3 hours)
+const HISTOGRAM_RANGES: u64 = 20;
+/// Reported latency quantiles
+const LATENCY_QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// Latency histogram of a request step
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Latency {
+    /// Arkose solve step
+    Solve,
+    /// Upstream call
+    Upstream,
+    /// Total request
+    Total,
+    /// Wait for a solver local worker (challenge image encoding), not the remote solver
+    SolverWorkQueue,
+}
+
+impl Latency {
+    const ALL: [Latency; 4] = [
+        Latency::Solve,
+        Latency::Upstream,
+        Latency::Total,
+        Latency::SolverWorkQueue,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Latency::Solve => "solve",
+            Latency::Upstream => "upstream",
+            Latency::Total => "total",
+            Latency::SolverWorkQueue => "solver_work_queue",
+        }
+    }
+}
+
+/// HDR-style latency histogram (milliseconds), power of two ranges with linear sub-buckets
+pub struct Histogram {
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        let len = HISTOGRAM_SUB_BUCKETS * (HISTOGRAM_RANGES + 1);
+        Self {
+            buckets: (0..len).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    /// Record a latency
+    pub fn record(&self, latency: Duration) {
+        let ms = latency.as_millis() as u64;
+        let index = Self::index(ms).min(self.buckets.len() - 1);
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(ms, Ordering::Relaxed);
+    }
+
+    /// Quantile (milliseconds), the upper bound of the bucket, `None` if nothing is recorded
+    pub fn quantile(&self, q: f64) -> Option<u64> {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == 0 {
+            return None;
+        }
+        let rank = ((q * count as f64).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                return Some(Self::upper_bound(index));
+            }
+        }
+        Some(Self::upper_bound(self.buckets.len() - 1))
+    }
+
+    fn index(ms: u64) -> usize {
+        if ms < HISTOGRAM_SUB_BUCKETS {
+            return ms as usize;
+        }
+        // 8 <= ms >> shift < 16
+        let shift = 63 - ms.leading_zeros() as u64 - HISTOGRAM_SUB_BUCKETS.trailing_zeros() as u64;
+        let sub = (ms >> shift) - HISTOGRAM_SUB_BUCKETS;
+        (HISTOGRAM_SUB_BUCKETS * (shift + 1) + sub) as usize
+    }
+
+    fn upper_bound(index: usize) -> u64 {
+        let index = index as u64;
+        if index < HISTOGRAM_SUB_BUCKETS {
+            return index;
+        }
+        let shift = index / HISTOGRAM_SUB_BUCKETS - 1;
+        let sub = index % HISTOGRAM_SUB_BUCKETS;
+        ((HISTOGRAM_SUB_BUCKETS + sub + 1) << shift) - 1
+    }
+
+    fn snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            count: self.count.load(Ordering::Relaxed),
+            sum: self.sum.load(Ordering::Relaxed),
+            p50: self.quantile(0.5),
+            p90: self.quantile(0.9),
+            p99: self.quantile(0.99),
+        }
+    }
+}
+
+/// Active connection count of the server
+pub type ConnectionGauge = Box<dyn Fn() -> usize + Send + Sync>;
+
+/// In-flight request or queued solver work, released on drop
+pub struct InFlight<'a>(&'a AtomicU64);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Server metrics
+#[derive(Default)]
+pub struct Metrics {
+    /// Total requests
+    requests: AtomicU64,
+    /// Client error responses (4xx)
+    client_errors: AtomicU64,
+    /// Server error responses (5xx)
+    server_errors: AtomicU64,
+    /// Direct connections while the pool has proxy clients
+    direct_fallbacks: AtomicU64,
+    /// In-flight requests
+    in_flight: AtomicU64,
+    /// Active connection count, set by the server on startup
+    connections: OnceLock<ConnectionGauge>,
+    /// Solver local work waiting for a solver worker
+    solver_work_queued: AtomicU64,
+    /// Solver local work rejected after the queue timeout
+    solver_work_rejections: AtomicU64,
+    /// Latency histograms
+    solve: Histogram,
+    upstream: Histogram,
+    total: Histogram,
+    solver_work_queue: Histogram,
+}
+
+/// Point-in-time copy of the server metrics
+#[derive(Serialize, Clone, Debug)]
+pub struct MetricsSnapshot {
+    pub requests: u64,
+    pub client_errors: u64,
+    pub server_errors: u64,
+    pub direct_fallbacks: u64,
+    pub in_flight: u64,
+    pub connections: usize,
+    pub solver_work_queued: u64,
+    pub solver_work_rejections: u64,
+    /// Latency quantiles (milliseconds) of each step
+    pub latency: HashMap<&'static str, LatencySnapshot>,
+}
+
+/// Point-in-time latency quantiles (milliseconds)
+#[derive(Serialize, Clone, Debug)]
+pub struct LatencySnapshot {
+    pub count: u64,
+    pub sum: u64,
+    pub p50: Option<u64>,
+    pub p90: Option<u64>,
+    pub p99: Option<u64>,
+}
+
+impl Metrics {
+    /// Record a response status
+    pub fn record(&self, status: u16) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        match status {
+            400..=499 => self.client_errors.fetch_add(1, Ordering::Relaxed),
+            500..=599 => self.server_errors.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+    }
+
+    /// Start an in-flight request, finished when the guard is dropped
+    pub fn start_request(&self) -> InFlight<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(&self.in_flight)
+    }
+
+    /// In-flight requests
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Set the active connection count of the server, only the first one is kept
+    pub fn set_connection_gauge(&self, gauge: impl Fn() -> usize + Send + Sync + 'static) {
+        let _ = self.connections.set(Box::new(gauge));
+    }
+
+    /// Active connection count, 0 if the server is not started
+    pub fn connections(&self) -> usize {
+        self.connections
+            .get()
+            .map(|gauge| gauge())
+            .unwrap_or_default()
+    }
+
+    /// Queue a solver local work for a solver worker, dequeued when the guard is dropped
+    pub fn start_solver_work_wait(&self) -> InFlight<'_> {
+        self.solver_work_queued.fetch_add(1, Ordering::Relaxed);
+        InFlight(&self.solver_work_queued)
+    }
+
+    /// Solver local work waiting for a solver worker
+    pub fn solver_work_queued(&self) -> u64 {
+        self.solver_work_queued.load(Ordering::Relaxed)
+    }
+
+    /// Record a solver local work rejected after the queue timeout
+    pub fn record_solver_work_rejection(&self) {
+        self.solver_work_rejections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a direct connection while the pool has proxy clients
+    pub fn record_direct_fallback(&self) {
+        self.direct_fallbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the latency of a request step
+    pub fn record_latency(&self, latency: Latency, elapsed: Duration) {
+        self.histogram(latency).record(elapsed)
+    }
+
+    fn histogram(&self, latency: Latency) -> &Histogram {
+        match latency {
+            Latency::Solve => &self.solve,
+            Latency::Upstream => &self.upstream,
+            Latency::Total => &self.total,
+            Latency::SolverWorkQueue => &self.solver_work_queue,
+        }
+    }
+
+    /// Snapshot of the current metrics
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            client_errors: self.client_errors.load(Ordering::Relaxed),
+            server_errors: self.server_errors.load(Ordering::Relaxed),
+            direct_fallbacks: self.direct_fallbacks.load(Ordering::Relaxed),
+            in_flight: self.in_flight(),
+            connections: self.connections(),
+            solver_work_queued: self.solver_work_queued(),
+            solver_work_rejections: self.solver_work_rejections.load(Ordering::Relaxed),
+            latency: Latency::ALL
+                .into_iter()
+                .map(|l| (l.name(), self.histogram(l).snapshot()))
+                .collect(),
+        }
+    }
+
+    /// Render the metrics in the Prometheus text format
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            ("ninja_requests_total", &self.requests),
+            ("ninja_client_errors_total", &self.client_errors),
+            ("ninja_server_errors_total", &self.server_errors),
+            ("ninja_direct_fallbacks_total", &self.direct_fallbacks),
+            (
+                "ninja_solver_work_rejections_total",
+                &self.solver_work_rejections,
+            ),
+        ];
+        for (name, counter) in counters {
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", counter.load(Ordering::Relaxed));
+        }
+
+        let gauges = [
+            ("ninja_in_flight_requests", self.in_flight()),
+            ("ninja_active_connections", self.connections() as u64),
+            ("ninja_solver_work_queued", self.solver_work_queued()),
+        ];
+        for (name, gauge) in gauges {
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name} {gauge}");
+        }
+
+        let _ = writeln!(out, "# TYPE ninja_latency_milliseconds summary");
+        for latency in Latency::ALL {
+            let step = latency.name();
+            let histogram = self.histogram(latency);
+            for q in LATENCY_QUANTILES {
+                if let Some(value) = histogram.quantile(q) {
+                    let _ = writeln!(
+                        out,
+                        "ninja_latency_milliseconds{{step=\"{step}\",quantile=\"{q}\"}} {value}"
+                    );
+                }
+            }
+            let _ = writeln!(
+                out,
+                "ninja_latency_milliseconds_sum{{step=\"{step}\"}} {}",
+                histogram.sum.load(Ordering::Relaxed)
+            );
+            let _ = writeln!(
+                out,
+                "ninja_latency_milliseconds_count{{step=\"{step}\"}} {}",
+                histogram.count.load(Ordering::Relaxed)
+            );
+        }
+        out
+    }
+}
+
+/// Rolling solver success rate of each arkose type
+#[derive(Default)]
+pub struct SolverStats {
+    windows: Mutex<HashMap<arkose::Type, SolverWindow>>,
+    /// Successful solves of each solver in the chain, keyed by `solver#index`
+    solved_by: Mutex<HashMap<String, u64>>,
+    /// Successful solves of the difficulty routed challenges, keyed by `difficulty/solver#index`
+    solved_by_difficulty: Mutex<HashMap<String, u64>>,
+}
+
+#[derive(Default)]
+struct SolverWindow {
+    results: VecDeque<bool>,
+    degraded: bool,
+}
+
+impl SolverWindow {
+    fn success_rate(&self) -> Option<f64> {
+        if self.results.len() < SOLVER_MIN_SAMPLES {
+            return None;
+        }
+        let success = self.results.iter().filter(|ok| **ok).count();
+        Some(success as f64 / self.results.len() as f64)
+    }
+}
+
+impl SolverStats {
+    /// Record a solver result, warn when the success rate drops below the threshold
+    pub fn record(&self, t: arkose::Type, success: bool) {
+        if is_unrecorded() {
+            return;
+        }
+        let mut windows = self.windows.lock().expect("Failed to get solver stats");
+        let window = windows.entry(t).or_default();
+
+        if window.results.len() >= SOLVER_WINDOW_SIZE {
+            window.results.pop_front();
+        }
+        window.results.push_back(success);
+
+        if let Some(rate) = window.success_rate() {
+            let degraded = rate < SOLVER_DEGRADED_THRESHOLD;
+            if degraded && !window.degraded {
+                warn!(
+                    "Arkose {t} solver success rate dropped to {:.0}%, the solver/HAR needs attention",
+                    rate * 100.0
+                );
+            } else if !degraded && window.degraded {
+                info!(
+                    "Arkose {t} solver success rate recovered to {:.0}%",
+                    rate * 100.0
+                );
+            }
+            window.degraded = degraded;
+        }
+    }
+
+    /// Rolling success rate, `None` until enough results are recorded
+    pub fn success_rate(&self, t: arkose::Type) -> Option<f64> {
+        self.windows
+            .lock()
+            .ok()
+            .and_then(|windows| windows.get(&t).and_then(SolverWindow::success_rate))
+    }
+
+    /// Whether the success rate is below the degraded threshold
+    pub fn is_degraded(&self, t: arkose::Type) -> bool {
+        self.windows
+            .lock()
+            .ok()
+            .and_then(|windows| windows.get(&t).map(|w| w.degraded))
+            .unwrap_or(false)
+    }
+
+    /// Record the solver of the chain that ultimately solved the challenge, and its difficulty
+    /// if the challenge is routed by the difficulty
+    pub fn record_solved_by(
+        &self,
+        index: usize,
+        solver: &ArkoseSolver,
+        difficulty: Option<Difficulty>,
+    ) {
+        if is_unrecorded() {
+            return;
+        }
+        let key = format!("{}#{index}", solver.solver);
+        if let Some(difficulty) = difficulty {
+            if let Ok(mut solved_by) = self.solved_by_difficulty.lock() {
+                *solved_by.entry(format!("{difficulty}/{key}")).or_default() += 1;
+            }
+        }
+        if let Ok(mut solved_by) = self.solved_by.lock() {
+            *solved_by.entry(key).or_default() += 1;
+        }
+    }
+
+    /// Successful solves of each solver in the chain
+    pub fn solved_by(&self) -> HashMap<String, u64> {
+        self.solved_by
+            .lock()
+            .map(|solved_by| solved_by.clone())
+            .unwrap_or_default()
+    }
+
+    /// Successful solves of the difficulty routed challenges of each solver in the chain
+    pub fn solved_by_difficulty(&self) -> HashMap<String, u64> {
+        self.solved_by_difficulty
+            .lock()
+            .map(|solved_by| solved_by.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Histogram;
+    use std::time::Duration;
+
+    #[test]
+    fn test_histogram_bucket_bounds() {
+        for ms in [0, 1, 7, 8, 9, 15, 16, 17, 100, 1000, 12345, 600_000] {
+            let upper = Histogram::upper_bound(Histogram::index(ms));
+            assert!(upper >= ms, "{ms} above the bucket upper bound {upper}");
+            // ~12.5% relative precision
+            assert!(
+                upper - ms <= ms / 8,
+                "{ms} bucket upper bound {upper} too coarse"
+            );
+        }
+    }
+
+    #[test]
+    fn test_histogram_quantile() {
+        let histogram = Histogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+        for ms in 1..=100 {
+            histogram.record(Duration::from_millis(ms));
+        }
+        let p50 = histogram.quantile(0.5).unwrap();
+        let p99 = histogram.quantile(0.99).unwrap();
+        assert!((50..=56).contains(&p50), "p50 {p50}");
+        assert!((99..=111).contains(&p99), "p99 {p99}");
+    }
+}
@@ -1,17 +1,48 @@
 pub mod args;
 pub mod arkose;
+pub mod audit;
+pub mod decision;
+pub mod event;
 pub mod init;
-mod preauth;
+pub mod metrics;
+pub mod preauth;
+pub mod reload;
 
-use self::preauth::PreauthCookieProvider;
+use self::{
+    args::UnknownArkosePolicy,
+    arkose::warm::WarmPool,
+    audit::AuditLog,
+    event::{Event, EventCallback},
+    metrics::{Metrics, SolverStats},
+    preauth::{PreauthCapture, PreauthCookieProvider},
+    reload::{ReloadSource, ReloadStatus},
+};
 use crate::{
-    arkose::funcaptcha::solver::ArkoseSolver, auth::AuthClient, client::ClientRoundRobinBalancer,
+    arkose::{
+        flight::SingleFlight,
+        funcaptcha::solver::{ArkoseSolver, DifficultyRoute, SolverSigning},
+    },
+    auth::AuthClient,
+    client::{
+        ClientAtCapacity, ClientRoundRobinBalancer, Egress, ProbeResult, ProxyStat, RequestTracker,
+        StickyKey, TrackedClient,
+    },
+    info, warn,
+};
+use reqwest::{
+    header::{HeaderName, HeaderValue},
+    Client,
 };
-use reqwest::Client;
 use std::{
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
-    sync::OnceLock,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock, RwLock,
+    },
+    time::Duration,
 };
+use tokio::sync::{Notify, Semaphore};
 
 pub const WORKER_DIR: &str = ".ninja";
 // Program context
@@ -34,63 +65,285 @@ pub fn init(args: args::Args) {
     init::init(args);
 }
 
+/// Maintenance mode, the proxy requests are rejected with the status and message
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Maintenance {
+    pub status: u16,
+    pub message: String,
+}
+
 pub struct CfTurnstile {
     pub site_key: String,
     pub secret_key: String,
+    /// Timeout of each siteverify call
+    pub verify_timeout: Duration,
+    /// Retries of the transiently failed siteverify call
+    pub verify_retries: u32,
 }
 
 pub struct Context {
-    /// Requesting client
-    api_client: ClientRoundRobinBalancer,
-    /// Requesting oauth client
-    auth_client: ClientRoundRobinBalancer,
-    /// Requesting arkose client
-    arkose_client: ClientRoundRobinBalancer,
+    /// Requesting client, swapped on reload
+    api_client: RwLock<Arc<ClientRoundRobinBalancer>>,
+    /// Requesting oauth client, swapped on reload
+    auth_client: RwLock<Arc<ClientRoundRobinBalancer>>,
+    /// Requesting arkose client, swapped on reload
+    arkose_client: RwLock<Arc<ClientRoundRobinBalancer>>,
+    /// Notify the health check task of the reloaded clients
+    client_reloaded: Notify,
     /// Arkoselabs context
     arkose_context: arkose::ArkoseVersionContext<'static>,
-    /// arkoselabs solver
-    arkose_solver: Option<ArkoseSolver>,
+    /// arkoselabs solver chain, the primary solver first, swapped on reload
+    arkose_solver_chain: RwLock<Arc<[ArkoseSolver]>>,
+    /// arkoselabs solver routes of the easy challenges of each type
+    arkose_solver_difficulty: HashMap<crate::arkose::Type, DifficultyRoute>,
+    /// arkoselabs pre-solved token pools of each type
+    arkose_warm_pools: HashMap<crate::arkose::Type, WarmPool>,
     /// Enable files proxy
     enable_file_proxy: bool,
+    /// Enable HTTP CONNECT proxy
+    enable_connect_proxy: bool,
+    /// HTTP CONNECT proxy host allowlist
+    connect_proxy_allowlist: Vec<String>,
     /// Login auth key
     auth_key: Option<String>,
+    /// HAR upload keys scoped to a single type (key -> type)
+    har_upload_keys: HashMap<String, crate::arkose::Type>,
+    /// Admin and HAR management endpoints ip allowlist
+    admin_ip_allowlist: Vec<cidr::IpCidr>,
+    /// Trusted reverse proxies
+    trusted_proxies: Vec<cidr::IpCidr>,
     /// visitor_email_whitelist
     visitor_email_whitelist: Option<Vec<String>>,
     /// Cloudflare Turnstile
     cf_turnstile: Option<CfTurnstile>,
+    /// Proxy response body read idle timeout
+    read_idle_timeout: Option<Duration>,
+    /// Proxy response body size limit (bytes)
+    max_upstream_body_size: Option<u64>,
+    /// Proxy request SLO deadline
+    slo: Option<Duration>,
+    /// Proxy request budget
+    request_budget: Option<Duration>,
+    /// Server shutdown grace period
+    shutdown_grace: Duration,
+    /// Upstream error cache cooldown
+    error_cache_ttl: Option<Duration>,
+    /// Consecutive upstream failures to start the error cache cooldown
+    error_cache_threshold: usize,
+    /// End-to-end request pipeline semaphore
+    pipeline_semaphore: Option<Arc<Semaphore>>,
+    /// Retry-After jitter (milliseconds) of the 429 rejections
+    retry_after_jitter_ms: u64,
     /// Arkose endpoint
     arkose_endpoint: Option<String>,
+    /// Arkose endpoint of each type
+    arkose_endpoints: HashMap<crate::arkose::Type, String>,
+    /// Model to arkose type map
+    model_arkose_map: HashMap<String, crate::arkose::Type>,
+    /// Handling of a model mapped to no arkose type
+    unknown_arkose_policy: UnknownArkosePolicy,
+    /// Models mapped to no arkose type, logged once
+    unmapped_models: std::sync::Mutex<HashSet<String>>,
     /// Enable Arkose GPT-3.5 experiment
     arkose_gpt3_experiment: bool,
     /// Enable Arkose GPT-3.5 experiment solver
     arkose_gpt3_experiment_solver: bool,
+    /// Arkose solver request signing
+    arkose_solver_signing: Option<SolverSigning>,
     /// Arkose solver tguess endpoint
     arkose_solver_tguess_endpoint: Option<String>,
     /// Arkose solver image store directory
     arkose_solver_image_dir: Option<PathBuf>,
+    /// Arkose solver local work semaphore
+    solver_workers: Option<Semaphore>,
+    /// Arkose solver local work queue timeout
+    solver_queue_timeout: Option<Duration>,
+    /// Arkose solve single-flight of each type, the failure is shared
+    solve_flights: Option<HashMap<crate::arkose::Type, SingleFlight<()>>>,
+    /// Maximum HAR file size (bytes)
+    max_har_size: Option<u64>,
+    /// Maximum HAR age (seconds)
+    har_max_age: Option<u64>,
+    /// Maximum HAR capture age (seconds) on load
+    har_max_capture_age: Option<u64>,
+    /// Pin the HAR of the pool by the client IP
+    har_affinity: bool,
+    /// Primary node url of the replica
+    primary_url: Option<String>,
+    /// Emit the request routing decisions in the debug response headers
+    debug_headers: bool,
+    /// Emit the phase timings in the Server-Timing response header
+    server_timing: bool,
+    /// Sticky session key source, `None` if the sticky sessions are disabled
+    sticky_key: Option<StickyKey>,
+    /// Fraction of the requests logging the access lines
+    log_sample_rate: f64,
+    /// Slow request threshold
+    slow_request_threshold: Option<Duration>,
     /// PreAuth cookie cache
     preauth_provider: Option<PreauthCookieProvider>,
+    /// Fraction of the Apple logins taking the preauth path
+    preauth_ratio: f64,
+    /// Strip response headers
+    strip_response_headers: Vec<HeaderName>,
+    /// Rewrite response headers
+    rewrite_response_headers: Vec<(HeaderName, HeaderValue)>,
+    /// OpenAI API upstream base url
+    upstream_base: Option<String>,
+    /// Upstream path prefix map
+    upstream_path_map: Vec<(String, String)>,
+    /// Server concurrent limit
+    concurrent_limit: usize,
+    /// Server metrics
+    metrics: Metrics,
+    /// Request lifecycle event callback
+    on_event: Option<EventCallback>,
+    /// Arkose solver stats
+    solver_stats: SolverStats,
+    /// Admin actions audit log
+    audit_log: AuditLog,
+    /// Last reload results of each subsystem
+    reload_status: ReloadStatus,
+    /// Maintenance mode
+    maintenance: RwLock<Option<Maintenance>>,
+    /// Drain mode
+    drained: AtomicBool,
+    /// Static model list served when the upstream is down
+    fallback_models: Option<serde_json::Value>,
 }
 
 impl Context {
-    /// Get the reqwest client
-    pub fn api_client(&self) -> Client {
-        self.api_client.next().into()
+    /// Get the reqwest client, holding the in-flight permit of the request until dropped
+    pub async fn api_client(&self) -> Result<TrackedClient<Client>, ClientAtCapacity> {
+        let (client, tracker) = current(&self.api_client).next_tracked().await?;
+        decision::record(|d| d.proxy_index = Some(tracker.index()));
+        self.record_egress(&tracker);
+        Ok(TrackedClient::new(client.into(), tracker))
+    }
+
+    /// Get the reqwest client with the request tracker, the proxy is pre-checked reachable if enabled
+    pub async fn api_client_checked(&self) -> Result<(Client, RequestTracker), ClientAtCapacity> {
+        let (client, tracker) = current(&self.api_client).next_tracked_checked().await?;
+        decision::record(|d| d.proxy_index = Some(tracker.index()));
+        self.record_egress(&tracker);
+        Ok((client.into(), tracker))
+    }
+
+    /// Get the reqwest auth client, holding the in-flight permit of the request until dropped
+    pub async fn auth_client(&self) -> Result<TrackedClient<AuthClient>, ClientAtCapacity> {
+        let (client, tracker) = current(&self.auth_client).next_tracked().await?;
+        self.record_egress(&tracker);
+        Ok(TrackedClient::new(client.into(), tracker))
+    }
+
+    /// Get the reqwest arkose client, holding the in-flight permit of the request until dropped
+    pub async fn arkose_client(&self) -> Result<TrackedClient<Client>, ClientAtCapacity> {
+        let (client, tracker) = current(&self.arkose_client).next_tracked().await?;
+        self.record_egress(&tracker);
+        Ok(TrackedClient::new(client.into(), tracker))
+    }
+
+    /// Record the egress kind of the request, count the direct fallbacks
+    fn record_egress(&self, tracker: &RequestTracker) {
+        let egress = tracker.egress_kind();
+        decision::record(|d| d.egress = Some(egress));
+        if tracker.is_direct_fallback() {
+            self.metrics.record_direct_fallback();
+        }
     }
 
-    /// Get the reqwest auth client
-    pub fn auth_client(&self) -> AuthClient {
-        self.auth_client.next().into()
+    /// Probe the client proxies with the url
+    pub async fn probe_proxies(&self, url: &str) -> Vec<ProbeResult> {
+        let (api, auth, arkose) = (
+            current(&self.api_client),
+            current(&self.auth_client),
+            current(&self.arkose_client),
+        );
+        let (api, auth, arkose) = tokio::join!(
+            api.probe("api", url),
+            auth.probe("auth", url),
+            arkose.probe("arkose", url)
+        );
+        api.into_iter().chain(auth).chain(arkose).collect()
     }
 
-    /// Get the reqwest arkose client
-    pub fn arkose_client(&self) -> Client {
-        self.arkose_client.next().into()
+    /// Run a periodic task to health check the client proxies,
+    /// restarted with the new clients after a reload
+    pub async fn periodic_healthcheck(&self) {
+        loop {
+            let (api, auth, arkose) = (
+                current(&self.api_client),
+                current(&self.auth_client),
+                current(&self.arkose_client),
+            );
+            tokio::select! {
+                _ = async {
+                    tokio::join!(
+                        api.periodic_healthcheck(),
+                        auth.periodic_healthcheck(),
+                        arkose.periodic_healthcheck()
+                    );
+                    std::future::pending::<()>().await
+                } => {}
+                _ = self.client_reloaded.notified() => {}
+            }
+        }
+    }
+
+    /// Reload the client proxies and the arkoselabs solver chain, HAR is untouched
+    pub fn reload(&self, args: &args::Args) -> anyhow::Result<()> {
+        let api_client = Arc::new(ClientRoundRobinBalancer::new_client(args)?);
+        let auth_client = Arc::new(ClientRoundRobinBalancer::new_auth_client(args)?);
+        let arkose_client = Arc::new(ClientRoundRobinBalancer::new_arkose_client(args)?);
+        let arkose_solver_chain: Arc<[ArkoseSolver]> = args
+            .arkose_solver
+            .iter()
+            .chain(&args.arkose_solver_chain)
+            .cloned()
+            .collect();
+
+        info!(
+            "Reloaded clients: api {}, auth {}, arkose {}, solver chain: {:?}",
+            api_client.pool_size(),
+            auth_client.pool_size(),
+            arkose_client.pool_size(),
+            arkose_solver_chain
+                .iter()
+                .map(|s| &s.solver)
+                .collect::<Vec<_>>()
+        );
+
+        replace(&self.api_client, api_client);
+        replace(&self.auth_client, auth_client);
+        replace(&self.arkose_client, arkose_client);
+        replace(&self.arkose_solver_chain, arkose_solver_chain);
+        self.client_reloaded.notify_waiters();
+        Ok(())
     }
 
     /// Get the arkoselabs solver
-    pub fn arkose_solver(&self) -> Option<&ArkoseSolver> {
-        self.arkose_solver.as_ref()
+    pub fn arkose_solver(&self) -> Option<ArkoseSolver> {
+        current(&self.arkose_solver_chain).first().cloned()
+    }
+
+    /// Get the arkoselabs solver chain, tried in order
+    pub fn arkose_solver_chain(&self) -> Arc<[ArkoseSolver]> {
+        current(&self.arkose_solver_chain)
+    }
+
+    /// Get the arkoselabs pre-solved token pool of the type
+    pub fn arkose_warm_pool(&self, t: crate::arkose::Type) -> Option<&WarmPool> {
+        self.arkose_warm_pools.get(&t)
+    }
+
+    /// Get the arkoselabs pre-solved token pools of each type
+    pub fn arkose_warm_pools(&self) -> &HashMap<crate::arkose::Type, WarmPool> {
+        &self.arkose_warm_pools
+    }
+
+    /// Get the arkoselabs solver route by the challenge difficulty of the type
+    pub fn arkose_solver_route(&self, t: crate::arkose::Type) -> Option<DifficultyRoute> {
+        self.arkose_solver_difficulty.get(&t).copied()
     }
 
     /// Cloudflare Turnstile config
@@ -98,9 +351,79 @@ impl Context {
         self.cf_turnstile.as_ref()
     }
 
-    /// Arkoselabs endpoint
-    pub fn arkose_endpoint(&self) -> Option<&str> {
-        self.arkose_endpoint.as_deref()
+    /// End-to-end request pipeline semaphore
+    pub fn pipeline_semaphore(&self) -> Option<&Arc<Semaphore>> {
+        self.pipeline_semaphore.as_ref()
+    }
+
+    /// Retry-After jitter (milliseconds) of the 429 rejections
+    pub fn retry_after_jitter_ms(&self) -> u64 {
+        self.retry_after_jitter_ms
+    }
+
+    /// Proxy response body read idle timeout
+    pub fn read_idle_timeout(&self) -> Option<Duration> {
+        self.read_idle_timeout
+    }
+
+    /// Proxy response body size limit (bytes)
+    pub fn max_upstream_body_size(&self) -> Option<u64> {
+        self.max_upstream_body_size
+    }
+
+    /// Proxy request SLO deadline
+    pub fn slo(&self) -> Option<Duration> {
+        self.slo
+    }
+
+    /// Proxy request budget
+    pub fn request_budget(&self) -> Option<Duration> {
+        self.request_budget
+    }
+
+    /// Server shutdown grace period
+    pub fn shutdown_grace(&self) -> Duration {
+        self.shutdown_grace
+    }
+
+    /// Upstream error cache cooldown, `None` if disabled
+    pub fn error_cache_ttl(&self) -> Option<Duration> {
+        self.error_cache_ttl
+    }
+
+    /// Consecutive upstream failures to start the error cache cooldown
+    pub fn error_cache_threshold(&self) -> usize {
+        self.error_cache_threshold
+    }
+
+    /// Arkoselabs endpoint of the given type, fallback to the global endpoint
+    pub fn arkose_endpoint(&self, t: crate::arkose::Type) -> Option<&str> {
+        self.arkose_endpoints
+            .get(&t)
+            .map(String::as_str)
+            .or(self.arkose_endpoint.as_deref())
+    }
+
+    /// Arkoselabs type of the configured model, `None` falls back to the default model mapping
+    pub fn model_arkose_type(&self, model: &str) -> Option<crate::arkose::Type> {
+        self.model_arkose_map.get(model).copied()
+    }
+
+    /// Handling of a model mapped to no arkose type
+    pub fn unknown_arkose_policy(&self) -> UnknownArkosePolicy {
+        self.unknown_arkose_policy
+    }
+
+    /// Log the model mapped to no arkose type, once per model
+    pub fn log_unmapped_model(&self, model: &str) {
+        if let Ok(mut models) = self.unmapped_models.lock() {
+            if models.insert(model.to_owned()) {
+                warn!(
+                    "Model {model} maps to no arkose type ({}), map it with --model-arkose-map",
+                    self.unknown_arkose_policy
+                );
+            }
+        }
     }
 
     /// Login auth key
@@ -108,12 +431,22 @@ impl Context {
         self.auth_key.as_deref()
     }
 
-    /// Push a preauth cookie
+    /// Type of the scoped HAR upload key
+    pub fn har_upload_key_type(&self, key: &str) -> Option<crate::arkose::Type> {
+        self.har_upload_keys.get(key).copied()
+    }
+
+    /// Push a preauth cookie, `ttl` overrides the cache default of the entry
     #[cfg(feature = "preauth")]
-    pub fn push_preauth_cookie(&self, value: &str, max_age: Option<u32>) {
+    pub fn push_preauth_cookie(
+        &self,
+        value: &str,
+        max_age: Option<u32>,
+        ttl: Option<std::time::Duration>,
+    ) {
         self.preauth_provider
             .as_ref()
-            .map(|p| p.push(value, max_age));
+            .map(|p| p.push(value, max_age, ttl));
     }
 
     /// Pop a preauth cookie
@@ -122,6 +455,115 @@ impl Context {
         self.preauth_provider.as_ref().map(|p| p.get()).flatten()
     }
 
+    /// Subscribe to preauth cookie capture events
+    /// The cookie value is only included when `preauth_event_value` is enabled
+    #[cfg(feature = "preauth")]
+    pub fn subscribe_preauth_captures(
+        &self,
+    ) -> Option<tokio::sync::broadcast::Receiver<PreauthCapture>> {
+        self.preauth_provider.as_ref().map(|p| p.subscribe())
+    }
+
+    /// Fraction (0.0-1.0) of the Apple logins taking the preauth path, the rest fall back to the web login
+    pub fn preauth_ratio(&self) -> f64 {
+        self.preauth_ratio
+    }
+
+    /// Number of cached preauth cookies
+    pub fn preauth_cookie_count(&self) -> Option<u64> {
+        self.preauth_provider.as_ref().map(|p| p.size())
+    }
+
+    /// Get the server metrics
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Emit the request lifecycle event to the callback if registered
+    pub fn emit(&self, event: impl FnOnce() -> Event) {
+        if let Some(on_event) = self.on_event.as_ref() {
+            on_event(event())
+        }
+    }
+
+    /// Server concurrent limit
+    pub fn concurrent_limit(&self) -> usize {
+        self.concurrent_limit
+    }
+
+    /// Get the maintenance mode, `None` is not in maintenance
+    pub fn maintenance(&self) -> Option<Maintenance> {
+        self.maintenance
+            .read()
+            .map(|maintenance| maintenance.clone())
+            .unwrap_or_default()
+    }
+
+    /// Set the maintenance mode
+    pub fn set_maintenance(&self, maintenance: Option<Maintenance>) {
+        if let Ok(mut lock) = self.maintenance.write() {
+            *lock = maintenance;
+        }
+    }
+
+    /// Check if the server is drained, the new proxy requests are rejected with 503 while the
+    /// in-flight ones complete
+    pub fn is_drained(&self) -> bool {
+        self.drained.load(Ordering::Relaxed)
+    }
+
+    /// Set the drain mode, e.g. before taking the node out of a load balancer
+    pub fn set_drained(&self, drained: bool) {
+        self.drained.store(drained, Ordering::Relaxed)
+    }
+
+    /// Static model list served when the upstream is down
+    pub fn fallback_models(&self) -> Option<&serde_json::Value> {
+        self.fallback_models.as_ref()
+    }
+
+    /// Validate the HAR bytes and install it into the pool of the type from memory,
+    /// the programmatic equivalent of the HAR upload bypassing the filesystem
+    pub fn set_har(&self, t: crate::arkose::Type, bytes: &[u8]) -> anyhow::Result<()> {
+        let result = arkose::har::install(&t, bytes).map(|_| ());
+        self.reload_status
+            .record(format!("har:{t}"), ReloadSource::Api, &result);
+        result
+    }
+
+    /// Admin actions audit log
+    pub fn audit_log(&self) -> &AuditLog {
+        &self.audit_log
+    }
+
+    /// Last reload results of each subsystem
+    pub fn reload_status(&self) -> &ReloadStatus {
+        &self.reload_status
+    }
+
+    /// Arkose solver stats
+    pub fn solver_stats(&self) -> &SolverStats {
+        &self.solver_stats
+    }
+
+    /// Rolling arkose solver success rate of the given type
+    pub fn solver_success_rate(&self, t: crate::arkose::Type) -> Option<f64> {
+        self.solver_stats.success_rate(t)
+    }
+
+    /// Check if the address is a configured interface of the api clients
+    pub fn has_egress_ip(&self, ip: std::net::IpAddr) -> bool {
+        current(&self.api_client).has_bind(ip)
+    }
+
+    /// Get the client proxy stats
+    pub fn proxy_stats(&self) -> Vec<ProxyStat> {
+        let mut stats = current(&self.api_client).stats("api");
+        stats.extend(current(&self.auth_client).stats("auth"));
+        stats.extend(current(&self.arkose_client).stats("arkose"));
+        stats
+    }
+
     /// Get the arkose gpt3 experiment
     pub fn arkose_gpt3_experiment(&self) -> bool {
         self.arkose_gpt3_experiment
@@ -132,6 +574,45 @@ impl Context {
         self.enable_file_proxy
     }
 
+    /// Enable HTTP CONNECT proxy
+    pub fn enable_connect_proxy(&self) -> bool {
+        self.enable_connect_proxy
+    }
+
+    /// Check if the HTTP CONNECT proxy allows the host
+    pub fn connect_proxy_allowed(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.connect_proxy_allowlist
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{domain}")))
+    }
+
+    /// Get the next client egress for the HTTP CONNECT proxy host, the tracker holds the
+    /// in-flight permit of the tunnel
+    pub async fn connect_egress(
+        &self,
+        host: &str,
+    ) -> Result<(Egress, RequestTracker), ClientAtCapacity> {
+        let balancer = if host.contains("arkoselabs.com") {
+            current(&self.arkose_client)
+        } else if host.starts_with("auth0.") || host.starts_with("auth.") {
+            current(&self.auth_client)
+        } else {
+            current(&self.api_client)
+        };
+        balancer.next_egress().await
+    }
+
+    /// Get the admin and HAR management endpoints ip allowlist
+    pub fn admin_ip_allowlist(&self) -> &[cidr::IpCidr] {
+        &self.admin_ip_allowlist
+    }
+
+    /// Check if the ip is a trusted reverse proxy
+    pub fn is_trusted_proxy(&self, ip: &std::net::IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|cidr| cidr.contains(ip))
+    }
+
     /// Get the visitor email whitelist
     pub fn visitor_email_whitelist(&self) -> Option<&[String]> {
         self.visitor_email_whitelist.as_deref()
@@ -142,11 +623,53 @@ impl Context {
         self.arkose_gpt3_experiment_solver
     }
 
+    /// Get the response headers to strip
+    pub fn strip_response_headers(&self) -> &[HeaderName] {
+        &self.strip_response_headers
+    }
+
+    /// Get the response headers to rewrite
+    pub fn rewrite_response_headers(&self) -> &[(HeaderName, HeaderValue)] {
+        &self.rewrite_response_headers
+    }
+
+    /// Upstream url of the request, rewrite the OpenAI API base url and path prefix if configured,
+    /// the query string is preserved
+    pub fn upstream_url(&self, origin: &str, path_and_query: &str) -> String {
+        let base = match self.upstream_base.as_deref() {
+            Some(base) if origin == crate::URL_PLATFORM_API => base,
+            _ => return format!("{origin}{path_and_query}"),
+        };
+
+        let (path, query) = match path_and_query.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (path_and_query, None),
+        };
+        let path = self
+            .upstream_path_map
+            .iter()
+            .find_map(|(from, to)| {
+                path.strip_prefix(from.as_str())
+                    .map(|rest| format!("{to}{rest}"))
+            })
+            .unwrap_or_else(|| path.to_owned());
+
+        match query {
+            Some(query) => format!("{base}{path}?{query}"),
+            None => format!("{base}{path}"),
+        }
+    }
+
     /// Get the arkose context
     pub fn arkose_context(&self) -> &arkose::ArkoseVersionContext<'static> {
         &self.arkose_context
     }
 
+    /// Get the arkose solver request signing, `None` if the requests are not signed
+    pub fn arkose_solver_signing(&self) -> Option<&SolverSigning> {
+        self.arkose_solver_signing.as_ref()
+    }
+
     /// Get the arkose solver tguess endpoint, Example: https://tguess.arkoselabs.com
     pub fn arkose_solver_tguess_endpoint(&self) -> Option<&str> {
         self.arkose_solver_tguess_endpoint.as_deref()
@@ -156,4 +679,83 @@ impl Context {
     pub fn arkose_solver_image_dir(&self) -> Option<&Path> {
         self.arkose_solver_image_dir.as_deref()
     }
+
+    /// Arkose solver local work semaphore
+    pub fn solver_workers(&self) -> Option<&Semaphore> {
+        self.solver_workers.as_ref()
+    }
+
+    /// Arkose solver local work queue timeout, `None` waits without bound
+    pub fn solver_queue_timeout(&self) -> Option<Duration> {
+        self.solver_queue_timeout
+    }
+
+    /// Arkose solve single-flight of the type, `None` if the solves are not coalesced
+    pub fn solve_flight(&self, typed: crate::arkose::Type) -> Option<&SingleFlight<()>> {
+        self.solve_flights
+            .as_ref()
+            .and_then(|flights| flights.get(&typed))
+    }
+
+    /// Maximum HAR file size (bytes)
+    pub fn max_har_size(&self) -> Option<u64> {
+        self.max_har_size
+    }
+
+    /// Maximum HAR age (seconds) since the capture
+    pub fn har_max_age(&self) -> Option<u64> {
+        self.har_max_age
+    }
+
+    /// Maximum HAR capture age (seconds) accepted on upload, refresh and install
+    pub fn har_max_capture_age(&self) -> Option<u64> {
+        self.har_max_capture_age
+    }
+
+    /// Pin the HAR of the pool by the client IP
+    pub fn har_affinity(&self) -> bool {
+        self.har_affinity
+    }
+
+    /// Primary node url, `Some` if the node is a replica
+    pub fn primary_url(&self) -> Option<&str> {
+        self.primary_url.as_deref()
+    }
+
+    /// Emit the request routing decisions in the debug response headers
+    pub fn debug_headers(&self) -> bool {
+        self.debug_headers
+    }
+
+    /// Emit the phase timings in the Server-Timing response header
+    pub fn server_timing(&self) -> bool {
+        self.server_timing
+    }
+
+    /// Sticky session key source, `None` if the sticky sessions are disabled
+    pub fn sticky_key(&self) -> Option<&StickyKey> {
+        self.sticky_key.as_ref()
+    }
+
+    /// Fraction (0.0-1.0) of the requests logging the access lines
+    pub fn log_sample_rate(&self) -> f64 {
+        self.log_sample_rate
+    }
+
+    /// Slow request threshold, the slower requests log a warning
+    pub fn slow_request_threshold(&self) -> Option<Duration> {
+        self.slow_request_threshold
+    }
+}
+
+/// Get the current value of the reloadable field
+fn current<T: ?Sized>(lock: &RwLock<Arc<T>>) -> Arc<T> {
+    lock.read()
+        .expect("Failed to read the reloadable field")
+        .clone()
+}
+
+/// Replace the value of the reloadable field
+fn replace<T: ?Sized>(lock: &RwLock<Arc<T>>, value: Arc<T>) {
+    *lock.write().expect("Failed to write the reloadable field") = value;
 }
@@ -1,26 +1,100 @@
-use crate::{error, homedir::home_dir, info, now_duration};
-use moka::sync::Cache;
+use crate::{debug, error, homedir::home_dir, info, now_duration};
+use moka::{sync::Cache, Expiry};
+use serde::Serialize;
 use std::{
     path::{Path, PathBuf},
     sync::Mutex,
-    time::Duration,
+    time::{Duration, Instant},
 };
+use tokio::sync::broadcast;
 
 const SEPARATOR: &str = "---";
 const DEFAULT_MAX_AGE: u32 = 3600;
 const DEFAULT_MAX_CAPACITY: u64 = 1000;
+const CAPTURE_CHANNEL_CAPACITY: usize = 64;
 
 static LOCK: Mutex<()> = Mutex::new(());
-static mut CACHE: Option<Cache<String, String>> = None;
+static mut CACHE: Option<Cache<String, Entry>> = None;
 
-fn get_or_init_cache(max_age: Option<u32>) -> &'static Cache<String, String> {
+/// Cached preauth cookie
+#[derive(Clone)]
+struct Entry {
+    value: String,
+    /// Per-entry TTL, overrides the cache default
+    ttl: Option<Duration>,
+}
+
+/// Expire the entry after its own TTL, or the cache default
+struct EntryExpiry(Duration);
+
+impl EntryExpiry {
+    fn new(max_age: Option<u32>) -> Self {
+        Self(Duration::from_secs(
+            max_age.unwrap_or(DEFAULT_MAX_AGE).into(),
+        ))
+    }
+}
+
+impl Expiry<String, Entry> for EntryExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        entry: &Entry,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(entry.ttl.unwrap_or(self.0))
+    }
+
+    /// Overwritten entry starts a fresh TTL
+    fn expire_after_update(
+        &self,
+        _key: &String,
+        entry: &Entry,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        Some(entry.ttl.unwrap_or(self.0))
+    }
+}
+
+/// Policy of pushing a preauth cookie whose device id is already cached
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreauthPushPolicy {
+    /// Keep the cached cookie, the pushed one is dropped
+    KeepFirst,
+    /// The newest cookie wins, its TTL starts over
+    #[default]
+    Overwrite,
+}
+
+impl std::str::FromStr for PreauthPushPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep_first" => Ok(Self::KeepFirst),
+            "overwrite" => Ok(Self::Overwrite),
+            _ => anyhow::bail!("Only support `keep_first` / `overwrite` preauth push policy"),
+        }
+    }
+}
+
+impl std::fmt::Display for PreauthPushPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::KeepFirst => write!(f, "keep_first"),
+            Self::Overwrite => write!(f, "overwrite"),
+        }
+    }
+}
+
+fn get_or_init_cache(max_age: Option<u32>) -> &'static Cache<String, Entry> {
     unsafe {
         CACHE.is_none().then(|| {
             let cache = Cache::builder()
                 .max_capacity(DEFAULT_MAX_CAPACITY)
-                .time_to_live(Duration::from_secs(
-                    max_age.unwrap_or(DEFAULT_MAX_AGE).into(),
-                ))
+                .expire_after(EntryExpiry::new(max_age))
                 .build();
             CACHE = Some(cache);
         });
@@ -39,9 +113,7 @@ fn reload_cache(max_age: Option<u32>) {
 
     let new_cache = Cache::builder()
         .max_capacity(DEFAULT_MAX_CAPACITY)
-        .time_to_live(Duration::from_secs(
-            max_age.unwrap_or(DEFAULT_MAX_AGE).into(),
-        ))
+        .expire_after(EntryExpiry::new(max_age))
         .build();
 
     cache.iter().for_each(|(k, v)| {
@@ -57,13 +129,28 @@ fn reload_cache(max_age: Option<u32>) {
     }
 }
 
+/// Emitted whenever the preauth MITM captures a cookie
+#[derive(Debug, Clone, Serialize)]
+pub struct PreauthCapture {
+    /// Device id the cookie belongs to
+    pub key: String,
+    /// Capture timestamp (unix seconds)
+    pub timestamp: u64,
+    /// Cookie value, only present when explicitly allowed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
 pub(super) struct PreauthCookieProvider {
     path: PathBuf,
     max_age: Option<u32>,
+    events: broadcast::Sender<PreauthCapture>,
+    event_value: bool,
+    push_policy: PreauthPushPolicy,
 }
 
 impl PreauthCookieProvider {
-    pub fn new() -> Self {
+    pub fn new(event_value: bool, push_policy: PreauthPushPolicy) -> Self {
         let path = home_dir()
             .unwrap_or(PathBuf::from("."))
             .join(".preauth_cookies");
@@ -81,19 +168,23 @@ impl PreauthCookieProvider {
         let mut provider = PreauthCookieProvider {
             path,
             max_age: None,
+            events: broadcast::channel(CAPTURE_CHANNEL_CAPACITY).0,
+            event_value,
+            push_policy,
         };
 
         // Load from file
         data.into_iter().for_each(|value| {
             // split by `---`, example: `max_age---device_id:timestamp-xxxx`
+            // or with the per-entry TTL: `max_age---device_id:timestamp-xxxx---ttl`
             let group = value
                 .split(SEPARATOR)
                 .into_iter()
                 .filter(|s| !s.is_empty())
                 .collect::<Vec<&str>>();
 
-            // If group length is not 2, skip
-            if group.len() != 2 {
+            // If group length is not 2 or 3, skip
+            if group.len() != 2 && group.len() != 3 {
                 return;
             }
 
@@ -101,6 +192,11 @@ impl PreauthCookieProvider {
             let max_age = group[0].parse::<u32>().unwrap_or(0);
             // Parse value
             let value = group[1];
+            // Parse per-entry TTL
+            let ttl = group
+                .get(2)
+                .and_then(|ttl| ttl.parse::<u64>().ok())
+                .map(Duration::from_secs);
 
             provider.max_age = Some(max_age);
 
@@ -109,7 +205,13 @@ impl PreauthCookieProvider {
                 // If is invalid, skip
                 if !Self::is_invalid(value, Some(max_age)) {
                     info!("Loading preauth cookie value: {value}",);
-                    get_or_init_cache(Some(max_age)).insert(device_id.to_owned(), value.to_owned())
+                    get_or_init_cache(Some(max_age)).insert(
+                        device_id.to_owned(),
+                        Entry {
+                            value: value.to_owned(),
+                            ttl,
+                        },
+                    )
                 }
             });
         });
@@ -117,14 +219,45 @@ impl PreauthCookieProvider {
         provider
     }
 
-    /// Push a preauth cookie
+    /// Push a preauth cookie, `ttl` overrides the cache default of the entry,
+    /// a cached device id is kept or overwritten by the push policy
     /// Example: `id1:1704031809-xxx`
-    pub fn push(&self, value: &str, max_age: Option<u32>) {
+    pub fn push(&self, value: &str, max_age: Option<u32>, ttl: Option<Duration>) {
         value.find(":").map(|colon_index| {
             let device_id = &value[..colon_index];
+            let cache = get_or_init_cache(max_age);
+            if self.push_policy == PreauthPushPolicy::KeepFirst && cache.contains_key(device_id) {
+                debug!("Keep the cached PreAuth Cookie of {device_id}, drop: {value}");
+                return;
+            }
             info!("Push PreAuth Cookie: {value}");
-            get_or_init_cache(max_age).insert(device_id.to_owned(), value.to_owned());
+            cache.insert(
+                device_id.to_owned(),
+                Entry {
+                    value: value.to_owned(),
+                    ttl,
+                },
+            );
             self.sync_to_file(&self.path, max_age);
+            self.emit(device_id, value);
+        });
+    }
+
+    /// Subscribe to capture events
+    pub fn subscribe(&self) -> broadcast::Receiver<PreauthCapture> {
+        self.events.subscribe()
+    }
+
+    /// Notify subscribers, sending fails only when nobody is listening
+    fn emit(&self, device_id: &str, value: &str) {
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+        let timestamp = now_duration().map(|d| d.as_secs()).unwrap_or_default();
+        let _ = self.events.send(PreauthCapture {
+            key: device_id.to_owned(),
+            timestamp,
+            value: self.event_value.then(|| value.to_owned()),
         });
     }
 
@@ -132,16 +265,23 @@ impl PreauthCookieProvider {
     /// Example: `id1:1704031809-xxx`
     pub fn get(&self) -> Option<String> {
         use rand::seq::IteratorRandom;
-        if let Some((_, v)) = get_or_init_cache(self.max_age)
+        if let Some((_, entry)) = get_or_init_cache(self.max_age)
             .iter()
-            .filter(|(_, input)| Self::is_invalid(input, self.max_age))
+            .filter(|(_, entry)| Self::is_invalid(&entry.value, self.max_age))
             .choose(&mut rand::thread_rng())
         {
-            return Some(v);
+            return Some(entry.value);
         }
         None
     }
 
+    /// Number of cached preauth cookies
+    pub fn size(&self) -> u64 {
+        let cache = get_or_init_cache(self.max_age);
+        cache.run_pending_tasks();
+        cache.entry_count()
+    }
+
     /// Check if is invalid
     fn is_invalid(input: &str, max_age: Option<u32>) -> bool {
         let parts: Vec<&str> = input.split(':').collect();
@@ -173,7 +313,17 @@ impl PreauthCookieProvider {
 
         let data = get_or_init_cache(max_age)
             .iter()
-            .map(|(_, v)| format!("{}{SEPARATOR}{v}", max_age.unwrap_or(DEFAULT_MAX_AGE)))
+            .map(|(_, entry)| {
+                let max_age = max_age.unwrap_or(DEFAULT_MAX_AGE);
+                match entry.ttl {
+                    Some(ttl) => format!(
+                        "{max_age}{SEPARATOR}{}{SEPARATOR}{}",
+                        entry.value,
+                        ttl.as_secs()
+                    ),
+                    None => format!("{max_age}{SEPARATOR}{}", entry.value),
+                }
+            })
             .collect::<Vec<String>>()
             .join("\n");
         let _ = std::fs::write(path.as_ref(), data).map_err(|err| {
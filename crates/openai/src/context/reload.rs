@@ -0,0 +1,60 @@
+use crate::now_duration;
+use serde::Serialize;
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// Trigger source of a reload
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReloadSource {
+    /// Store change watched by hotwatch
+    Hotwatch,
+    /// SIGUSR1
+    Signal,
+    /// Admin/HAR API or the embedder
+    Api,
+}
+
+/// Result of the last reload of a subsystem
+#[derive(Clone, Debug, Serialize)]
+pub struct ReloadResult {
+    /// Reload time (unix seconds)
+    pub timestamp: u64,
+    pub source: ReloadSource,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Last reload results of each subsystem, e.g. `proxies`, `solver`, `har:gpt4`
+#[derive(Default)]
+pub struct ReloadStatus {
+    subsystems: Mutex<BTreeMap<String, ReloadResult>>,
+}
+
+impl ReloadStatus {
+    /// Record the reload result of the subsystem
+    pub fn record<T, E: std::fmt::Display>(
+        &self,
+        subsystem: impl Into<String>,
+        source: ReloadSource,
+        result: &Result<T, E>,
+    ) {
+        let result = ReloadResult {
+            timestamp: now_duration().map(|d| d.as_secs()).unwrap_or_default(),
+            source,
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|err| err.to_string()),
+        };
+        if let Ok(mut subsystems) = self.subsystems.lock() {
+            subsystems.insert(subsystem.into(), result);
+        }
+    }
+
+    /// Last reload results of each subsystem
+    pub fn subsystems(&self) -> BTreeMap<String, ReloadResult> {
+        self.subsystems
+            .lock()
+            .map(|subsystems| subsystems.clone())
+            .unwrap_or_default()
+    }
+}
@@ -51,6 +51,13 @@ impl Proxy {
             Proxy::Arkose(_) => "Arkose",
         }
     }
+
+    /// Inner proxy regardless of the protocol
+    pub fn into_inner(self) -> InnerProxy {
+        match self {
+            Proxy::All(v) | Proxy::Api(v) | Proxy::Auth(v) | Proxy::Arkose(v) => v,
+        }
+    }
 }
 
 const UNSUPPORTED_PROTOCOL: &str = "Unsupported protocol";
@@ -0,0 +1,128 @@
+use crate::client::ClientRoundRobinBalancer;
+use crate::context::args::Args;
+use futures::StreamExt;
+use std::time::{Duration, Instant};
+
+/// Per-client bench stat
+#[derive(Default)]
+struct Stat {
+    success: usize,
+    failure: usize,
+    latencies: Vec<Duration>,
+}
+
+impl Stat {
+    /// Latency percentile of the successful requests
+    fn percentile(&self, p: usize) -> Option<Duration> {
+        (!self.latencies.is_empty()).then(|| {
+            let index = (self.latencies.len() * p / 100).min(self.latencies.len() - 1);
+            self.latencies[index]
+        })
+    }
+}
+
+/// Benchmark the client proxy pool, send the test requests through the balancer round-robin,
+/// then report the per-proxy success rate and latency percentiles.
+pub struct Bench {
+    args: Args,
+    /// Client type (api/auth/arkose)
+    kind: String,
+    url: String,
+    requests: usize,
+    concurrency: usize,
+}
+
+impl Bench {
+    pub fn new(args: Args, kind: String, url: String, requests: usize, concurrency: usize) -> Self {
+        Self {
+            args,
+            kind,
+            url,
+            requests,
+            concurrency,
+        }
+    }
+
+    #[tokio::main]
+    pub async fn run(self) -> anyhow::Result<()> {
+        let balancer = match self.kind.as_str() {
+            "api" => ClientRoundRobinBalancer::new_client(&self.args)?,
+            "auth" => ClientRoundRobinBalancer::new_auth_client(&self.args)?,
+            "arkose" => ClientRoundRobinBalancer::new_arkose_client(&self.args)?,
+            kind => anyhow::bail!("Unknown client type: {kind}"),
+        };
+
+        let size = balancer.pool_size();
+        if size == 0 {
+            anyhow::bail!("No {} client available", self.kind);
+        }
+        println!(
+            "Bench {} {} client(s), {} request(s), concurrency {}: {}",
+            self.kind, size, self.requests, self.concurrency, self.url
+        );
+
+        let start = Instant::now();
+        let results = futures::stream::iter(0..self.requests)
+            .map(|n| {
+                let balancer = &balancer;
+                let url = self.url.as_str();
+                async move { (n % size, balancer.request_at(n % size, url).await) }
+            })
+            .buffer_unordered(self.concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+        let elapsed = start.elapsed();
+
+        let mut stats = (0..size).map(|_| Stat::default()).collect::<Vec<_>>();
+        for (index, result) in results {
+            let stat = &mut stats[index];
+            match result {
+                // 5xx is an upstream or proxy failure
+                Ok((status, latency)) if !status.is_server_error() => {
+                    stat.success += 1;
+                    stat.latencies.push(latency);
+                }
+                _ => stat.failure += 1,
+            }
+        }
+
+        let ms = |d: Option<Duration>| {
+            d.map(|d| d.as_millis().to_string())
+                .unwrap_or_else(|| "-".to_owned())
+        };
+
+        println!(
+            "{:<6} {:<40} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            "index", "egress", "success", "rate", "p50(ms)", "p90(ms)", "p99(ms)"
+        );
+        for (index, stat) in stats.iter_mut().enumerate() {
+            stat.latencies.sort();
+            let total = stat.success + stat.failure;
+            let rate = if total > 0 {
+                stat.success as f64 / total as f64 * 100.0
+            } else {
+                0.0
+            };
+            println!(
+                "{:<6} {:<40} {:>8} {:>7.1}% {:>8} {:>8} {:>8}",
+                index,
+                balancer.egress_at(index).unwrap_or("direct"),
+                format!("{}/{total}", stat.success),
+                rate,
+                ms(stat.percentile(50)),
+                ms(stat.percentile(90)),
+                ms(stat.percentile(99)),
+            );
+        }
+
+        let success = stats.iter().map(|s| s.success).sum::<usize>();
+        println!(
+            "Total {success}/{} succeeded in {:.2}s, {:.1} req/s",
+            self.requests,
+            elapsed.as_secs_f64(),
+            self.requests as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+        );
+
+        Ok(())
+    }
+}
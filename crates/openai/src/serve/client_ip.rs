@@ -0,0 +1,39 @@
+use crate::with_context;
+use axum::http::HeaderMap;
+use std::net::IpAddr;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_REAL_IP: &str = "x-real-ip";
+
+/// Get the real client ip, the forwarded headers are only trusted when the peer is a trusted proxy
+pub(crate) fn client_ip(peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let ctx = with_context!();
+    if !ctx.is_trusted_proxy(&peer) {
+        return peer;
+    }
+
+    // Walk the X-Forwarded-For from right to left, the first untrusted address is the client
+    let forwarded = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+        .collect::<Vec<_>>();
+
+    if let Some(ip) = forwarded
+        .iter()
+        .rev()
+        .find(|ip| !ctx.is_trusted_proxy(ip))
+        .or(forwarded.first())
+    {
+        return *ip;
+    }
+
+    // Fallback to the X-Real-IP
+    headers
+        .get(X_REAL_IP)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<IpAddr>().ok())
+        .unwrap_or(peer)
+}
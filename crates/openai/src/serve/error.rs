@@ -1,4 +1,6 @@
+use crate::arkose::error::ArkoseError;
 use crate::auth::error::AuthError;
+use crate::client::ClientAtCapacity;
 use axum::http::header::{CONTENT_TYPE, LOCATION};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
@@ -16,6 +18,8 @@ pub enum ProxyError {
     AccessTokenRequired,
     #[error("Model required")]
     ModelRequired,
+    #[error("Model `{0}` maps to no arkose type")]
+    UnknownArkoseModel(String),
     #[error("Body required")]
     BodyRequired,
     #[error("Body must be a json object")]
@@ -34,10 +38,34 @@ pub enum ProxyError {
     InvalidUploadField,
     #[error("Too Many Requests")]
     TooManyRequests,
+    #[error("Request pipeline is full")]
+    PipelineFull,
+    #[error("Too many connections from the client")]
+    TooManyConnections,
+    #[error("Server is draining")]
+    Draining,
+    #[error("Request queue is full")]
+    QueueFull,
+    #[error("Request queue wait timed out")]
+    QueueTimeout,
+    #[error("Request exceeded the SLO deadline")]
+    SloExceeded,
+    #[error("Request budget exhausted")]
+    BudgetExhausted,
+    #[error("Upstream response body exceeds {0} bytes")]
+    UpstreamBodyTooLarge(u64),
+    #[error("Upstream is failing, retry in {0}s")]
+    UpstreamCoolingDown(u64),
     #[error("Your access is not in the whitelist")]
     AccessNotInWhitelist,
+    #[error("Your IP is not allowed")]
+    IpNotAllowed,
+    #[error("Egress IP `{0}` is not a configured interface")]
+    InvalidEgressIp(String),
     #[error("Auth Key required!")]
     AuthKeyRequired,
+    #[error("HAR upload key is not allowed for the type")]
+    HarUploadKeyTypeMismatch,
     #[error("Event-source stream error ({0})")]
     EventSourceStreamError(EventStreamError<reqwest::Error>),
     #[error("Deserialize error ({0})")]
@@ -54,6 +82,8 @@ pub enum ProxyError {
     CfMissingCaptcha,
     #[error("Cloudflare error ({0})")]
     CfError(reqwest::Error),
+    #[error("Cloudflare Turnstile verification is unavailable after {0} attempts, please try again later")]
+    CfVerifyUnavailable(u32),
 
     /// Request error
     #[error("Request error ({0})")]
@@ -118,6 +148,11 @@ where
             path: None,
         };
 
+        // Every client is at capacity, possibly wrapped by the auth and arkose errors
+        if err.chain().any(|err| err.is::<ClientAtCapacity>()) {
+            return make_error(StatusCode::TOO_MANY_REQUESTS);
+        }
+
         // Try to downcast the error to our own AuthError type.
         if let Some(auth_error) = err.downcast_ref::<AuthError>() {
             return match auth_error {
@@ -146,6 +181,11 @@ where
             };
         }
 
+        // Missing arkose solver
+        if let Some(ArkoseError::SolverRequired(_)) = err.downcast_ref::<ArkoseError>() {
+            return make_error(StatusCode::SERVICE_UNAVAILABLE);
+        }
+
         // default 500
         make_error(StatusCode::INTERNAL_SERVER_ERROR)
     }
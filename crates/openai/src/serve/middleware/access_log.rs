@@ -0,0 +1,72 @@
+use crate::context::decision::{self, Decisions};
+use crate::with_context;
+use axum::{http::Request, middleware::Next, response::Response};
+use std::time::{Duration, Instant};
+
+/// Log the access lines of the sampled requests, the client error responses are always logged,
+/// the server errors are logged by the trace layer on failure
+pub(crate) async fn access_log_middleware<B>(request: Request<B>, next: Next<B>) -> Response {
+    let sampled = sampled(with_context!(log_sample_rate));
+    if sampled {
+        tracing::info!("started processing request");
+    }
+
+    let start = Instant::now();
+    let slow_threshold = with_context!(slow_request_threshold);
+    let (response, decisions) = match slow_threshold {
+        Some(_) => {
+            let (response, decisions) = decision::scope(next.run(request)).await;
+            (response, Some(decisions))
+        }
+        None => (next.run(request).await, None),
+    };
+    let elapsed = start.elapsed();
+    let latency = elapsed.as_millis();
+    let status = response.status();
+
+    if let (Some(threshold), Some(decisions)) = (slow_threshold, decisions) {
+        if elapsed >= threshold {
+            log_slow(elapsed, status.as_u16(), &decisions);
+        }
+    }
+
+    if status.is_client_error() {
+        tracing::warn!(latency = %format!("{latency} ms"), status = status.as_u16(), "finished processing request");
+    } else if sampled {
+        tracing::info!(latency = %format!("{latency} ms"), status = status.as_u16(), "finished processing request");
+    }
+    response
+}
+
+/// Log the slow request with the phase breakdown and the proxy index
+fn log_slow(total: Duration, status: u16, decisions: &Decisions) {
+    tracing::warn!(
+        total = %format!("{} ms", total.as_millis()),
+        solve = %format!("{} ms", decisions.solve.as_millis()),
+        upstream = %format!("{} ms", decisions.upstream.as_millis()),
+        proxy_index = ?decisions.proxy_index,
+        status,
+        "slow request"
+    );
+}
+
+/// Cheap RNG check of the sample rate (0.0-1.0)
+fn sampled(rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    rate > 0.0 && rand::random::<f64>() < rate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sampled;
+
+    #[test]
+    fn test_sampled() {
+        assert!((0..100).all(|_| sampled(1.0)));
+        assert!((0..100).all(|_| !sampled(0.0)));
+        let hits = (0..10_000).filter(|_| sampled(0.5)).count();
+        assert!((4_000..6_000).contains(&hits));
+    }
+}
@@ -0,0 +1,24 @@
+use crate::serve::client_ip::client_ip;
+use crate::serve::error::{ProxyError, ResponseError};
+use crate::{warn, with_context};
+use axum::{extract::ConnectInfo, http::Request, middleware::Next, response::Response};
+
+/// Restrict the admin and HAR management endpoints to the allowlisted ips, evaluated before auth
+pub(crate) async fn admin_ip_middleware<B>(
+    ConnectInfo(socket_addr): ConnectInfo<std::net::SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, ResponseError> {
+    let allowlist = with_context!(admin_ip_allowlist);
+    if allowlist.is_empty() {
+        return Ok(next.run(request).await);
+    }
+
+    let ip = client_ip(socket_addr.ip(), request.headers());
+    if allowlist.iter().any(|cidr| cidr.contains(&ip)) {
+        return Ok(next.run(request).await);
+    }
+
+    warn!("Admin access denied: {ip} {}", request.uri().path());
+    Err(ResponseError::Forbidden(ProxyError::IpNotAllowed))
+}
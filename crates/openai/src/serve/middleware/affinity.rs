@@ -0,0 +1,19 @@
+use crate::context::arkose::har;
+use crate::serve::client_ip::client_ip;
+use crate::with_context;
+use axum::{extract::ConnectInfo, http::Request, middleware::Next, response::Response};
+use std::net::SocketAddr;
+
+/// Pin the HAR of the arkose pool by the client ip, only if the HAR affinity is enabled
+pub(crate) async fn har_affinity_middleware<B>(
+    ConnectInfo(socket_addr): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !with_context!(har_affinity) {
+        return next.run(request).await;
+    }
+
+    let ip = client_ip(socket_addr.ip(), request.headers());
+    har::affinity_scope(ip, next.run(request)).await
+}
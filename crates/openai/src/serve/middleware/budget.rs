@@ -0,0 +1,76 @@
+use crate::context::{decision, event::Event, metrics::Latency};
+use crate::serve::error::{ProxyError, ResponseError};
+use crate::{warn, with_context};
+use axum::{
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+tokio::task_local! {
+    /// Deadline of the current request budget
+    static DEADLINE: Instant;
+}
+
+/// Cap the whole request lifetime (arkose solve + upstream) with the request budget,
+/// the steps run within the remaining budget, respond 504 once exhausted
+pub(crate) async fn budget_middleware<B>(request: Request<B>, next: Next<B>) -> Response {
+    let budget = match with_context!(request_budget) {
+        Some(budget) => budget,
+        None => return next.run(request).await,
+    };
+
+    let uri = request.uri().clone();
+    let deadline = Instant::now() + budget;
+    match tokio::time::timeout(budget, DEADLINE.scope(deadline, next.run(request))).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!(
+                "Request {uri} exhausted the budget ({}ms)",
+                budget.as_millis()
+            );
+            ResponseError::GatewayTimeout(ProxyError::BudgetExhausted).into_response()
+        }
+    }
+}
+
+/// Remaining budget of the current request, `None` if the request has no budget
+pub(crate) fn remaining() -> Option<Duration> {
+    DEADLINE
+        .try_with(|deadline| deadline.saturating_duration_since(Instant::now()))
+        .ok()
+}
+
+/// Run a request step within the remaining budget, the step latency is recorded to the metrics
+/// and emitted to the lifecycle event callback
+pub(crate) async fn step<F: Future>(step: Latency, fut: F) -> Result<F::Output, ResponseError> {
+    let ctx = with_context!();
+    if let Latency::Solve = step {
+        ctx.emit(|| Event::SolveStarted);
+    }
+
+    let start = Instant::now();
+    let output = match remaining() {
+        Some(remaining) => tokio::time::timeout(remaining, fut).await.map_err(|_| {
+            warn!("Request budget exhausted during {} step", step.name());
+            ResponseError::GatewayTimeout(ProxyError::BudgetExhausted)
+        }),
+        None => Ok(fut.await),
+    };
+    let elapsed = start.elapsed();
+    ctx.metrics().record_latency(step, elapsed);
+    match step {
+        Latency::Solve => {
+            decision::record(|d| d.solve += elapsed);
+            ctx.emit(|| Event::SolveFinished { elapsed })
+        }
+        Latency::Upstream => {
+            decision::record(|d| d.upstream += elapsed);
+            ctx.emit(|| Event::UpstreamResponse { elapsed })
+        }
+        Latency::Total | Latency::SolverWorkQueue => {}
+    }
+    output
+}
@@ -0,0 +1,281 @@
+use super::retry_after::set_retry_after;
+use crate::serve::client_ip::client_ip;
+use crate::serve::error::{ProxyError, ResponseError};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, HeaderValue, Request, Version},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::{future::MapOk, TryFutureExt};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, OnceLock},
+    task::{Context, Poll},
+};
+use tower::Service;
+
+/// Retry-After (seconds) when the client ip is over the connection limit
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// Active connections per client ip
+pub(crate) struct ConnLimiter {
+    /// Maximum active connections per client ip, `None` is unlimited
+    max: Option<usize>,
+    active: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl ConnLimiter {
+    pub(crate) fn new(max: Option<usize>) -> Self {
+        Self {
+            max,
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Acquire a connection of the client ip, `None` if over the limit
+    fn acquire(self: &Arc<Self>, ip: IpAddr, max: usize) -> Option<ConnGuard> {
+        let mut active = self
+            .active
+            .lock()
+            .expect("Failed to get active connections");
+        let count = active.entry(ip).or_default();
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(ConnGuard {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    /// Count the connection against the client ip once, on its first request, `false` if over the limit
+    fn admit(self: &Arc<Self>, connection: &Connection, ip: IpAddr, max: usize) -> bool {
+        if connection.guard.get().is_some() {
+            return true;
+        }
+        match self.acquire(ip, max) {
+            Some(guard) => {
+                // A concurrent request of the connection won the slot, this guard is released
+                let _ = connection.guard.set(guard);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Active connection of the client ip, released on drop
+struct ConnGuard {
+    limiter: Arc<ConnLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        if let Ok(mut active) = self.limiter.active.lock() {
+            if let Some(count) = active.get_mut(&self.ip) {
+                *count -= 1;
+                if *count == 0 {
+                    active.remove(&self.ip);
+                }
+            }
+        }
+    }
+}
+
+/// Accepted connection, shared by the request extensions of the connection and dropped
+/// when the connection is closed and its last request is done
+#[derive(Default)]
+pub(crate) struct Connection {
+    guard: OnceLock<ConnGuard>,
+}
+
+/// Make service attaching the [`Connection`] to the requests of each accepted connection
+#[derive(Clone)]
+pub(crate) struct TrackConnections<M>(pub(crate) M);
+
+impl<M, T> Service<T> for TrackConnections<M>
+where
+    M: Service<T>,
+{
+    type Response = TrackedConnection<M::Response>;
+    type Error = M::Error;
+    type Future = MapOk<M::Future, fn(M::Response) -> TrackedConnection<M::Response>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        self.0.call(target).map_ok(TrackedConnection::new)
+    }
+}
+
+/// Service of an accepted connection, lives as long as the connection
+#[derive(Clone)]
+pub(crate) struct TrackedConnection<S> {
+    inner: S,
+    connection: Arc<Connection>,
+}
+
+impl<S> TrackedConnection<S> {
+    fn new(inner: S) -> Self {
+        Self {
+            inner,
+            connection: Arc::default(),
+        }
+    }
+}
+
+impl<S, B> Service<Request<B>> for TrackedConnection<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        request.extensions_mut().insert(self.connection.clone());
+        self.inner.call(request)
+    }
+}
+
+/// Limit the active connections per client ip, respond 429 and close the connection when over the limit.
+/// The connection is counted against the client ip (the real ip behind the trusted proxies) of its
+/// first request, until the connection is closed
+pub(crate) async fn conn_limit_middleware<B>(
+    State(limiter): State<Arc<ConnLimiter>>,
+    ConnectInfo(socket_addr): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let max = match limiter.max {
+        Some(max) => max,
+        None => return next.run(request).await,
+    };
+
+    let connection = match request.extensions().get::<Arc<Connection>>() {
+        Some(connection) => connection.clone(),
+        None => return next.run(request).await,
+    };
+
+    let ip = client_ip(socket_addr.ip(), request.headers());
+    if !limiter.admit(&connection, ip, max) {
+        let mut response =
+            ResponseError::TooManyRequests(ProxyError::TooManyConnections).into_response();
+        set_retry_after(&mut response, RETRY_AFTER_SECS);
+        // The connection is not accepted, HTTP/2 has no connection header
+        if request.version() < Version::HTTP_2 {
+            response
+                .headers_mut()
+                .insert(header::CONNECTION, HeaderValue::from_static("close"));
+        }
+        return response;
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnLimiter, Connection, TrackConnections};
+    use axum::{
+        extract::ConnectInfo, http::StatusCode, response::IntoResponse, routing::get, Extension,
+        Router,
+    };
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::sync::Arc;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    #[test]
+    fn test_conn_limiter() {
+        let limiter = Arc::new(ConnLimiter::new(Some(2)));
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        let first = limiter.acquire(ip, 2).unwrap();
+        let _second = limiter.acquire(ip, 2).unwrap();
+        assert!(limiter.acquire(ip, 2).is_none());
+        assert!(limiter.acquire(other, 2).is_some());
+
+        drop(first);
+        assert!(limiter.acquire(ip, 2).is_some());
+    }
+
+    /// Serve the test router admitting the connections of the peer ip, return the bound address
+    async fn serve(limiter: Arc<ConnLimiter>) -> SocketAddr {
+        let router = Router::new().route(
+            "/",
+            get(
+                move |Extension(connection): Extension<Arc<Connection>>,
+                      ConnectInfo(addr): ConnectInfo<SocketAddr>| {
+                    let admitted = limiter.admit(&connection, addr.ip(), 1);
+                    async move {
+                        match admitted {
+                            true => "ok".into_response(),
+                            false => StatusCode::TOO_MANY_REQUESTS.into_response(),
+                        }
+                    }
+                },
+            ),
+        );
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(TrackConnections(
+                    router.into_make_service_with_connect_info::<SocketAddr>(),
+                )),
+        );
+        addr
+    }
+
+    /// Send a keep-alive request on the connection, return the status line
+    async fn request(stream: &mut TcpStream) -> String {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = vec![0; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        String::from_utf8_lossy(&buf[..n])
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_owned()
+    }
+
+    #[tokio::test]
+    async fn test_conn_limit_counts_connections() {
+        let limiter = Arc::new(ConnLimiter::new(Some(1)));
+        let addr = serve(limiter.clone()).await;
+
+        // The requests of the same connection are counted once
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        assert!(request(&mut first).await.contains("200"));
+        assert!(request(&mut first).await.contains("200"));
+
+        // The idle keep-alive connection still holds the slot
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        assert!(request(&mut second).await.contains("429"));
+
+        // The slot is released when the connection is closed
+        drop(first);
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let mut third = TcpStream::connect(addr).await.unwrap();
+        assert!(request(&mut third).await.contains("200"));
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{client::Egress, debug, warn, with_context};
+use anyhow::{anyhow, bail};
+use axum::{
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose, Engine};
+use std::{net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpSocket, TcpStream},
+};
+use url::Url;
+
+/// Upstream tunnel connect timeout
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// HTTP CONNECT proxy, tunnel to the allowlisted hosts through the client egress
+pub(crate) async fn connect_middleware<B: Send + 'static>(
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if request.method() != Method::CONNECT || !with_context!(enable_connect_proxy) {
+        return next.run(request).await;
+    }
+
+    if !authorized(request.headers()) {
+        return (
+            StatusCode::PROXY_AUTHENTICATION_REQUIRED,
+            [(header::PROXY_AUTHENTICATE, "Basic realm=\"ninja\"")],
+        )
+            .into_response();
+    }
+
+    let (host, port) = match request.uri().authority() {
+        Some(authority) => (
+            authority.host().to_owned(),
+            authority.port_u16().unwrap_or(443),
+        ),
+        None => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    if !with_context!().connect_proxy_allowed(&host) {
+        warn!("CONNECT proxy host not allowed: {host}");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    // The tracker holds the client permit for the life of the tunnel
+    let (egress, mut tracker) = match with_context!().connect_egress(&host).await {
+        Ok(egress) => egress,
+        Err(err) => {
+            warn!("CONNECT proxy {host}:{port} error: {err}");
+            return StatusCode::TOO_MANY_REQUESTS.into_response();
+        }
+    };
+    let mut upstream =
+        match tokio::time::timeout(CONNECT_TIMEOUT, connect(&egress, &host, port)).await {
+            Ok(Ok(upstream)) => upstream,
+            Ok(Err(err)) => {
+                warn!("CONNECT proxy {host}:{port} error: {err}");
+                return StatusCode::BAD_GATEWAY.into_response();
+            }
+            Err(_) => {
+                warn!("CONNECT proxy {host}:{port} timeout");
+                return StatusCode::GATEWAY_TIMEOUT.into_response();
+            }
+        };
+    tracker.release_connect_permit();
+
+    tokio::spawn(async move {
+        match mitm::proxy::hyper::upgrade::on(request).await {
+            Ok(mut upgraded) => {
+                if let Some(err) = tokio::io::copy_bidirectional(&mut upgraded, &mut upstream)
+                    .await
+                    .err()
+                {
+                    debug!("CONNECT proxy {host}:{port} tunnel closed: {err}");
+                }
+            }
+            Err(err) => warn!("CONNECT proxy upgrade error: {err}"),
+        }
+        drop(tracker);
+    });
+
+    StatusCode::OK.into_response()
+}
+
+/// Check the proxy authorization against the auth key, support Bearer and Basic (password as the key).
+/// Without the auth key nothing is authorized
+fn authorized(headers: &HeaderMap) -> bool {
+    let auth_key = match with_context!(auth_key) {
+        Some(auth_key) => auth_key,
+        None => return false,
+    };
+
+    let value = match headers
+        .get(header::PROXY_AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(value) => value,
+        None => return false,
+    };
+
+    if let Some(token) = value.strip_prefix("Bearer ") {
+        return token.trim() == auth_key;
+    }
+
+    value
+        .strip_prefix("Basic ")
+        .and_then(|credentials| general_purpose::STANDARD.decode(credentials.trim()).ok())
+        .and_then(|credentials| String::from_utf8(credentials).ok())
+        .map(|credentials| {
+            credentials
+                .split_once(':')
+                .map(|(_, password)| password == auth_key)
+                .unwrap_or(false)
+        })
+        .unwrap_or(false)
+}
+
+/// Connect to the target through the egress
+async fn connect(egress: &Egress, host: &str, port: u16) -> anyhow::Result<TcpStream> {
+    match egress {
+        Egress::Direct(bind) => connect_direct(*bind, host, port).await,
+        Egress::Proxy(url) => match url.scheme() {
+            "http" => connect_http_proxy(url, host, port).await,
+            "socks5" | "socks5h" => connect_socks5_proxy(url, host, port).await,
+            scheme => bail!("Unsupported tunnel proxy scheme: {scheme}"),
+        },
+    }
+}
+
+async fn connect_direct(
+    bind: Option<std::net::IpAddr>,
+    host: &str,
+    port: u16,
+) -> anyhow::Result<TcpStream> {
+    let mut last_err = None;
+    for addr in tokio::net::lookup_host((host, port)).await? {
+        let result = match bind {
+            // skip the address family mismatch
+            Some(bind) if bind.is_ipv4() != addr.is_ipv4() => continue,
+            Some(bind) => {
+                let socket = if addr.is_ipv4() {
+                    TcpSocket::new_v4()?
+                } else {
+                    TcpSocket::new_v6()?
+                };
+                socket.bind(SocketAddr::new(bind, 0))?;
+                socket.connect(addr).await
+            }
+            None => TcpStream::connect(addr).await,
+        };
+
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err
+        .map(Into::into)
+        .unwrap_or_else(|| anyhow!("No address available for {host}:{port}")))
+}
+
+async fn connect_proxy_server(url: &Url, default_port: u16) -> anyhow::Result<TcpStream> {
+    let proxy_host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("Invalid proxy url: {url}"))?;
+    let proxy_port = url.port_or_known_default().unwrap_or(default_port);
+    Ok(TcpStream::connect((proxy_host, proxy_port)).await?)
+}
+
+async fn connect_http_proxy(url: &Url, host: &str, port: u16) -> anyhow::Result<TcpStream> {
+    let mut stream = connect_proxy_server(url, 80).await?;
+
+    let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+    if !url.username().is_empty() {
+        let credentials = format!("{}:{}", url.username(), url.password().unwrap_or_default());
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            general_purpose::STANDARD.encode(credentials)
+        ));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read the response head byte by byte, the tunnel data must not be consumed
+    let mut head = Vec::with_capacity(256);
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).await? == 0 {
+            bail!("Proxy closed the connection");
+        }
+        head.push(byte[0]);
+        if head.len() > 8192 {
+            bail!("Proxy response head too large");
+        }
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let status_line = head.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        bail!("Proxy CONNECT failed: {status_line}");
+    }
+
+    Ok(stream)
+}
+
+async fn connect_socks5_proxy(url: &Url, host: &str, port: u16) -> anyhow::Result<TcpStream> {
+    let mut stream = connect_proxy_server(url, 1080).await?;
+
+    // Greeting, no authentication or username/password
+    let auth = !url.username().is_empty();
+    let method = if auth { 0x02 } else { 0x00 };
+    stream.write_all(&[0x05, 0x01, method]).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    match reply {
+        [0x05, 0x00] => {}
+        [0x05, 0x02] if auth => {
+            let username = url.username().as_bytes();
+            let password = url.password().unwrap_or_default().as_bytes();
+            let username_len = u8::try_from(username.len())
+                .map_err(|_| anyhow!("SOCKS5 username is longer than 255 bytes"))?;
+            let password_len = u8::try_from(password.len())
+                .map_err(|_| anyhow!("SOCKS5 password is longer than 255 bytes"))?;
+            let mut request = vec![0x01, username_len];
+            request.extend_from_slice(username);
+            request.push(password_len);
+            request.extend_from_slice(password);
+            stream.write_all(&request).await?;
+
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0x00 {
+                bail!("SOCKS5 authentication failed");
+            }
+        }
+        _ => bail!("SOCKS5 handshake failed"),
+    }
+
+    // Connect request with the domain name
+    let host_len = u8::try_from(host.len())
+        .map_err(|_| anyhow!("SOCKS5 host {host} is longer than 255 bytes"))?;
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, host_len];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    if head[1] != 0x00 {
+        bail!("SOCKS5 connect failed: {}", head[1]);
+    }
+
+    // Skip the bound address and port
+    let len = match head[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            len[0] as usize
+        }
+        _ => bail!("SOCKS5 invalid address type"),
+    };
+    let mut bound = vec![0u8; len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(stream)
+}
@@ -0,0 +1,119 @@
+use axum::{
+    http::{header, HeaderMap, HeaderValue, Request, Version},
+    middleware::Next,
+    response::Response,
+};
+
+/// Honor the client connection semantics, an HTTP/1.0 client without `Connection: keep-alive`
+/// or any client sending `Connection: close` gets `Connection: close` and the connection is closed
+/// after the response, streaming bodies are delimited by the connection close
+pub(crate) async fn connection_middleware<B>(request: Request<B>, next: Next<B>) -> Response {
+    let close = wants_close(request.version(), request.headers());
+    let mut response = next.run(request).await;
+    if close {
+        response
+            .headers_mut()
+            .insert(header::CONNECTION, HeaderValue::from_static("close"));
+    }
+    response
+}
+
+/// Check if the client wants the connection closed after the response
+fn wants_close(version: Version, headers: &HeaderMap) -> bool {
+    let has_token = |token: &str| {
+        headers
+            .get_all(header::CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    };
+
+    match version {
+        Version::HTTP_09 | Version::HTTP_10 => !has_token("keep-alive"),
+        Version::HTTP_11 => has_token("close"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::connection_middleware;
+    use axum::{
+        body::{Body, StreamBody},
+        routing::get,
+        Router,
+    };
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Serve the test router, return the bound address
+    async fn serve() -> SocketAddr {
+        let router = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .route(
+                "/stream",
+                get(|| async {
+                    let chunks = ["data: 1\n\n", "data: 2\n\n"]
+                        .into_iter()
+                        .map(Ok::<_, std::io::Error>);
+                    StreamBody::new(Body::wrap_stream(futures::stream::iter(chunks)))
+                }),
+            )
+            .layer(axum::middleware::from_fn(connection_middleware));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+        addr
+    }
+
+    /// Send the raw request, read until the server closes the connection
+    async fn raw_request(addr: SocketAddr, request: &str) -> String {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            stream.read_to_string(&mut response),
+        )
+        .await
+        .expect("connection is not closed")
+        .unwrap();
+        response.to_ascii_lowercase()
+    }
+
+    #[tokio::test]
+    async fn test_http10_closes_connection() {
+        let addr = serve().await;
+        let response = raw_request(addr, "GET / HTTP/1.0\r\n\r\n").await;
+        assert!(response.starts_with("http/1.0 200"));
+        assert!(response.contains("connection: close"));
+        assert!(response.ends_with("ok"));
+    }
+
+    #[tokio::test]
+    async fn test_http11_connection_close() {
+        let addr = serve().await;
+        let response = raw_request(
+            addr,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("http/1.1 200"));
+        assert!(response.contains("connection: close"));
+    }
+
+    #[tokio::test]
+    async fn test_http10_streaming() {
+        let addr = serve().await;
+        let response = raw_request(addr, "GET /stream HTTP/1.0\r\n\r\n").await;
+        assert!(response.starts_with("http/1.0 200"));
+        assert!(!response.contains("transfer-encoding"));
+        assert!(response.ends_with("data: 1\n\ndata: 2\n\n"));
+    }
+}
@@ -0,0 +1,96 @@
+use crate::context::decision::{self, Decisions};
+use crate::with_context;
+use axum::{
+    http::{HeaderMap, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::time::{Duration, Instant};
+
+/// Request header to opt in the debug response headers, the value is the auth key
+pub(crate) const DEBUG_HEADER: &str = "x-ninja-debug";
+
+/// Emit the request routing decisions (arkose type, HAR index, proxy index, egress, solver) in the `X-Ninja-*`
+/// response headers and the phase timings in the `Server-Timing` header, only if enabled and the request
+/// opts in with the auth key
+pub(crate) async fn debug_middleware<B>(request: Request<B>, next: Next<B>) -> Response {
+    let (debug_headers, server_timing) =
+        (with_context!(debug_headers), with_context!(server_timing));
+    if !(debug_headers || server_timing) || !opted_in(request.headers()) {
+        return next.run(request).await;
+    }
+
+    let start = Instant::now();
+    let (mut response, decisions) = decision::scope(next.run(request)).await;
+    if debug_headers {
+        insert_headers(response.headers_mut(), &decisions);
+    }
+    if server_timing {
+        let value = server_timing_value(&decisions, start.elapsed());
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().insert("server-timing", value);
+        }
+    }
+    response
+}
+
+/// Check if the request opts in the debug headers with the auth key, never without an auth key
+fn opted_in(headers: &HeaderMap) -> bool {
+    match (with_context!(auth_key), headers.get(DEBUG_HEADER)) {
+        (Some(auth_key), Some(value)) => value.as_bytes() == auth_key.as_bytes(),
+        _ => false,
+    }
+}
+
+/// Server-Timing header value of the phases, the durations in milliseconds
+fn server_timing_value(decisions: &Decisions, total: Duration) -> String {
+    let dur = |d: Duration| d.as_secs_f64() * 1000.0;
+    format!(
+        "solve;dur={:.1}, upstream;dur={:.1}, total;dur={:.1}",
+        dur(decisions.solve),
+        dur(decisions.upstream),
+        dur(total)
+    )
+}
+
+fn insert_headers(headers: &mut HeaderMap, decisions: &Decisions) {
+    let mut insert = |name: &'static str, value: Option<String>| {
+        if let Some(value) = value.and_then(|v| HeaderValue::from_str(&v).ok()) {
+            headers.insert(name, value);
+        }
+    };
+    insert(
+        "x-ninja-arkose-type",
+        decisions.arkose_type.map(|t| t.to_string()),
+    );
+    insert(
+        "x-ninja-har-index",
+        decisions.har_index.map(|i| i.to_string()),
+    );
+    insert(
+        "x-ninja-proxy-index",
+        decisions.proxy_index.map(|i| i.to_string()),
+    );
+    insert("x-ninja-egress", decisions.egress.map(|e| e.to_string()));
+    insert("x-ninja-solver", decisions.solver.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::server_timing_value;
+    use crate::context::decision::Decisions;
+    use std::time::Duration;
+
+    #[test]
+    fn test_server_timing_value() {
+        let decisions = Decisions {
+            solve: Duration::from_micros(12_340),
+            upstream: Duration::from_millis(200),
+            ..Default::default()
+        };
+        assert_eq!(
+            server_timing_value(&decisions, Duration::from_millis(250)),
+            "solve;dur=12.3, upstream;dur=200.0, total;dur=250.0"
+        );
+    }
+}
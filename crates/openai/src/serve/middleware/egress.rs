@@ -0,0 +1,50 @@
+use super::debug::DEBUG_HEADER;
+use crate::client;
+use crate::serve::error::{ProxyError, ResponseError};
+use crate::with_context;
+use axum::{
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::net::IpAddr;
+
+/// Request header selecting the configured interface of the upstream client
+const EGRESS_HEADER: &str = "x-egress-ip";
+
+/// Send the request from the interface of the `X-Egress-Ip` header, for debugging the egress.
+/// The override requires the auth key in the `X-Ninja-Debug` header
+pub(crate) async fn egress_middleware<B>(request: Request<B>, next: Next<B>) -> Response {
+    let value = match request.headers().get(EGRESS_HEADER) {
+        Some(value) => value,
+        None => return next.run(request).await,
+    };
+
+    match (with_context!(auth_key), request.headers().get(DEBUG_HEADER)) {
+        (Some(auth_key), Some(key)) if key.as_bytes() == auth_key.as_bytes() => {}
+        (Some(_), Some(_)) => {
+            return ResponseError::Forbidden(ProxyError::AuthKeyError).into_response()
+        }
+        _ => return ResponseError::Unauthorized(ProxyError::AuthKeyRequired).into_response(),
+    }
+
+    let ip = match value
+        .to_str()
+        .ok()
+        .and_then(|v| v.trim().parse::<IpAddr>().ok())
+    {
+        Some(ip) => ip,
+        None => {
+            return ResponseError::BadRequest(ProxyError::InvalidEgressIp(
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            ))
+            .into_response()
+        }
+    };
+    if !with_context!(has_egress_ip, ip) {
+        return ResponseError::BadRequest(ProxyError::InvalidEgressIp(ip.to_string()))
+            .into_response();
+    }
+
+    client::egress_scope(ip, next.run(request)).await
+}
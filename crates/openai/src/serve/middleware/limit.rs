@@ -1,24 +1,34 @@
+use crate::serve::client_ip::client_ip;
 use crate::serve::error::{ProxyError, ResponseError};
 use axum::{
     extract::{ConnectInfo, State},
     http::Request,
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
 };
 
-use super::tokenbucket::{TokenBucket, TokenBucketProvider};
+use super::retry_after::set_retry_after;
+use super::tokenbucket::{SeriesTokenBucket, TokenBucket};
+
+/// Retry-After (seconds) when the token bucket is empty, the bucket refills every second
+const RETRY_AFTER_SECS: u64 = 1;
 
 pub(crate) async fn limit_middleware<B>(
-    State(limit): State<std::sync::Arc<TokenBucketProvider>>,
+    State(limit): State<std::sync::Arc<SeriesTokenBucket>>,
     ConnectInfo(socket_addr): ConnectInfo<std::net::SocketAddr>,
     request: Request<B>,
     next: Next<B>,
 ) -> Result<Response, ResponseError> {
-    let addr = socket_addr.ip();
+    let addr = client_ip(socket_addr.ip(), request.headers());
     match limit.acquire(addr) {
         Ok(condition) => match condition {
             true => Ok(next.run(request).await),
-            false => Err(ResponseError::TooManyRequests(ProxyError::TooManyRequests)),
+            false => {
+                let mut response =
+                    ResponseError::TooManyRequests(ProxyError::TooManyRequests).into_response();
+                set_retry_after(&mut response, RETRY_AFTER_SECS);
+                Ok(response)
+            }
         },
         Err(err) => Err(ResponseError::BadGateway(err)),
     }
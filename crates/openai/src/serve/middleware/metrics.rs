@@ -0,0 +1,23 @@
+use crate::context::{event::Event, metrics::Latency};
+use crate::with_context;
+use axum::{http::Request, middleware::Next, response::Response};
+use std::time::Instant;
+
+pub(crate) async fn metrics_middleware<B>(request: Request<B>, next: Next<B>) -> Response {
+    let ctx = with_context!();
+    let metrics = ctx.metrics();
+    let _in_flight = metrics.start_request();
+    ctx.emit(|| Event::RequestReceived {
+        method: request.method().to_string(),
+        path: request.uri().path().to_owned(),
+    });
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let status = response.status().as_u16();
+    let elapsed = start.elapsed();
+    metrics.record(status);
+    metrics.record_latency(Latency::Total, elapsed);
+    ctx.emit(|| Event::RequestCompleted { status, elapsed });
+    response
+}
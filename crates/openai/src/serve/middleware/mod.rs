@@ -1,6 +1,22 @@
+pub mod access_log;
+pub mod admin;
+pub mod affinity;
 pub mod auth;
+pub mod budget;
+pub mod conn_limit;
+pub mod connect;
+pub mod connection;
 pub mod csrf;
+pub mod debug;
+pub mod egress;
 #[cfg(feature = "limit")]
 pub mod limit;
+pub mod metrics;
+pub mod pipeline;
+pub mod queue;
+pub mod replica;
+pub mod retry_after;
+pub mod slo;
+pub mod sticky;
 #[cfg(feature = "limit")]
 pub mod tokenbucket;
@@ -0,0 +1,33 @@
+use super::retry_after::set_retry_after;
+use crate::serve::error::{ProxyError, ResponseError};
+use crate::with_context;
+use axum::{
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Retry-After (seconds) when the pipeline is full
+const RETRY_AFTER_SECS: u64 = 5;
+
+/// Limit the concurrent requests end-to-end, including the arkose solve step
+pub(crate) async fn pipeline_middleware<B>(request: Request<B>, next: Next<B>) -> Response {
+    let semaphore = match with_context!(pipeline_semaphore) {
+        Some(semaphore) => semaphore,
+        None => return next.run(request).await,
+    };
+
+    match semaphore.clone().try_acquire_owned() {
+        Ok(permit) => {
+            let response = next.run(request).await;
+            drop(permit);
+            response
+        }
+        Err(_) => {
+            let mut response =
+                ResponseError::TooManyRequests(ProxyError::PipelineFull).into_response();
+            set_retry_after(&mut response, RETRY_AFTER_SECS);
+            response
+        }
+    }
+}
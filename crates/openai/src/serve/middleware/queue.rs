@@ -0,0 +1,117 @@
+use super::retry_after::set_retry_after;
+use crate::serve::error::{ProxyError, ResponseError};
+use axum::{
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Retry-After (seconds) when the request queue is full or the wait timed out
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// Bounded FIFO queue of the requests waiting for a concurrent limit slot
+pub(crate) struct RequestQueue {
+    slots: Semaphore,
+    /// Maximum queued requests, `None` is unbounded
+    depth: Option<usize>,
+    /// Maximum wait for a slot, `None` waits until the server timeout
+    timeout: Option<Duration>,
+    queued: AtomicUsize,
+}
+
+/// Queued request, left the queue on drop
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl RequestQueue {
+    pub(crate) fn new(limit: usize, depth: Option<usize>, timeout: Option<Duration>) -> Self {
+        Self {
+            slots: Semaphore::new(limit.clamp(1, Semaphore::MAX_PERMITS)),
+            depth,
+            timeout,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Acquire a slot, wait in the queue if the concurrent limit is reached
+    async fn acquire(&self) -> Result<SemaphorePermit<'_>, ProxyError> {
+        if let Ok(permit) = self.slots.try_acquire() {
+            return Ok(permit);
+        }
+
+        let queued = self.queued.fetch_add(1, Ordering::SeqCst);
+        let _queued = Queued(&self.queued);
+        if self.depth.is_some_and(|depth| queued >= depth) {
+            return Err(ProxyError::QueueFull);
+        }
+
+        let permit = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.slots.acquire())
+                .await
+                .map_err(|_| ProxyError::QueueTimeout)?,
+            None => self.slots.acquire().await,
+        };
+        permit.map_err(|_| ProxyError::QueueFull)
+    }
+}
+
+/// Limit the concurrent requests, the requests over the limit wait in the bounded queue,
+/// respond 429 when the queue is full or the wait timed out
+pub(crate) async fn queue_middleware<B>(
+    State(queue): State<Arc<RequestQueue>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    match queue.acquire().await {
+        Ok(permit) => {
+            let response = next.run(request).await;
+            drop(permit);
+            response
+        }
+        Err(err) => {
+            let mut response = ResponseError::TooManyRequests(err).into_response();
+            set_retry_after(&mut response, RETRY_AFTER_SECS);
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestQueue;
+    use crate::serve::error::ProxyError;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_request_queue() {
+        let queue = RequestQueue::new(1, Some(1), Some(Duration::from_millis(50)));
+        let permit = queue.acquire().await.unwrap();
+        assert!(matches!(
+            queue.acquire().await,
+            Err(ProxyError::QueueTimeout)
+        ));
+
+        let (waiting, full) = tokio::join!(queue.acquire(), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let full = queue.acquire().await;
+            drop(permit);
+            full
+        });
+        assert!(matches!(full, Err(ProxyError::QueueFull)));
+        assert!(waiting.is_ok());
+    }
+}
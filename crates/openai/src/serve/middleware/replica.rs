@@ -0,0 +1,100 @@
+use crate::serve::error::ResponseError;
+use crate::{debug, warn, with_context};
+use axum::{
+    body::{Body, StreamBody},
+    http::{header, HeaderMap, Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// HAR write endpoints, forwarded to the primary on a replica
+const WRITE_PATHS: [&str; 3] = ["/har/upload", "/har/delete", "/har/rename"];
+/// HAR refresh endpoints, applied locally and forwarded to the primary on a replica,
+/// the refresh reloads the in-memory pool of the node without writing the shared HAR directory
+const REFRESH_PATHS: [&str; 1] = ["/admin/har/refresh"];
+/// Forward request timeout
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(60);
+
+static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/// On a replica, forward the HAR writes to the primary, the primary writes the shared
+/// HAR directory and the replicas reload it by watching. The HAR refreshes are applied
+/// on both. The other admin endpoints keep the node-local state (e.g. maintenance, drain)
+pub(crate) async fn replica_middleware(
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Result<Response, ResponseError> {
+    let path = request.uri().path();
+    let primary_url = match with_context!(primary_url) {
+        Some(primary_url) if request.method() == Method::POST => primary_url,
+        _ => return Ok(next.run(request).await),
+    };
+
+    if REFRESH_PATHS.contains(&path) {
+        let path_and_query = path_and_query(&request);
+        let headers = request.headers().clone();
+        let response = next.run(request).await;
+        if let Err(err) = forward(primary_url, &path_and_query, headers, Body::empty()).await {
+            warn!("Replica failed to forward {path_and_query} to the primary: {err}");
+        }
+        return Ok(response);
+    }
+
+    if !WRITE_PATHS.contains(&path) {
+        return Ok(next.run(request).await);
+    }
+
+    let path_and_query = path_and_query(&request);
+    let (parts, body) = request.into_parts();
+    forward(primary_url, &path_and_query, parts.headers, body).await
+}
+
+fn path_and_query(request: &Request<Body>) -> String {
+    request
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// Forward the POST request to the primary, respond with the primary response
+async fn forward(
+    primary_url: &str,
+    path_and_query: &str,
+    mut headers: HeaderMap,
+    body: Body,
+) -> Result<Response, ResponseError> {
+    let url = format!("{}{path_and_query}", primary_url.trim_end_matches('/'));
+    debug!("Replica forward to the primary: {url}");
+
+    headers.remove(header::HOST);
+    let resp = CLIENT
+        .get_or_init(|| {
+            reqwest::Client::builder()
+                .timeout(FORWARD_TIMEOUT)
+                .build()
+                .expect("Failed to build replica forward client")
+        })
+        .post(url)
+        .headers(headers)
+        .body(reqwest::Body::wrap_stream(body))
+        .send()
+        .await
+        .map_err(ResponseError::BadGateway)?;
+
+    let mut builder = Response::builder().status(resp.status());
+    for (name, value) in resp
+        .headers()
+        .iter()
+        .filter(|(k, _)| k.ne(&header::CONTENT_LENGTH))
+    {
+        builder = builder.header(name, value);
+    }
+    Ok(builder
+        .body(StreamBody::new(resp.bytes_stream()))
+        .map_err(ResponseError::InternalServerError)?
+        .into_response())
+}
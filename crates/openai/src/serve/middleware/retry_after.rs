@@ -0,0 +1,34 @@
+use crate::with_context;
+use axum::http::{header, HeaderValue};
+use axum::response::Response;
+use rand::Rng;
+
+/// Set the Retry-After (seconds) of the rejection, with the configured random jitter
+/// to spread the retries of the clients rejected at the same time
+pub(crate) fn set_retry_after(response: &mut Response, secs: u64) {
+    let jitter_ms = match with_context!(retry_after_jitter_ms) {
+        0 => 0,
+        jitter_ms => rand::thread_rng().gen_range(0..=jitter_ms),
+    };
+    response.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(jittered_secs(secs, jitter_ms)),
+    );
+}
+
+/// Retry-After only supports whole seconds, round the jittered delay up
+fn jittered_secs(secs: u64, jitter_ms: u64) -> u64 {
+    (secs * 1000 + jitter_ms + 999) / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::jittered_secs;
+
+    #[test]
+    fn test_jittered_secs_round_up() {
+        assert_eq!(jittered_secs(5, 0), 5);
+        assert_eq!(jittered_secs(5, 1), 6);
+        assert_eq!(jittered_secs(1, 2500), 4);
+    }
+}
@@ -0,0 +1,28 @@
+use crate::serve::error::{ProxyError, ResponseError};
+use crate::{warn, with_context};
+use axum::{
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Respond 504 once the SLO deadline is exceeded, dropping the inner future cancels the upstream request.
+/// The deadline covers the time to the response head, streaming bodies are bounded by `read_idle_timeout`
+pub(crate) async fn slo_middleware<B>(request: Request<B>, next: Next<B>) -> Response {
+    let slo = match with_context!(slo) {
+        Some(slo) => slo,
+        None => return next.run(request).await,
+    };
+
+    let uri = request.uri().clone();
+    match tokio::time::timeout(slo, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!(
+                "Request {uri} exceeded the SLO deadline ({}ms)",
+                slo.as_millis()
+            );
+            ResponseError::GatewayTimeout(ProxyError::SloExceeded).into_response()
+        }
+    }
+}
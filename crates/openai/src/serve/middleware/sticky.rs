@@ -0,0 +1,34 @@
+use crate::client::{self, StickyKey};
+use crate::with_context;
+use axum::{
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use axum_extra::extract::CookieJar;
+
+/// Keep the requests of the same session key on the same client, only if the sticky sessions
+/// are enabled, the requests without the key keep the rotation
+pub(crate) async fn sticky_middleware<B>(request: Request<B>, next: Next<B>) -> Response {
+    let key = match with_context!(sticky_key) {
+        Some(sticky_key) => session_key(sticky_key, request.headers()),
+        None => None,
+    };
+
+    match key {
+        Some(key) => client::sticky_scope(&key, next.run(request)).await,
+        None => next.run(request).await,
+    }
+}
+
+/// Session key of the request, `None` if absent or empty
+fn session_key(sticky_key: &StickyKey, headers: &HeaderMap) -> Option<String> {
+    let key = match sticky_key {
+        StickyKey::Header(name) => headers.get(name)?.to_str().ok()?.to_owned(),
+        StickyKey::Cookie(name) => CookieJar::from_headers(headers)
+            .get(name)?
+            .value()
+            .to_owned(),
+    };
+    (!key.is_empty()).then_some(key)
+}
@@ -65,6 +65,17 @@ impl MemTokenBucket {
             buckets,
         }
     }
+
+    /// Return a token acquired by the ip, capped at the capacity
+    fn release(&self, ip: IpAddr) {
+        if !self.enable {
+            return;
+        }
+        if let Some(mut bucket) = self.buckets.get(&ip) {
+            bucket.tokens = (bucket.tokens + 1).min(self.capacity);
+            self.buckets.insert(ip, bucket);
+        }
+    }
 }
 
 impl TokenBucket for MemTokenBucket {
@@ -299,3 +310,57 @@ impl TokenBucket for TokenBucketProvider {
         Ok(condition?)
     }
 }
+
+/// Token buckets in series, a request must acquire a token from every bucket.
+/// The optional burst bucket caps short-term bursts while the sustained bucket
+/// caps the long-term rate, the burst bucket is always kept in memory.
+pub struct SeriesTokenBucket {
+    sustained: TokenBucketProvider,
+    burst: Option<MemTokenBucket>,
+}
+
+impl SeriesTokenBucket {
+    pub fn new(sustained: TokenBucketProvider, burst: Option<MemTokenBucket>) -> Self {
+        Self { sustained, burst }
+    }
+}
+
+impl TokenBucket for SeriesTokenBucket {
+    fn acquire(&self, ip: IpAddr) -> anyhow::Result<bool> {
+        let burst = match self.burst.as_ref() {
+            Some(burst) => burst,
+            None => return self.sustained.acquire(ip),
+        };
+        if !burst.acquire(ip)? {
+            return Ok(false);
+        }
+
+        // the request rejected by the sustained bucket gives the burst token back
+        let acquired = self.sustained.acquire(ip);
+        if !matches!(acquired, Ok(true)) {
+            burst.release(ip);
+        }
+        acquired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemTokenBucket, SeriesTokenBucket, TokenBucket, TokenBucketProvider};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn test_series_token_bucket_refund_burst() {
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let series = SeriesTokenBucket::new(
+            TokenBucketProvider::Mem(MemTokenBucket::new(true, 1, 0, 60)),
+            Some(MemTokenBucket::new(true, 2, 0, 60)),
+        );
+
+        assert!(series.acquire(ip).unwrap());
+        // rejected by the sustained bucket, the burst token is refunded
+        assert!(!series.acquire(ip).unwrap());
+        let burst = series.burst.as_ref().unwrap();
+        assert_eq!(burst.buckets.get(&ip).unwrap().tokens, 1);
+    }
+}
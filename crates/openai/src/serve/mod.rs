@@ -1,23 +1,30 @@
+pub mod bench;
+mod client_ip;
 mod error;
 mod middleware;
+mod otlp;
 #[cfg(feature = "preauth")]
 mod preauth;
 mod proxy;
 mod puid;
+mod restart;
 #[cfg(feature = "template")]
 mod router;
 mod signal;
+mod startup;
 mod turnstile;
 mod whitelist;
 
 use self::proxy::ext::RequestExt;
+use self::proxy::ext::ResponseExt;
 use self::proxy::ext::SendRequestExt;
-use self::proxy::resp::response_convert;
+use self::proxy::resp::{hold_until_body_end, response_convert};
 use crate::arkose;
 use crate::arkose::ArkoseContext;
 use crate::arkose::ArkoseToken;
 use crate::auth::model::{AccessToken, AuthAccount, RefreshToken, SessionAccessToken};
 use crate::auth::provide::AuthProvider;
+use crate::client::RequestTracker;
 use crate::constant::API_AUTH_SESSION_COOKIE_KEY;
 use crate::context;
 use crate::context::args::Args;
@@ -25,7 +32,9 @@ use crate::dns;
 use crate::proxy::{InnerProxy, Proxy};
 use crate::serve::error::ProxyError;
 use crate::serve::error::ResponseError;
-use crate::serve::middleware::tokenbucket::{Strategy, TokenBucketProvider};
+use crate::serve::middleware::tokenbucket::{
+    MemTokenBucket, SeriesTokenBucket, Strategy, TokenBucketProvider,
+};
 use crate::{info, warn, with_context};
 use crate::{URL_CHATGPT_API, URL_PLATFORM_API};
 use axum::body::Body;
@@ -51,6 +60,7 @@ use std::str::FromStr;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tower_http::trace;
+use tracing::Instrument;
 use tracing::Level;
 use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
@@ -84,9 +94,65 @@ fn print_boot_message(inner: &Args) {
     inner.arkose_solver.as_ref().map(|solver| {
         info!("ArkoseLabs solver: {:?}", solver.solver);
     });
+    if !inner.arkose_solver_chain.is_empty() {
+        info!(
+            "ArkoseLabs solver fallback chain: {:?}",
+            inner
+                .arkose_solver_chain
+                .iter()
+                .map(|s| &s.solver)
+                .collect::<Vec<_>>()
+        );
+    }
     inner.arkose_endpoint.as_ref().map(|endpoint| {
         info!("ArkoseLabs endpoint: {:?}", endpoint);
     });
+    inner.pipeline_limit.map(|limit| {
+        info!("Request pipeline limit: {limit}");
+    });
+    inner.max_conns_per_ip.map(|limit| {
+        info!("Connections limit per client ip: {limit}");
+    });
+    inner.slo_ms.map(|slo_ms| {
+        info!("Request SLO deadline: {slo_ms}ms");
+    });
+    inner.request_budget_ms.map(|budget_ms| {
+        info!("Request budget: {budget_ms}ms");
+    });
+    if inner.graceful_restart {
+        info!("Graceful restart enabled, send SIGUSR2 to re-exec and drain");
+    }
+    if inner.log_sample_rate < 1.0 {
+        info!("Access log sample rate: {}", inner.log_sample_rate);
+    }
+    if inner.debug_headers {
+        info!("Debug headers enabled, opt in with the X-Ninja-Debug request header");
+    }
+    if (inner.debug_headers || inner.server_timing) && inner.auth_key.is_none() {
+        warn!("Debug headers and Server-Timing require --auth-key, no request can opt in");
+    }
+    inner.primary_url.as_ref().map(|primary_url| {
+        info!("Replica mode, HAR writes are forwarded to the primary: {primary_url}");
+    });
+    inner.otlp_endpoint.as_ref().map(|endpoint| {
+        info!("OTLP traces endpoint: {endpoint}");
+    });
+    inner.solver_workers.map(|workers| {
+        info!("Solver workers: {workers}");
+    });
+    if inner.enable_connect_proxy {
+        info!("HTTP CONNECT proxy enabled");
+    }
+    inner.arkose_endpoints.iter().for_each(|(t, endpoint)| {
+        info!("ArkoseLabs {t} endpoint: {:?}", endpoint);
+    });
+
+    inner.proxy_healthcheck_url.as_ref().map(|url| {
+        info!(
+            "Proxy health check: {url} every {} seconds",
+            inner.proxy_healthcheck_interval
+        );
+    });
 
     inner.proxies.iter().for_each(|p| match p {
         Proxy::All(inner) | Proxy::Api(inner) | Proxy::Auth(inner) | Proxy::Arkose(inner) => {
@@ -121,11 +187,25 @@ impl Serve {
                     .unwrap_or_else(|_| "RUST_LOG=warn".into()),
             )
             .with(tracing_subscriber::fmt::layer())
+            .with(otlp::layer(self.0.otlp_endpoint.as_deref())?)
             .init();
 
         // print boot message
         print_boot_message(&self.0);
 
+        // Require arkose solver
+        if self.0.require_solver
+            && self.0.arkose_solver.is_none()
+            && self.0.arkose_solver_chain.is_empty()
+        {
+            anyhow::bail!("ArkoseLabs solver is required but not configured");
+        }
+
+        // Fail closed, the CONNECT proxy is never exposed as an open proxy of the egress
+        if self.0.enable_connect_proxy && self.0.auth_key.is_none() {
+            anyhow::bail!("HTTP CONNECT proxy requires --auth-key");
+        }
+
         // init context
         context::init(self.0.clone());
 
@@ -133,13 +213,62 @@ impl Serve {
         let global_layer = tower::ServiceBuilder::new()
             .layer(
                 tower_http::trace::TraceLayer::new_for_http()
-                    .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
-                    .on_response(trace::DefaultOnResponse::new().level(Level::INFO))
-                    .on_request(trace::DefaultOnRequest::new().level(Level::INFO))
+                    .make_span_with(|request: &axum::http::Request<Body>| {
+                        // Log the real client ip behind the trusted proxies
+                        let client_ip = request
+                            .extensions()
+                            .get::<axum::extract::ConnectInfo<SocketAddr>>()
+                            .map(|info| client_ip::client_ip(info.0.ip(), request.headers()));
+                        tracing::info_span!(
+                            "request",
+                            method = %request.method(),
+                            uri = %request.uri(),
+                            version = ?request.version(),
+                            client_ip = ?client_ip,
+                        )
+                    })
+                    // The access lines are sampled by the access log middleware
+                    .on_response(())
+                    .on_request(())
                     .on_failure(trace::DefaultOnFailure::new().level(Level::WARN)),
             )
-            .layer(tower::limit::ConcurrencyLimitLayer::new(
-                self.0.concurrent_limit,
+            .layer(axum::middleware::from_fn(
+                middleware::access_log::access_log_middleware,
+            ))
+            .layer(axum::middleware::from_fn(
+                middleware::metrics::metrics_middleware,
+            ))
+            .layer(axum::middleware::from_fn(
+                middleware::connection::connection_middleware,
+            ))
+            .layer(axum::middleware::from_fn(
+                middleware::sticky::sticky_middleware,
+            ))
+            .layer(axum::middleware::from_fn(
+                middleware::egress::egress_middleware,
+            ))
+            .layer(axum::middleware::from_fn(
+                middleware::debug::debug_middleware,
+            ))
+            .layer(axum::middleware::from_fn(
+                middleware::connect::connect_middleware,
+            ))
+            .layer(axum::middleware::from_fn(
+                middleware::affinity::har_affinity_middleware,
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(middleware::conn_limit::ConnLimiter::new(
+                    self.0.max_conns_per_ip,
+                )),
+                middleware::conn_limit::conn_limit_middleware,
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(middleware::queue::RequestQueue::new(
+                    self.0.concurrent_limit,
+                    self.0.queue_depth,
+                    self.0.queue_timeout.map(Duration::from_secs),
+                )),
+                middleware::queue::queue_middleware,
             ))
             .layer(
                 tower_http::cors::CorsLayer::new()
@@ -158,13 +287,23 @@ impl Serve {
 
         // init auth layer provider
         let app_layer = {
-            let limit_context = TokenBucketProvider::from((
-                Strategy::from_str(self.0.tb_strategy.as_str())?,
-                self.0.tb_enable,
-                self.0.tb_capacity,
-                self.0.tb_fill_rate,
-                self.0.tb_expired,
-            ));
+            let limit_context = SeriesTokenBucket::new(
+                TokenBucketProvider::from((
+                    Strategy::from_str(self.0.tb_strategy.as_str())?,
+                    self.0.tb_enable,
+                    self.0.tb_capacity,
+                    self.0.tb_fill_rate,
+                    self.0.tb_expired,
+                )),
+                self.0.tb_burst_capacity.map(|capacity| {
+                    MemTokenBucket::new(
+                        self.0.tb_enable,
+                        capacity,
+                        self.0.tb_burst_rate,
+                        self.0.tb_expired,
+                    )
+                }),
+            );
 
             tower::ServiceBuilder::new()
                 .layer(axum::middleware::from_fn(middleware::auth::auth_middleware))
@@ -172,6 +311,13 @@ impl Serve {
                     Arc::new(limit_context),
                     middleware::limit::limit_middleware,
                 ))
+                .layer(axum::middleware::from_fn(
+                    middleware::pipeline::pipeline_middleware,
+                ))
+                .layer(axum::middleware::from_fn(middleware::slo::slo_middleware))
+                .layer(axum::middleware::from_fn(
+                    middleware::budget::budget_middleware,
+                ))
         };
 
         let router = Router::new()
@@ -190,7 +336,12 @@ impl Serve {
         let router = router::config(
             // Enable arkose token endpoint proxy
             if self.0.enable_arkose_proxy {
-                router.route("/auth/arkose_token/:path", get(get_arkose_token))
+                router.route(
+                    "/auth/arkose_token/:path",
+                    get(get_arkose_token).route_layer(axum::middleware::from_fn(
+                        middleware::pipeline::pipeline_middleware,
+                    )),
+                )
             } else {
                 router
             },
@@ -201,18 +352,46 @@ impl Serve {
         // Signal the server to shutdown using Handle.
         let handle = Handle::new();
 
+        // Expose the active connection count
+        with_context!(metrics).set_connection_gauge({
+            let handle = handle.clone();
+            move || handle.connection_count()
+        });
+
         // Spawn a task to gracefully shutdown server.
         tokio::spawn(signal::graceful_shutdown(handle.clone()));
 
         // Fast dns test
         dns::fast::load_fastest_dns(self.0.fastest_dns).await?;
 
+        // Fail fast on the gated arkose types without a HAR and a solver
+        startup::check_gated_types(&self.0)?;
+
+        // Startup validation report
+        if self.0.startup_report {
+            startup::report(&self.0).await?;
+        }
+
         // check wan address.
         check_wan_address().await;
 
         // upgrade arkose version.
         tokio::spawn(with_context!(arkose_context).periodic_upgrade());
 
+        // refill arkose warm pools.
+        for (typed, pool) in with_context!(arkose_warm_pools) {
+            tokio::spawn(pool.periodic_refill(*typed));
+        }
+
+        // health check client proxies.
+        tokio::spawn(with_context!().periodic_healthcheck());
+
+        // reload client proxies and solver config.
+        #[cfg(target_family = "unix")]
+        if let Some(reloader) = self.0.config_reloader.clone() {
+            tokio::spawn(signal::reload_on_sigusr1(reloader));
+        }
+
         // http server tcp keepalive
         let tcp_keepalive = Duration::from_secs(self.0.tcp_keepalive as u64 + 1);
 
@@ -238,6 +417,8 @@ impl Serve {
             let builder = mitm::Builder::builder()
                 .bind(pbind)
                 .upstream_proxy(self.0.pupstream.clone())
+                .upstream_timeout(Duration::from_secs(self.0.preauth_timeout))
+                .concurrency(self.0.preauth_concurrency)
                 .cert(self.0.pcert.clone())
                 .key(self.0.pkey.clone())
                 .graceful_shutdown(rx)
@@ -250,37 +431,117 @@ impl Serve {
             }
         }
 
-        info!(
-            "Starting HTTP(S) server at http(s)://{:?}",
-            self.0.bind.unwrap()
-        );
+        // Require at least one bind address
+        if self.0.bind.is_empty() {
+            anyhow::bail!("No server bind address specified");
+        }
 
-        // Run http server
-        let result = match (self.0.tls_cert, self.0.tls_key) {
+        // Admin router, served on the admin bind address
+        let admin_router = self.0.admin_bind.map(|bind| {
+            let router = router::admin(&self.0).layer(
+                tower_http::trace::TraceLayer::new_for_http()
+                    .on_failure(trace::DefaultOnFailure::new().level(Level::WARN)),
+            );
+            (bind, router)
+        });
+
+        // Load TLS config once, shared by all listeners
+        let tls_config = match (self.0.tls_cert, self.0.tls_key) {
             (Some(cert), Some(key)) => {
                 let tls_config = RustlsConfig::from_pem_file(cert, key)
                     .await
                     .expect("Failed to load TLS keypair");
-
-                axum_server::bind_rustls(self.0.bind.unwrap(), tls_config)
-                    .handle(handle)
-                    .addr_incoming_config(incoming_config)
-                    .http_config(http_config)
-                    .serve(router.into_make_service_with_connect_info::<SocketAddr>())
-                    .await
-            }
-            _ => {
-                axum_server::bind(self.0.bind.unwrap())
-                    .handle(handle)
-                    .addr_incoming_config(incoming_config)
-                    .http_config(http_config)
-                    .serve(router.into_make_service_with_connect_info::<SocketAddr>())
-                    .await
+                // Override the advertised ALPN protocols
+                if !self.0.tls_alpn.is_empty() {
+                    let mut server_config = (*tls_config.get_inner()).clone();
+                    server_config.alpn_protocols = self
+                        .0
+                        .tls_alpn
+                        .iter()
+                        .map(|p| p.as_bytes().to_vec())
+                        .collect();
+                    tls_config.reload_from_config(Arc::new(server_config));
+                }
+                Some(tls_config)
             }
+            _ => None,
         };
 
-        if let Some(err) = result.err() {
-            warn!("Http Server error: {}", err);
+        // Bind the listeners of the bind addresses and the admin bind address last,
+        // inherited from the previous process on graceful restart
+        let binds = self
+            .0
+            .bind
+            .iter()
+            .chain(self.0.admin_bind.as_ref())
+            .copied()
+            .collect::<Vec<_>>();
+        let mut listeners = restart::bind_listeners(&binds, self.0.graceful_restart)?;
+
+        // Re-exec on SIGUSR2 passing the listening sockets
+        #[cfg(target_family = "unix")]
+        if self.0.graceful_restart {
+            let listeners = listeners
+                .iter()
+                .map(|listener| listener.try_clone())
+                .collect::<std::io::Result<Vec<_>>>()?;
+            tokio::spawn(restart::restart_on_sigusr2(handle.clone(), listeners));
+        }
+
+        // Spawn the admin listener, plain HTTP on the private interface
+        let mut servers = Vec::with_capacity(binds.len());
+        if let Some((admin_bind, admin_router)) = admin_router {
+            info!("Starting admin HTTP server at http://{:?}", admin_bind);
+            let listener = listeners.pop().expect("Failed to get admin listener");
+            let server = tokio::spawn(
+                axum_server::from_tcp(listener)
+                    .handle(handle.clone())
+                    .addr_incoming_config(incoming_config.clone())
+                    .http_config(http_config.clone())
+                    .serve(admin_router.into_make_service_with_connect_info::<SocketAddr>()),
+            );
+            servers.push((admin_bind, server));
+        }
+
+        // Spawn a listener per bind address, sharing the same router. The connections are
+        // tracked for the per client ip connection limit
+        let make_service = middleware::conn_limit::TrackConnections(
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        );
+        for (bind, listener) in self.0.bind.iter().copied().zip(listeners) {
+            info!("Starting HTTP(S) server at http(s)://{:?}", bind);
+
+            let server = match tls_config.clone() {
+                Some(tls_config) => tokio::spawn(
+                    axum_server::tls_rustls::from_tcp_rustls(listener, tls_config)
+                        .handle(handle.clone())
+                        .addr_incoming_config(incoming_config.clone())
+                        .http_config(http_config.clone())
+                        .serve(make_service.clone()),
+                ),
+                None => tokio::spawn(
+                    axum_server::from_tcp(listener)
+                        .handle(handle.clone())
+                        .addr_incoming_config(incoming_config.clone())
+                        .http_config(http_config.clone())
+                        .serve(make_service.clone()),
+                ),
+            };
+            servers.push((bind, server));
+        }
+
+        // Wait for all listeners to exit
+        for (bind, server) in servers {
+            match server.await {
+                Ok(Err(err)) => warn!("Http Server {bind} error: {}", err),
+                Err(err) => warn!("Http Server {bind} task error: {}", err),
+                _ => {}
+            }
+        }
+
+        // Write shutdown report
+        if let Some(path) = self.0.shutdown_report_path.as_ref() {
+            write_shutdown_report(path).await;
         }
 
         if let Some(err) = tx.send(()).await.err() {
@@ -288,6 +549,7 @@ impl Serve {
         }
 
         tokio::time::sleep(Duration::from_secs(1)).await;
+        otlp::shutdown();
         Ok(())
     }
 }
@@ -297,6 +559,7 @@ async fn post_billing(
     TypedHeader(bearer): TypedHeader<Authorization<Bearer>>,
 ) -> Result<impl IntoResponse, ResponseError> {
     match with_context!(auth_client)
+        .await?
         .billing_credit_grants(bearer.token())
         .await
     {
@@ -310,6 +573,7 @@ async fn post_refresh_session(
     TypedHeader(bearer): TypedHeader<Authorization<Bearer>>,
 ) -> Result<impl IntoResponse, ResponseError> {
     let session_token = with_context!(auth_client)
+        .await?
         .refresh_session(bearer.token())
         .await
         .map_err(ResponseError::BadRequest)?;
@@ -330,6 +594,7 @@ async fn post_sess_token(
     TypedHeader(bearer): TypedHeader<Authorization<Bearer>>,
 ) -> Result<impl IntoResponse, ResponseError> {
     match with_context!(auth_client)
+        .await?
         .dashboard_login(bearer.token())
         .await
     {
@@ -341,7 +606,8 @@ async fn post_sess_token(
 /// POST /auth/token
 async fn post_access_token(
     bearer: Option<TypedHeader<Authorization<Bearer>>>,
-    account: axum::Form<AuthAccount>,
+    headers: axum::http::HeaderMap,
+    mut account: axum::Form<AuthAccount>,
 ) -> Result<impl IntoResponse, ResponseError> {
     // check username/email in whitelist
     whitelist::check_whitelist(&account.username).map_err(ResponseError::Forbidden)?;
@@ -355,7 +621,14 @@ async fn post_access_token(
         }
     }
 
-    match with_context!(auth_client).do_access_token(&account).await? {
+    #[cfg(feature = "preauth")]
+    preauth::select_strategy(&mut account, &headers);
+
+    match with_context!(auth_client)
+        .await?
+        .do_access_token(&account)
+        .await?
+    {
         AccessToken::Session(session_token) => {
             let resp: Response<Body> = session_token.try_into()?;
             Ok(resp.into_response())
@@ -369,6 +642,7 @@ async fn post_refresh_token(
     TypedHeader(bearer): TypedHeader<Authorization<Bearer>>,
 ) -> Result<Json<RefreshToken>, ResponseError> {
     match with_context!(auth_client)
+        .await?
         .do_refresh_token(bearer.token())
         .await
     {
@@ -382,6 +656,7 @@ async fn post_revoke_token(
     TypedHeader(bearer): TypedHeader<Authorization<Bearer>>,
 ) -> Result<StatusCode, ResponseError> {
     match with_context!(auth_client)
+        .await?
         .do_revoke_token(bearer.token())
         .await
     {
@@ -415,9 +690,10 @@ async fn get_arkose_token(
     // Require arkose token endpoint public key
     let typed = arkose::Type::from_pk(pk.as_str()).map_err(ResponseError::BadRequest)?;
 
+    let arkose_client = with_context!(arkose_client).await?;
     ArkoseToken::new_from_context(
         ArkoseContext::builder()
-            .client(with_context!(arkose_client))
+            .client(arkose_client.client())
             .typed(typed)
             .identifier(blob.map(|v| v.0.blob).flatten())
             .build(),
@@ -437,18 +713,85 @@ async fn get_arkose_token(
 /// platform API match path /v1/{tail.*}
 /// reference: https://platform.openai.com/docs/api-reference
 async fn official_proxy(req: RequestExt) -> Result<impl IntoResponse, ResponseError> {
-    let resp = with_context!(api_client)
+    check_maintenance()?;
+    let models = req.method == axum::http::Method::GET && req.uri.path() == "/v1/models";
+    let (client, mut tracker) = with_context!(api_client_checked).await?;
+    let span = upstream_span(tracker.index());
+    let resp = client
         .send_request(URL_PLATFORM_API, req)
-        .await?;
-    response_convert(resp).await
+        .instrument(span.clone())
+        .await;
+    finish_upstream(&mut tracker, &span, &resp);
+    if models {
+        if let Some(fallback) = fallback_models(&resp) {
+            return Ok(fallback);
+        }
+    }
+    Ok(hold_until_body_end(response_convert(resp?).await?, tracker))
+}
+
+/// Serve the static model list if the upstream is unreachable or fails
+fn fallback_models(resp: &Result<ResponseExt, ResponseError>) -> Option<axum::response::Response> {
+    let upstream_down = match resp {
+        Ok(resp) => resp.inner.status().is_server_error(),
+        Err(_) => true,
+    };
+    if !upstream_down {
+        return None;
+    }
+
+    let models = with_context!(fallback_models)?;
+    warn!("Upstream models unavailable, serving the fallback models");
+    Some(Json(models.clone()).into_response())
+}
+
+/// Reject the proxy request in maintenance or drain mode
+fn check_maintenance() -> Result<(), ResponseError> {
+    if with_context!(is_drained) {
+        return Err(ResponseError::ServiceUnavailable(ProxyError::Draining));
+    }
+    match with_context!(maintenance) {
+        Some(maintenance) => Err(ResponseError::new(
+            maintenance.message,
+            StatusCode::from_u16(maintenance.status).unwrap_or(StatusCode::SERVICE_UNAVAILABLE),
+        )),
+        None => Ok(()),
+    }
 }
 
 /// reference: doc/http.rest
 async fn unofficial_proxy(req: RequestExt) -> Result<impl IntoResponse, ResponseError> {
-    let resp = with_context!(api_client)
+    check_maintenance()?;
+    let (client, mut tracker) = with_context!(api_client_checked).await?;
+    let span = upstream_span(tracker.index());
+    let resp = client
         .send_request(URL_CHATGPT_API, req)
-        .await?;
-    response_convert(resp).await
+        .instrument(span.clone())
+        .await;
+    finish_upstream(&mut tracker, &span, &resp);
+    Ok(hold_until_body_end(response_convert(resp?).await?, tracker))
+}
+
+/// Upstream call span, covering the arkose solve and the upstream request
+fn upstream_span(proxy_index: usize) -> tracing::Span {
+    tracing::info_span!(
+        "upstream",
+        proxy.index = proxy_index,
+        http.status_code = tracing::field::Empty,
+    )
+}
+
+/// Record the upstream result to the client stats and the span, the client is connected on a response
+fn finish_upstream(
+    tracker: &mut RequestTracker,
+    span: &tracing::Span,
+    resp: &Result<ResponseExt, ResponseError>,
+) {
+    if let Ok(resp) = resp {
+        span.record("http.status_code", resp.inner.status().as_u16());
+        tracker.connected();
+    }
+    tracker.finish(matches!(resp, Ok(resp) if !resp.inner.status().is_server_error()));
 }
 
 impl TryInto<Response<Body>> for SessionAccessToken {
@@ -488,8 +831,36 @@ impl TryInto<Response<Body>> for SessionAccessToken {
     }
 }
 
+async fn write_shutdown_report(path: &std::path::Path) {
+    let ctx = with_context!();
+    let report = serde_json::json!({
+        "timestamp": crate::now_duration().map(|d| d.as_secs()).unwrap_or_default(),
+        "metrics": ctx.metrics().snapshot(),
+        "preauth_cookies": ctx.preauth_cookie_count(),
+        "proxies": ctx.proxy_stats(),
+    });
+
+    info!("Shutdown report: {report}");
+
+    match serde_json::to_vec_pretty(&report) {
+        Ok(bytes) => {
+            if let Some(err) = tokio::fs::write(path, bytes).await.err() {
+                warn!("Write shutdown report error: {}", err)
+            }
+        }
+        Err(err) => warn!("Serialize shutdown report error: {}", err),
+    }
+}
+
 async fn check_wan_address() {
-    match with_context!(api_client)
+    let client = match with_context!(api_client).await {
+        Ok(client) => client,
+        Err(err) => {
+            warn!("Check IP address error: {err}");
+            return;
+        }
+    };
+    match client
         .get("https://ifconfig.me")
         .timeout(Duration::from_secs(70))
         .header(header::ACCEPT, mime::APPLICATION_JSON.as_ref())
@@ -0,0 +1,49 @@
+use tracing::Subscriber;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+/// OpenTelemetry OTLP traces layer, `None` if the endpoint is not configured
+#[cfg(feature = "otlp")]
+pub(super) fn layer<S>(endpoint: Option<&str>) -> anyhow::Result<Option<impl Layer<S>>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let endpoint = match endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+            opentelemetry_sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+                "service.name",
+                "ninja",
+            )]),
+        ))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// OpenTelemetry OTLP traces layer, requires the `otlp` feature
+#[cfg(not(feature = "otlp"))]
+pub(super) fn layer<S>(endpoint: Option<&str>) -> anyhow::Result<Option<impl Layer<S>>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    if endpoint.is_some() {
+        anyhow::bail!("OTLP endpoint is configured, but the `otlp` feature is not enabled");
+    }
+    Ok(None::<tracing_subscriber::layer::Identity>)
+}
+
+/// Flush and shutdown the OTLP traces exporter
+pub(super) fn shutdown() {
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -1,4 +1,5 @@
-use crate::with_context;
+use crate::auth::model::{AuthAccount, AuthStrategy};
+use crate::{debug, with_context};
 use axum_extra::extract::CookieJar;
 use mitm::proxy::hyper::{
     body::Body,
@@ -7,6 +8,37 @@ use mitm::proxy::hyper::{
 use mitm::proxy::{handler::HttpHandler, mitm::RequestOrResponse};
 use std::fmt::Write;
 
+/// Request header to take (`true`) or skip (`false`) the preauth path of the Apple login,
+/// overrides `preauth_ratio`
+const USE_PREAUTH_HEADER: &str = "x-use-preauth";
+
+/// Select the login path of the account, the Apple logins not taking the preauth path fall back
+/// to the web login
+pub(crate) fn select_strategy(account: &mut AuthAccount, headers: &axum::http::HeaderMap) {
+    if account.option != AuthStrategy::Apple {
+        return;
+    }
+
+    let use_preauth = match headers
+        .get(USE_PREAUTH_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("true" | "1") => true,
+        Some("false" | "0") => false,
+        _ => {
+            let ratio = with_context!(preauth_ratio);
+            ratio >= 1.0 || (ratio > 0.0 && rand::random::<f64>() < ratio)
+        }
+    };
+
+    if !use_preauth {
+        debug!("Preauth path skipped, fall back to the web login");
+        account.option = AuthStrategy::Web;
+    }
+}
+
 #[derive(Clone)]
 pub struct PreAuthHanlder;
 
@@ -30,7 +62,15 @@ fn collect_preauth_cookie(headers: &HeaderMap<HeaderValue>) {
         // Preauth cookie max age
         if c.name().eq("_preauth_devicecheck") {
             let max_age = c.max_age().map(|a| a.as_seconds_f32() as u32);
-            with_context!(push_preauth_cookie, c.value(), max_age);
+            // The cookie real lifetime, Max-Age takes precedence over Expires
+            let ttl = c
+                .max_age()
+                .or_else(|| {
+                    c.expires_datetime()
+                        .map(|expires| expires - time::OffsetDateTime::now_utc())
+                })
+                .and_then(|ttl| std::time::Duration::try_from(ttl).ok());
+            with_context!(push_preauth_cookie, c.value(), max_age, ttl);
         }
     }
 }
@@ -0,0 +1,117 @@
+use crate::serve::error::{ProxyError, ResponseError};
+use crate::{info, warn, with_context};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+static ERROR_CACHE: OnceLock<ErrorCache> = OnceLock::new();
+
+/// Consecutive upstream failures of an origin
+#[derive(Default)]
+struct Failures {
+    consecutive: usize,
+    /// End of the cooldown, the requests fail fast until then
+    until: Option<Instant>,
+}
+
+/// Negative cache of the upstream failures, after `threshold` consecutive failures of an origin
+/// the proxy requests fail fast for the `ttl` cooldown instead of attempting the upstream.
+/// The next failure after the cooldown starts a new one, a success recovers
+struct ErrorCache {
+    ttl: Duration,
+    threshold: usize,
+    origins: Mutex<HashMap<&'static str, Failures>>,
+}
+
+impl ErrorCache {
+    fn new(ttl: Duration, threshold: usize) -> Self {
+        Self {
+            ttl,
+            threshold: threshold.max(1),
+            origins: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Remaining cooldown of the origin, `None` if not cooling down
+    fn cooldown(&self, origin: &'static str) -> Option<Duration> {
+        let origins = self.origins.lock().ok()?;
+        let until = origins.get(origin)?.until?;
+        Some(until.saturating_duration_since(Instant::now())).filter(|d| !d.is_zero())
+    }
+
+    fn record(&self, origin: &'static str, success: bool) {
+        let mut origins = match self.origins.lock() {
+            Ok(origins) => origins,
+            Err(_) => return,
+        };
+        let failures = origins.entry(origin).or_default();
+
+        if success {
+            if failures.until.take().is_some() {
+                info!("Upstream {origin} recovered");
+            }
+            failures.consecutive = 0;
+            return;
+        }
+
+        failures.consecutive += 1;
+        let cooling = failures.until.is_some_and(|until| until > Instant::now());
+        if failures.consecutive >= self.threshold && !cooling {
+            warn!(
+                "Upstream {origin} failed {} times in a row, failing fast for {}s",
+                failures.consecutive,
+                self.ttl.as_secs()
+            );
+            failures.until = Some(Instant::now() + self.ttl);
+        }
+    }
+}
+
+/// Get the error cache, `None` if disabled
+fn get() -> Option<&'static ErrorCache> {
+    let ttl = with_context!(error_cache_ttl)?;
+    Some(ERROR_CACHE.get_or_init(|| ErrorCache::new(ttl, with_context!(error_cache_threshold))))
+}
+
+/// Fail fast with the cached error while the origin is cooling down
+pub(super) fn check(origin: &'static str) -> Result<(), ResponseError> {
+    match get().and_then(|cache| cache.cooldown(origin)) {
+        Some(remaining) => Err(ResponseError::ServiceUnavailable(
+            ProxyError::UpstreamCoolingDown(remaining.as_secs().max(1)),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Record the upstream result of the origin, the connection errors and 5xx are failures
+pub(super) fn record<E>(origin: &'static str, resp: &Result<reqwest::Response, E>) {
+    if let Some(cache) = get() {
+        let success = matches!(resp, Ok(resp) if !resp.status().is_server_error());
+        cache.record(origin, success);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrorCache;
+    use std::time::Duration;
+
+    #[test]
+    fn test_error_cache() {
+        let cache = ErrorCache::new(Duration::from_secs(60), 2);
+        let origin = "https://api.openai.com";
+
+        cache.record(origin, false);
+        assert!(cache.cooldown(origin).is_none());
+        cache.record(origin, false);
+        assert!(cache.cooldown(origin).is_some());
+        assert!(cache.cooldown("https://chat.openai.com").is_none());
+
+        cache.record(origin, true);
+        assert!(cache.cooldown(origin).is_none());
+        cache.record(origin, false);
+        assert!(cache.cooldown(origin).is_none());
+    }
+}
@@ -1,3 +1,4 @@
+mod error_cache;
 pub mod ext;
 pub mod req;
 pub mod resp;
@@ -8,16 +8,21 @@ use axum::{
 use http::header;
 use http::{HeaderMap, Method};
 use serde_json::{json, Value};
+use tracing::{info_span, Instrument};
 
 use crate::arkose::{ArkoseContext, ArkoseToken, Type};
 use crate::constant::{ARKOSE_TOKEN, EMPTY, MODEL, NULL, PUID};
 use crate::gpt_model::GPTModel;
 use crate::{arkose, with_context};
 
+use super::error_cache;
 use super::ext::{RequestExt, ResponseExt, SendRequestExt};
 use super::header_convert;
 use super::toapi;
+use crate::context::args::UnknownArkosePolicy;
+use crate::context::metrics::Latency;
 use crate::serve::error::{ProxyError, ResponseError};
+use crate::serve::middleware::budget;
 use crate::serve::puid::{get_or_init, reduce_key};
 
 #[async_trait]
@@ -32,6 +37,9 @@ impl SendRequestExt for reqwest::Client {
             return toapi::send_request(req).await;
         }
 
+        // Fail fast while the upstream is cooling down, before the arkose solve
+        error_cache::check(origin)?;
+
         // Build rqeuest path and query
         let path_and_query = req
             .uri
@@ -39,8 +47,8 @@ impl SendRequestExt for reqwest::Client {
             .map(|v| v.as_str())
             .unwrap_or(req.uri.path());
 
-        // Build url
-        let url = format!("{origin}{path_and_query}");
+        // Build url, rewrite the upstream if configured
+        let url = with_context!(upstream_url, origin, path_and_query);
 
         // Handle conversation request
         handle_conv_request(&mut req).await?;
@@ -57,7 +65,9 @@ impl SendRequestExt for reqwest::Client {
         }
 
         // Send request
-        Ok(ResponseExt::builder().inner(builder.send().await?).build())
+        let resp = budget::step(Latency::Upstream, builder.send()).await?;
+        error_cache::record(origin, &resp);
+        Ok(ResponseExt::builder().inner(resp?).build())
     }
 }
 
@@ -119,11 +129,30 @@ async fn handle_conv_request(req: &mut RequestExt) -> Result<(), ResponseError>
         }
     }
 
-    // Parse model
-    let model = GPTModel::from_str(model).map_err(ResponseError::BadRequest)?;
+    // Resolve the arkose type, the configured model map takes precedence
+    let typed = match with_context!(model_arkose_type, model) {
+        Some(typed) => Some(typed),
+        None => match GPTModel::from_str(model) {
+            // If model is gpt3 or gpt4, then add arkose_token
+            Ok(model) => ((with_context!(arkose_gpt3_experiment) && model.is_gpt3())
+                || model.is_gpt4())
+            .then(|| model.into()),
+            // Unknown model, passthrough without arkose_token or reject
+            Err(_) => {
+                with_context!(log_unmapped_model, model);
+                match with_context!(unknown_arkose_policy) {
+                    UnknownArkosePolicy::Passthrough => None,
+                    UnknownArkosePolicy::Reject => {
+                        return Err(ResponseError::BadRequest(ProxyError::UnknownArkoseModel(
+                            model.to_owned(),
+                        )))
+                    }
+                }
+            }
+        },
+    };
 
-    // If model is gpt3 or gpt4, then add arkose_token
-    if (with_context!(arkose_gpt3_experiment) && model.is_gpt3()) || model.is_gpt4() {
+    if let Some(typed) = typed {
         let condition = match body.get(ARKOSE_TOKEN) {
             Some(s) => {
                 let s = s.as_str().unwrap_or(EMPTY);
@@ -133,14 +162,19 @@ async fn handle_conv_request(req: &mut RequestExt) -> Result<(), ResponseError>
         };
 
         if condition {
-            let arkose_token = ArkoseToken::new_from_context(
-                ArkoseContext::builder()
-                    .client(with_context!(arkose_client))
-                    .typed(model.into())
-                    .identifier(Some(token))
-                    .build(),
+            let arkose_client = with_context!(arkose_client).await?;
+            let arkose_token = budget::step(
+                Latency::Solve,
+                ArkoseToken::new_from_context(
+                    ArkoseContext::builder()
+                        .client(arkose_client.client())
+                        .typed(typed)
+                        .identifier(Some(token))
+                        .build(),
+                )
+                .instrument(info_span!("arkose_solve", arkose.type = %typed)),
             )
-            .await?;
+            .await??;
             body.insert(ARKOSE_TOKEN.to_owned(), json!(arkose_token.value()));
             // Updaye Modify bytes
             req.body = Some(Bytes::from(
@@ -175,14 +209,19 @@ async fn handle_dashboard_request(req: &mut RequestExt) -> Result<(), ResponseEr
 
     // If arkose_token is not exist, then add it
     if body.get(ARKOSE_TOKEN).is_none() {
-        let arkose_token = arkose::ArkoseToken::new_from_context(
-            arkose::ArkoseContext::builder()
-                .client(with_context!(arkose_client))
-                .typed(Type::Platform)
-                .identifier(None)
-                .build(),
+        let arkose_client = with_context!(arkose_client).await?;
+        let arkose_token = budget::step(
+            Latency::Solve,
+            arkose::ArkoseToken::new_from_context(
+                arkose::ArkoseContext::builder()
+                    .client(arkose_client.client())
+                    .typed(Type::Platform)
+                    .identifier(None)
+                    .build(),
+            )
+            .instrument(info_span!("arkose_solve", arkose.type = %Type::Platform)),
         )
-        .await?;
+        .await??;
         body.insert(ARKOSE_TOKEN.to_owned(), json!(arkose_token.value()));
         // Updaye Modify bytes
         req.body = Some(Bytes::from(
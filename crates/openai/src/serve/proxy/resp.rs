@@ -1,44 +1,106 @@
 use std::time::UNIX_EPOCH;
 
 use crate::constant::{CF_CLEARANCE, NINJA_VERSION, PUID};
-use crate::with_context;
 use crate::LIB_VERSION;
-use axum::body::Body;
+use crate::{warn, with_context};
 use axum::body::StreamBody;
+use axum::body::{Body, Bytes, HttpBody};
 use axum::http::header;
+use axum::http::HeaderMap;
 use axum::response::{IntoResponse, Response};
+use axum::BoxError;
 use axum_extra::extract::cookie;
 use axum_extra::extract::cookie::Cookie;
 use serde_json::Value;
+use tokio_stream::{Stream, StreamExt};
 
-use crate::serve::error::ResponseError;
+use crate::serve::error::{ProxyError, ResponseError};
 
 use super::ext::ResponseExt;
 use super::toapi;
 
 /// Response convert
-pub(crate) async fn response_convert(
-    resp: ResponseExt,
-) -> Result<impl IntoResponse, ResponseError> {
+pub(crate) async fn response_convert(resp: ResponseExt) -> Result<Response, ResponseError> {
+    let mut response = convert(resp).await?;
+    rewrite_headers(response.headers_mut());
+    Ok(response)
+}
+
+/// Strip and rewrite response headers
+fn rewrite_headers(headers: &mut HeaderMap) {
+    let ctx = with_context!();
+
+    for name in ctx.strip_response_headers() {
+        headers.remove(name);
+    }
+
+    for (name, value) in ctx.rewrite_response_headers() {
+        headers.insert(name.clone(), value.clone());
+    }
+}
+
+/// Hop-by-hop headers, only meaningful for the upstream connection
+const HOP_BY_HOP_HEADERS: [header::HeaderName; 6] = [
+    header::CONNECTION,
+    header::TRANSFER_ENCODING,
+    header::TE,
+    header::TRAILER,
+    header::UPGRADE,
+    header::PROXY_AUTHENTICATE,
+];
+
+/// Copy the upstream response headers except for "set-cookie", "content-length" and the hop-by-hop headers,
+/// the client connection semantics are handled by the server
+fn copy_headers(
+    mut builder: axum::http::response::Builder,
+    headers: &HeaderMap,
+) -> axum::http::response::Builder {
+    // headers listed in the upstream "connection" header are hop-by-hop too
+    let connection_headers = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    for (name, value) in headers.into_iter().filter(|(k, _)| {
+        k.ne(&header::SET_COOKIE)
+            && k.ne(&header::CONTENT_LENGTH)
+            && !HOP_BY_HOP_HEADERS.contains(k)
+            && k.as_str() != "keep-alive"
+            && !connection_headers.iter().any(|name| name == k.as_str())
+    }) {
+        builder = builder.header(name, value);
+    }
+    builder
+}
+
+async fn convert(resp: ResponseExt) -> Result<Response, ResponseError> {
     // If to api is some, then convert to api response
     if resp.context.is_some() {
         return Ok(toapi::response_convert(resp).await?.into_response());
     }
 
+    // Reject the declared body past the limit before streaming
+    if let (Some(max), Some(len)) = (
+        with_context!(max_upstream_body_size),
+        resp.inner.content_length(),
+    ) {
+        if len > max {
+            return Err(ResponseError::BadGateway(ProxyError::UpstreamBodyTooLarge(
+                max,
+            )));
+        }
+    }
+
     // Build new response
     let mut builder = Response::builder()
         .status(resp.inner.status())
         .header(NINJA_VERSION, LIB_VERSION);
 
-    // Copy headers except for "set-cookie"
-    for kv in resp
-        .inner
-        .headers()
-        .into_iter()
-        .filter(|(k, _)| k.ne(&header::SET_COOKIE) && k.ne(&header::CONTENT_LENGTH))
-    {
-        builder = builder.header(kv.0, kv.1);
-    }
+    // Copy headers except for "set-cookie", e.g. the `x-ratelimit-*` headers are forwarded unchanged
+    builder = copy_headers(builder, resp.inner.headers());
 
     // Filter and transform cookies
     for cookie in resp.inner.cookies() {
@@ -93,10 +155,212 @@ pub(crate) async fn response_convert(
             .map_err(ResponseError::InternalServerError)?
             .into_response())
     } else {
-        // Non-files endpoint handling
+        // Non-files endpoint handling, the event streams end with an error event on failure
+        let sse = resp
+            .inner
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map_or(false, |value| value.starts_with("text/event-stream"));
+        let body = match with_context!(read_idle_timeout) {
+            Some(idle_timeout) => {
+                // Abort the stream if no data arrives within the idle timeout
+                let stream =
+                    resp.inner
+                        .bytes_stream()
+                        .timeout(idle_timeout)
+                        .map(|item| match item {
+                            Ok(chunk) => chunk.map_err(BoxError::from),
+                            Err(elapsed) => {
+                                warn!("Response body read idle timeout: {elapsed}");
+                                Err(BoxError::from(elapsed))
+                            }
+                        });
+                limited_body(stream, sse)
+            }
+            None => limited_body(resp.inner.bytes_stream(), sse),
+        };
+
         Ok(builder
-            .body(StreamBody::new(resp.inner.bytes_stream()))
+            .body(StreamBody::new(body))
             .map_err(ResponseError::InternalServerError)?
             .into_response())
     }
 }
+
+/// Wrap the upstream body stream, abort the stream past the body size limit.
+/// The event stream ends with an error event instead of a broken connection
+fn limited_body<S, E>(stream: S, sse: bool) -> Body
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<BoxError> + 'static,
+{
+    let max = with_context!(max_upstream_body_size);
+    let mut read = 0u64;
+    let stream = stream.map(move |item| {
+        let chunk = item.map_err(Into::into)?;
+        read += chunk.len() as u64;
+        match max {
+            Some(max) if read > max => {
+                warn!("Response body exceeds the limit: {max} bytes");
+                Err(BoxError::from(ProxyError::UpstreamBodyTooLarge(max)))
+            }
+            _ => Ok(chunk),
+        }
+    });
+
+    if sse {
+        Body::wrap_stream(sse_terminated(stream))
+    } else {
+        Body::wrap_stream(stream)
+    }
+}
+
+/// Hold the guard (the client permit of the request) until the streaming response body ends
+pub(crate) fn hold_until_body_end<G>(response: Response, guard: G) -> Response
+where
+    G: Send + 'static,
+{
+    let (parts, body) = response.into_parts();
+    if body.size_hint().exact().is_some() {
+        return Response::from_parts(parts, body);
+    }
+
+    let stream = futures::stream::unfold((body, guard), |(mut body, guard)| async move {
+        body.data().await.map(|chunk| (chunk, (body, guard)))
+    });
+    Response::from_parts(parts, axum::body::boxed(StreamBody::new(stream)))
+}
+
+/// End the event stream with a well-formed error event and `[DONE]` on the first upstream error,
+/// so that the SDK clients detect the interrupted generation
+fn sse_terminated<S>(stream: S) -> impl Stream<Item = Result<Bytes, BoxError>>
+where
+    S: Stream<Item = Result<Bytes, BoxError>> + Send + 'static,
+{
+    futures::stream::unfold(Some(Box::pin(stream)), |stream| async move {
+        let mut stream = stream?;
+        match stream.next().await? {
+            Ok(chunk) => Some((Ok(chunk), Some(stream))),
+            Err(err) => {
+                warn!("Upstream event stream failed mid-way: {err}");
+                let event = serde_json::json!({
+                    "error": {
+                        "message": err.to_string(),
+                        "type": "upstream_error",
+                    }
+                });
+                // the upstream may fail mid-event, end the partial event before the error event
+                let bytes = format!("\n\ndata: {event}\n\ndata: [DONE]\n\n");
+                Some((Ok(Bytes::from(bytes)), None))
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy_headers, sse_terminated};
+    use axum::body::Bytes;
+    use axum::http::{header, HeaderMap, HeaderValue, Response};
+    use axum::BoxError;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_sse_terminated_on_error() {
+        let upstream = tokio_stream::iter(vec![
+            Ok(Bytes::from_static(b"data: 1\n\n")),
+            Err(BoxError::from("connection reset")),
+            Ok(Bytes::from_static(b"data: 2\n\n")),
+        ]);
+        let chunks = sse_terminated(upstream)
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0], "data: 1\n\n");
+        assert_eq!(
+            chunks[1],
+            "\n\ndata: {\"error\":{\"message\":\"connection reset\",\"type\":\"upstream_error\"}}\n\ndata: [DONE]\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sse_terminated_mid_event() {
+        let upstream = tokio_stream::iter(vec![
+            Ok(Bytes::from_static(b"data: {\"partial\"")),
+            Err(BoxError::from("connection reset")),
+        ]);
+        let body = sse_terminated(upstream)
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .await
+            .concat();
+        let body = String::from_utf8(body).unwrap();
+        // the partial event is ended, the error event and `[DONE]` are dispatched on their own
+        let events = body.split("\n\n").collect::<Vec<_>>();
+        assert!(events.contains(&"data: [DONE]"));
+        assert!(events.iter().any(|e| e.starts_with("data: {\"error\"")));
+    }
+
+    #[test]
+    fn test_copy_headers_forwards_rate_limit() {
+        let rate_limit = [
+            ("x-ratelimit-limit-requests", "5000"),
+            ("x-ratelimit-limit-tokens", "160000"),
+            ("x-ratelimit-remaining-requests", "4999"),
+            ("x-ratelimit-remaining-tokens", "159976"),
+            ("x-ratelimit-reset-requests", "12ms"),
+            ("x-ratelimit-reset-tokens", "9ms"),
+        ];
+
+        let mut upstream = HeaderMap::new();
+        for (name, value) in rate_limit {
+            upstream.insert(name, HeaderValue::from_static(value));
+        }
+        upstream.insert(header::SET_COOKIE, HeaderValue::from_static("a=b"));
+        upstream.insert(header::CONTENT_LENGTH, HeaderValue::from_static("2"));
+
+        let response = copy_headers(Response::builder(), &upstream)
+            .body(())
+            .unwrap();
+        let headers = response.headers();
+        for (name, value) in rate_limit {
+            assert_eq!(headers.get(name).unwrap(), value);
+        }
+        assert!(headers.get(header::SET_COOKIE).is_none());
+        assert!(headers.get(header::CONTENT_LENGTH).is_none());
+    }
+
+    #[test]
+    fn test_copy_headers_strips_hop_by_hop() {
+        let mut upstream = HeaderMap::new();
+        upstream.insert(
+            header::CONNECTION,
+            HeaderValue::from_static("keep-alive, x-hop"),
+        );
+        upstream.insert("keep-alive", HeaderValue::from_static("timeout=5"));
+        upstream.insert("x-hop", HeaderValue::from_static("1"));
+        upstream.insert(
+            header::TRANSFER_ENCODING,
+            HeaderValue::from_static("chunked"),
+        );
+        upstream.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/event-stream"),
+        );
+
+        let response = copy_headers(Response::builder(), &upstream)
+            .body(())
+            .unwrap();
+        let headers = response.headers();
+        assert!(headers.get(header::CONNECTION).is_none());
+        assert!(headers.get("keep-alive").is_none());
+        assert!(headers.get("x-hop").is_none());
+        assert!(headers.get(header::TRANSFER_ENCODING).is_none());
+        assert_eq!(
+            headers.get(header::CONTENT_TYPE).unwrap(),
+            "text/event-stream"
+        );
+    }
+}
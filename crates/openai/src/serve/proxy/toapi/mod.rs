@@ -10,13 +10,16 @@ use axum::{
 use eventsource_stream::Eventsource;
 use reqwest::StatusCode;
 use std::str::FromStr;
+use tracing::Instrument;
 
 use crate::arkose::ArkoseContext;
 use crate::chatgpt::model::req::Metadata;
 use crate::chatgpt::model::Role;
+use crate::context::metrics::Latency;
 use crate::gpt_model::GPTModel;
 use crate::now_duration;
 use crate::serve::error::ProxyError;
+use crate::serve::middleware::budget;
 use crate::serve::ProxyResult;
 use crate::token;
 use crate::{
@@ -36,6 +39,7 @@ use crate::{
     uuid::uuid,
 };
 
+use super::error_cache;
 use super::ext::{Context, RequestExt, ResponseExt};
 use super::header_convert;
 use crate::URL_CHATGPT_API;
@@ -59,6 +63,9 @@ pub(super) fn support(req: &RequestExt) -> bool {
 
 /// Send request to ChatGPT API
 pub(super) async fn send_request(req: RequestExt) -> Result<ResponseExt, ResponseError> {
+    // Fail fast while the upstream is cooling down, before the arkose solve
+    error_cache::check(URL_CHATGPT_API)?;
+
     // Exstract the token from the Authorization header
     let baerer = req
         .bearer_auth()
@@ -97,26 +104,34 @@ pub(super) async fn send_request(req: RequestExt) -> Result<ResponseExt, Respons
     }
 
     // Request client
-    let client = with_context!(api_client);
+    let client = with_context!(api_client).await?;
 
     // OpenAI API to ChatGPT API model mapper
     let gpt_model = GPTModel::from_str(&body.model)?;
 
     // check if arkose token is required
-    let arkose_token: Option<String> =
-        if (with_context!(arkose_gpt3_experiment) && gpt_model.is_gpt3()) || gpt_model.is_gpt4() {
-            let arkose_token = ArkoseToken::new_from_context(
+    // the configured model map takes precedence
+    let typed = with_context!(model_arkose_type, &body.model).or_else(|| {
+        ((with_context!(arkose_gpt3_experiment) && gpt_model.is_gpt3()) || gpt_model.is_gpt4())
+            .then(|| gpt_model.clone().into())
+    });
+    let arkose_token: Option<String> = if let Some(typed) = typed {
+        let arkose_token = budget::step(
+            Latency::Solve,
+            ArkoseToken::new_from_context(
                 ArkoseContext::builder()
                     .client(client.clone())
-                    .typed(gpt_model.clone().into())
+                    .typed(typed)
                     .identifier(Some(baerer.to_owned()))
                     .build(),
             )
-            .await?;
-            Some(arkose_token.into())
-        } else {
-            None
-        };
+            .instrument(tracing::info_span!("arkose_solve", arkose.type = %typed)),
+        )
+        .await??;
+        Some(arkose_token.into())
+    } else {
+        None
+    };
 
     // Create request
     let parent_message_id = uuid();
@@ -147,11 +162,9 @@ pub(super) async fn send_request(req: RequestExt) -> Result<ResponseExt, Respons
     }
 
     // Send request
-    let resp = builder
-        .json(&req_body)
-        .send()
-        .await
-        .map_err(ResponseError::InternalServerError)?;
+    let resp = budget::step(Latency::Upstream, builder.json(&req_body).send()).await?;
+    error_cache::record(URL_CHATGPT_API, &resp);
+    let resp = resp.map_err(ResponseError::InternalServerError)?;
 
     Ok(ResponseExt::builder()
         .inner(resp)
@@ -0,0 +1,128 @@
+use crate::{info, warn};
+use std::net::{SocketAddr, TcpListener};
+#[cfg(target_family = "unix")]
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+/// Environment variable of the listening socket FDs inherited from the previous process, format: fd1,fd2
+#[cfg(target_family = "unix")]
+const LISTEN_FDS_ENV: &str = "NINJA_LISTEN_FDS";
+
+/// Bind the listeners of the bind addresses, reuse the listening sockets inherited from the
+/// previous process on graceful restart
+pub(super) fn bind_listeners(
+    binds: &[SocketAddr],
+    graceful_restart: bool,
+) -> anyhow::Result<Vec<TcpListener>> {
+    let mut inherited = if graceful_restart {
+        inherited_listeners()
+    } else {
+        vec![]
+    };
+
+    let mut listeners = Vec::with_capacity(binds.len());
+    for bind in binds {
+        let listener = match inherited
+            .iter()
+            .position(|listener| listener.local_addr().ok().as_ref() == Some(bind))
+        {
+            Some(index) => {
+                info!("Inherited HTTP(S) listener at {bind}");
+                inherited.swap_remove(index)
+            }
+            None => TcpListener::bind(bind)?,
+        };
+        listener.set_nonblocking(true)?;
+        listeners.push(listener);
+    }
+
+    // The inherited listeners of the removed bind addresses are closed on drop
+    Ok(listeners)
+}
+
+/// Take the listening sockets inherited from the previous process. The environment is left
+/// untouched, it is not safe to mutate once the runtime threads are running; the sockets are
+/// marked close-on-exec instead so the processes spawned later don't inherit them, and the
+/// next re-exec overrides the variable
+#[cfg(target_family = "unix")]
+fn inherited_listeners() -> Vec<TcpListener> {
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+    let fds = match std::env::var(LISTEN_FDS_ENV) {
+        Ok(fds) => fds,
+        Err(_) => return vec![],
+    };
+
+    fds.split(',')
+        .filter_map(|fd| fd.trim().parse::<RawFd>().ok())
+        .filter(
+            |fd| match fcntl(*fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)) {
+                Ok(_) => true,
+                Err(err) => {
+                    warn!("Inherited listener fd {fd} is invalid: {err}");
+                    false
+                }
+            },
+        )
+        // SAFETY: the FDs are the listening sockets passed by the previous process
+        .map(|fd| unsafe { TcpListener::from_raw_fd(fd) })
+        .collect()
+}
+
+#[cfg(not(target_family = "unix"))]
+fn inherited_listeners() -> Vec<TcpListener> {
+    warn!("Graceful restart is only supported on unix");
+    vec![]
+}
+
+/// Re-exec the current binary with the same arguments, passing the listening sockets,
+/// return the pid of the new process
+#[cfg(target_family = "unix")]
+pub(super) fn reexec(listeners: &[TcpListener]) -> anyhow::Result<u32> {
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+    use std::os::unix::process::CommandExt;
+
+    let fds = listeners
+        .iter()
+        .map(|listener| listener.as_raw_fd())
+        .collect::<Vec<_>>();
+
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command.args(std::env::args_os().skip(1)).env(
+        LISTEN_FDS_ENV,
+        fds.iter()
+            .map(|fd| fd.to_string())
+            .collect::<Vec<_>>()
+            .join(","),
+    );
+
+    // SAFETY: only fcntl is called between fork and exec, it is async-signal-safe
+    unsafe {
+        command.pre_exec(move || {
+            for fd in fds.iter() {
+                fcntl(*fd, FcntlArg::F_SETFD(FdFlag::empty()))?;
+            }
+            Ok(())
+        });
+    }
+
+    let child = command.spawn()?;
+    Ok(child.id())
+}
+
+/// Re-exec on SIGUSR2, then drain the connections of the current process
+#[cfg(target_family = "unix")]
+pub(super) async fn restart_on_sigusr2(handle: axum_server::Handle, listeners: Vec<TcpListener>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigusr2 = signal(SignalKind::user_defined2()).expect("SIGUSR2 signal hanlde error");
+    while sigusr2.recv().await.is_some() {
+        match reexec(&listeners) {
+            Ok(pid) => {
+                info!("SIGUSR2 received: restarted with pid {pid}");
+                super::signal::sending_graceful_shutdown_signal(handle, "SIGUSR2").await;
+                return;
+            }
+            Err(err) => warn!("Failed to restart: {err}"),
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use crate::arkose::{self, ArkoseContext, ArkoseToken};
+use crate::client::ProxyStat;
+use crate::context::args::Args;
+use crate::context::arkose::har;
+use crate::context::init;
+use crate::context::metrics;
+use crate::context::reload::ReloadSource;
+use crate::context::Maintenance;
+use crate::serve::error::{ProxyError, ResponseError};
+use crate::serve::middleware::admin::admin_ip_middleware;
+use crate::serve::middleware::replica::replica_middleware;
+use crate::{warn, with_context};
+use axum::extract::Query;
+use axum::headers::authorization::Bearer;
+use axum::headers::Authorization;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{get, post, MethodRouter};
+use axum::{Json, Router, TypedHeader};
+use std::str::FromStr;
+use std::time::Instant;
+
+/// Default maintenance response status
+const MAINTENANCE_STATUS: u16 = 503;
+/// Default maintenance response message
+const MAINTENANCE_MESSAGE: &str = "Service under maintenance";
+
+pub(super) fn config(router: Router, args: &Args) -> Router {
+    // Fail closed, the admin endpoints change the server state and are never exposed unprotected
+    if args.auth_key.is_none() && args.admin_ip_allowlist.is_empty() {
+        warn!(
+            "Admin endpoints are disabled, set --auth-key or --admin-ip-allowlist to enable them"
+        );
+        return router;
+    }
+
+    let routes: [(&str, MethodRouter); 12] = [
+        ("maintenance", post(post_maintenance)),
+        ("drain", post(post_drain)),
+        ("proxies", get(get_proxies)),
+        ("metrics", get(get_metrics)),
+        ("metrics/prometheus", get(get_metrics_prometheus)),
+        ("load", get(get_load)),
+        ("reload/status", get(get_reload_status)),
+        ("config", get(get_config)),
+        ("arkose/test", post(post_arkose_test)),
+        ("har/refresh", post(post_har_refresh)),
+        ("har/status", get(get_har_status)),
+        ("arkose/status", get(get_arkose_status)),
+    ];
+
+    // Only the listed endpoints are routed if configured, the disabled ones respond 404
+    let enabled = args.admin_endpoints_enabled.as_ref().map(|endpoints| {
+        endpoints
+            .iter()
+            .map(|endpoint| {
+                endpoint
+                    .trim()
+                    .trim_start_matches("/admin/")
+                    .trim_matches('/')
+            })
+            .collect::<Vec<_>>()
+    });
+    if let Some(enabled) = enabled.as_ref() {
+        enabled
+            .iter()
+            .filter(|endpoint| !routes.iter().any(|(path, _)| path == *endpoint))
+            .for_each(|endpoint| warn!("Unknown admin endpoint `{endpoint}` is ignored"));
+    }
+
+    let routes = routes
+        .into_iter()
+        .filter(|(path, _)| enabled.as_ref().map_or(true, |e| e.contains(path)))
+        .collect::<Vec<_>>();
+    if routes.is_empty() {
+        return router;
+    }
+
+    let admin = routes
+        .into_iter()
+        .fold(Router::new(), |admin, (path, route)| {
+            admin.route(&format!("/admin/{path}"), route)
+        });
+    router.merge(
+        admin
+            .route_layer(axum::middleware::from_fn(replica_middleware))
+            .route_layer(axum::middleware::from_fn(admin_ip_middleware)),
+    )
+}
+
+/// Check the admin auth key, return the authenticated key
+fn check_auth_key(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<Option<&'static str>, ResponseError> {
+    if let Some(auth_key) = with_context!(auth_key) {
+        // check bearer token exist
+        let bearer =
+            bearer.ok_or_else(|| ResponseError::Unauthorized(ProxyError::AuthKeyRequired))?;
+        if auth_key.ne(bearer.token()) {
+            return Err(ResponseError::Forbidden(ProxyError::AuthKeyError));
+        }
+        return Ok(Some(auth_key));
+    }
+    Ok(None)
+}
+
+#[derive(serde::Deserialize)]
+struct MaintenanceRequest {
+    enabled: bool,
+    status: Option<u16>,
+    message: Option<String>,
+}
+
+/// POST /admin/maintenance
+/// Toggle maintenance mode, the proxy requests are rejected with the status and message
+async fn post_maintenance(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    Json(body): Json<MaintenanceRequest>,
+) -> Result<Json<Option<Maintenance>>, ResponseError> {
+    let key = check_auth_key(bearer)?;
+
+    let result = if body.enabled {
+        let status = body.status.unwrap_or(MAINTENANCE_STATUS);
+        StatusCode::from_u16(status)
+            .map(|_| {
+                with_context!().set_maintenance(Some(Maintenance {
+                    status,
+                    message: body
+                        .message
+                        .clone()
+                        .unwrap_or_else(|| MAINTENANCE_MESSAGE.to_owned()),
+                }))
+            })
+            .map_err(|err| anyhow::anyhow!(err))
+    } else {
+        with_context!().set_maintenance(None);
+        Ok(())
+    };
+
+    with_context!(audit_log).record(
+        key,
+        "maintenance",
+        serde_json::json!({
+            "enabled": body.enabled,
+            "status": body.status,
+            "message": body.message,
+        }),
+        &result,
+    );
+
+    result.map_err(ResponseError::BadRequest)?;
+    Ok(Json(with_context!(maintenance)))
+}
+
+#[derive(serde::Deserialize)]
+struct DrainRequest {
+    enabled: bool,
+}
+
+/// POST /admin/drain
+/// Toggle drain mode, the new proxy requests are rejected with 503 while the in-flight ones complete
+async fn post_drain(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    Json(body): Json<DrainRequest>,
+) -> Result<Json<serde_json::Value>, ResponseError> {
+    let key = check_auth_key(bearer)?;
+    with_context!().set_drained(body.enabled);
+    with_context!(audit_log).record(
+        key,
+        "drain",
+        serde_json::json!({ "enabled": body.enabled }),
+        &Ok::<_, anyhow::Error>(()),
+    );
+    Ok(Json(serde_json::json!({
+        "drained": with_context!(is_drained),
+        "in_flight": with_context!(metrics).in_flight(),
+    })))
+}
+
+/// GET /admin/proxies
+/// List the client proxies with the request stats
+async fn get_proxies(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<Json<Vec<ProxyStat>>, ResponseError> {
+    check_auth_key(bearer)?;
+    Ok(Json(with_context!(proxy_stats)))
+}
+
+/// GET /admin/metrics
+/// Server metrics, arkose solver success rate and the client proxy stats
+async fn get_metrics(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<Json<serde_json::Value>, ResponseError> {
+    check_auth_key(bearer)?;
+    let ctx = with_context!();
+    let solver_success_rate = init::HAR_TYPES
+        .into_iter()
+        .map(|(t, _)| (t.to_string(), ctx.solver_success_rate(t)))
+        .collect::<std::collections::HashMap<_, _>>();
+
+    Ok(Json(serde_json::json!({
+        "metrics": ctx.metrics().snapshot(),
+        "solver_success_rate": solver_success_rate,
+        "solver_solved_by": ctx.solver_stats().solved_by(),
+        "solver_solved_by_difficulty": ctx.solver_stats().solved_by_difficulty(),
+        "preauth_cookies": ctx.preauth_cookie_count(),
+        "proxies": ctx.proxy_stats(),
+    })))
+}
+
+/// GET /admin/metrics/prometheus
+/// Server metrics and the latency quantiles in the Prometheus text format
+async fn get_metrics_prometheus(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<impl IntoResponse, ResponseError> {
+    check_auth_key(bearer)?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        with_context!(metrics).prometheus(),
+    ))
+}
+
+/// GET /admin/load
+/// In-flight requests and active connections against the concurrent limit
+async fn get_load(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<Json<serde_json::Value>, ResponseError> {
+    check_auth_key(bearer)?;
+    let ctx = with_context!();
+    Ok(Json(serde_json::json!({
+        "in_flight": ctx.metrics().in_flight(),
+        "connections": ctx.metrics().connections(),
+        "concurrent_limit": ctx.concurrent_limit(),
+        "drained": ctx.is_drained(),
+    })))
+}
+
+/// GET /admin/reload/status
+/// Last reload time, trigger source and result of each subsystem
+async fn get_reload_status(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<Json<serde_json::Value>, ResponseError> {
+    check_auth_key(bearer)?;
+    let subsystems = with_context!(reload_status).subsystems();
+    let last = subsystems
+        .iter()
+        .max_by_key(|(_, result)| result.timestamp)
+        .map(|(subsystem, result)| {
+            serde_json::json!({
+                "subsystem": subsystem,
+                "timestamp": result.timestamp,
+                "source": result.source,
+                "success": result.success,
+            })
+        });
+    Ok(Json(serde_json::json!({
+        "last": last,
+        "subsystems": subsystems,
+    })))
+}
+
+/// GET /admin/config
+/// Effective runtime config, the resolved HAR directory of each type
+async fn get_config(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<Json<serde_json::Value>, ResponseError> {
+    check_auth_key(bearer)?;
+    Ok(Json(serde_json::json!({
+        "har_dir": har::har_dirs(),
+    })))
+}
+
+/// GET /admin/har/status
+/// Age of the HAR files of each type, the stale ones are older than `har_max_age`
+async fn get_har_status(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<Json<serde_json::Value>, ResponseError> {
+    check_auth_key(bearer)?;
+    Ok(Json(serde_json::json!({
+        "har_max_age": with_context!(har_max_age),
+        "har": har::har_status(),
+    })))
+}
+
+#[derive(serde::Deserialize)]
+struct TypeQuery {
+    #[serde(rename = "type")]
+    typed: String,
+}
+
+/// POST /admin/arkose/test?type=chat4
+/// Run the full arkose solve path of the type once, return the result and timing.
+/// The probe is not recorded in the solver stats
+async fn post_arkose_test(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    Query(query): Query<TypeQuery>,
+) -> Result<Json<serde_json::Value>, ResponseError> {
+    check_auth_key(bearer)?;
+    let typed = arkose::Type::from_str(&query.typed).map_err(ResponseError::BadRequest)?;
+
+    let arkose_client = with_context!(arkose_client).await?;
+    let start = Instant::now();
+    let result = metrics::unrecorded_scope(ArkoseToken::new_from_context(
+        ArkoseContext::builder()
+            .client(arkose_client.client())
+            .typed(typed)
+            .identifier(None)
+            .build(),
+    ))
+    .await;
+    let elapsed_ms = start.elapsed().as_millis() as u64;
+
+    Ok(Json(match result {
+        Ok(token) => serde_json::json!({
+            "type": typed,
+            "success": token.success(),
+            "elapsed_ms": elapsed_ms,
+        }),
+        Err(err) => serde_json::json!({
+            "type": typed,
+            "success": false,
+            "elapsed_ms": elapsed_ms,
+            "error": err.to_string(),
+        }),
+    }))
+}
+
+/// GET /admin/arkose/status
+/// HAR, endpoint and solver status of each arkose type in one call, the secrets are masked
+async fn get_arkose_status(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<Json<serde_json::Value>, ResponseError> {
+    check_auth_key(bearer)?;
+    let ctx = with_context!();
+    let har_dirs = har::har_dirs();
+    let har_status = har::har_status();
+    let solver_chain = ctx
+        .arkose_solver_chain()
+        .iter()
+        .enumerate()
+        .map(|(index, solver)| {
+            serde_json::json!({
+                "solver": format!("{}#{index}", solver.solver),
+                "endpoint": mask_url(solver.endpoint()),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let types = init::HAR_TYPES
+        .iter()
+        .map(|(typed, name)| {
+            let files = har_status.get(*name).map(Vec::as_slice).unwrap_or_default();
+            let status = serde_json::json!({
+                "har": {
+                    "dir": har_dirs.get(*name),
+                    "files": files.len(),
+                    // At least one HAR file is fresh enough to serve
+                    "state": files.iter().any(|f| !f.stale),
+                    "age": files.iter().map(|f| f.age).min(),
+                },
+                "endpoint": ctx.arkose_endpoint(*typed).map(mask_url),
+                "solver": {
+                    "route": ctx.arkose_solver_route(*typed).map(|route| serde_json::json!({
+                        "max_waves": route.max_waves,
+                        "solver_index": route.solver_index,
+                    })),
+                    "success_rate": ctx.solver_stats().success_rate(*typed),
+                    "degraded": ctx.solver_stats().is_degraded(*typed),
+                },
+                "warm_pool": ctx.arkose_warm_pool(*typed).map(|pool| pool.len()),
+            });
+            (name.to_string(), status)
+        })
+        .collect::<serde_json::Map<_, _>>();
+
+    Ok(Json(serde_json::json!({
+        "har_max_age": ctx.har_max_age(),
+        "solver_chain": solver_chain,
+        "types": types,
+    })))
+}
+
+/// Mask the credentials of the url
+fn mask_url(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(mut url) => {
+            if url.password().is_some() {
+                let _ = url.set_password(Some("***"));
+            }
+            if url.query().is_some() {
+                url.set_query(Some("***"));
+            }
+            url.to_string()
+        }
+        Err(_) => "***".to_owned(),
+    }
+}
+
+/// POST /admin/har/refresh?type=chat4
+/// Re-fetch and re-validate the HAR files of the type from the store, return the valid entry count
+async fn post_har_refresh(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    Query(query): Query<TypeQuery>,
+) -> Result<Json<serde_json::Value>, ResponseError> {
+    let key = check_auth_key(bearer)?;
+    let typed = arkose::Type::from_str(&query.typed).map_err(ResponseError::BadRequest)?;
+
+    let result = tokio::task::spawn_blocking(move || har::refresh(&typed))
+        .await
+        .map_err(ResponseError::InternalServerError)?;
+
+    with_context!(reload_status).record(format!("har:{typed}"), ReloadSource::Api, &result);
+    with_context!(audit_log).record(
+        key,
+        "har_refresh",
+        serde_json::json!({ "type": typed }),
+        &result.as_ref().map(|_| ()),
+    );
+
+    let entries = result.map_err(ResponseError::BadRequest)?;
+    Ok(Json(serde_json::json!({
+        "type": typed,
+        "entries": entries,
+    })))
+}
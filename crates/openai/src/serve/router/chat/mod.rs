@@ -42,7 +42,8 @@ use crate::constant::PICTURE;
 use crate::constant::SITE_KEY;
 use crate::constant::SUPPORT_APPLE;
 use crate::constant::USERNAME;
-use crate::context::args::Args;
+use crate::context::args::{Args, RootResponse};
+use crate::serve::client_ip::client_ip;
 use crate::serve::error::ProxyError;
 use crate::serve::error::ResponseError;
 use crate::serve::middleware::csrf;
@@ -94,7 +95,7 @@ pub(super) fn config(router: Router, args: &Args) -> Router {
     };
 
     // Configure the UI routing
-    router
+    let router = router
         .route("/auth/login", get(login_index))
         .route(
             "/auth/login",
@@ -109,7 +110,6 @@ pub(super) fn config(router: Router, args: &Args) -> Router {
         .route("/auth/logout", get(logout))
         .route("/auth/session", get(session))
         .route("/auth/me", get(auth_me))
-        .route("/", get(chat))
         .route("/c", get(chat))
         .route("/c/:conversation_id", get(chat))
         .route("/chat", any(redirect_to_home))
@@ -142,7 +142,13 @@ pub(super) fn config(router: Router, args: &Args) -> Router {
         .route("/ulp/*path", get(get_static_resource))
         .route("/sweetalert2/*path", get(get_static_resource))
         // 404 endpoint
-        .fallback(error_404)
+        .fallback(error_404);
+
+    // The root is the chat page unless a root response is configured
+    match args.root_response {
+        RootResponse::Default => router.route("/", get(chat)),
+        _ => router,
+    }
 }
 
 /// Forwards the request to the auth provider
@@ -168,8 +174,9 @@ async fn login_index(token: CsrfToken) -> Result<impl IntoResponse, ResponseErro
 /// Login from username and password
 async fn login(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     token: CsrfToken,
-    account: axum::Form<AuthAccount>,
+    mut account: axum::Form<AuthAccount>,
 ) -> Result<impl IntoResponse, ResponseError> {
     // Check input username and password
     let err_handler = |err_msg: String| -> Result<Response<Body>, ResponseError> {
@@ -189,16 +196,25 @@ async fn login(
     };
 
     // Check if the request is in the turnstile
-    if let Some(err) =
-        turnstile::cf_turnstile_check(addr.ip(), account.cf_turnstile_response.as_deref())
-            .await
-            .map_err(|err| err_handler(err.to_string()))
-            .err()
+    if let Some(err) = turnstile::cf_turnstile_check(
+        client_ip(addr.ip(), &headers),
+        account.cf_turnstile_response.as_deref(),
+    )
+    .await
+    .map_err(|err| err_handler(err.to_string()))
+    .err()
     {
         return Ok(err.into_response());
     };
 
-    match with_context!(auth_client).do_access_token(&account).await {
+    #[cfg(feature = "preauth")]
+    crate::serve::preauth::select_strategy(&mut account, &headers);
+
+    match with_context!(auth_client)
+        .await?
+        .do_access_token(&account)
+        .await
+    {
         Ok(access_token) => {
             // Build session
             let session = Session::from(
@@ -251,6 +267,7 @@ async fn login_token(
         // Refresh token
         s if s.len() > 40 && s.len() < 100 => {
             let refresh_token = with_context!(auth_client)
+                .await?
                 .do_refresh_token(access_token)
                 .await
                 .map_err(ResponseError::BadRequest)?;
@@ -265,6 +282,7 @@ async fn login_token(
                 return Err(ResponseError::TempporaryRedirect(LOGIN_INDEX));
             }
             let access_token = with_context!(auth_client)
+                .await?
                 .refresh_session(access_token)
                 .await
                 .map_err(ResponseError::BadRequest)?;
@@ -322,6 +340,7 @@ async fn session(mut s: SessionExt) -> Result<Response<Body>, ResponseError> {
     // Refresh session
     let new_session = if let Some(session_token) = s.session_token.as_ref() {
         match with_context!(auth_client)
+            .await?
             .refresh_session(session_token)
             .await
         {
@@ -330,6 +349,7 @@ async fn session(mut s: SessionExt) -> Result<Response<Body>, ResponseError> {
         }
     } else if let Some(refresh_token) = s.session.refresh_token.as_ref() {
         match with_context!(auth_client)
+            .await?
             .do_refresh_token(&refresh_token)
             .await
         {
@@ -381,6 +401,7 @@ fn create_response_from_session(s: Session) -> Result<Response<Body>, ResponseEr
 /// Get auth me
 async fn auth_me(headers: HeaderMap, jar: CookieJar) -> Result<impl IntoResponse, ResponseError> {
     let resp = with_context!(api_client)
+        .await?
         .get(format!("{URL_CHATGPT_API}/backend-api/me"))
         .headers(header_convert(&headers, &jar, URL_CHATGPT_API)?)
         .send()
@@ -449,6 +470,7 @@ async fn share_chat(
 ) -> Result<Response<Body>, ResponseError> {
     let share_id = share_id.0;
     let resp = with_context!(api_client)
+        .await?
         .get(format!("{URL_CHATGPT_API}/backend-api/share/{share_id}"))
         .headers(header_convert(
             &extract.headers,
@@ -494,6 +516,7 @@ async fn share_chat_info(
 ) -> Result<Response<Body>, ResponseError> {
     let share_id = share_id.0.replace(".json", EMPTY);
     let resp = with_context!(api_client)
+        .await?
         .get(format!("{URL_CHATGPT_API}/backend-api/share/{share_id}"))
         .headers(header_convert(
             &extract.headers,
@@ -548,6 +571,7 @@ async fn share_chat_continue_info(
     s: SessionExt,
 ) -> Result<Response<Body>, ResponseError> {
     let resp = with_context!(api_client)
+        .await?
         .get(format!(
             "{URL_CHATGPT_API}/backend-api/share/{}",
             share_id.0
@@ -647,6 +671,6 @@ fn settings_template_data(ctx: &mut tera::Context) {
 
     // If the arkose endpoint is not empty, well enable the arkose captcha
     context
-        .arkose_endpoint()
+        .arkose_endpoint(crate::arkose::Type::Auth)
         .map(|arkose_endpoint| ctx.insert(ARKOSE_ENDPOINT, arkose_endpoint));
 }
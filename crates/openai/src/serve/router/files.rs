@@ -4,8 +4,10 @@ use axum::{response::IntoResponse, routing::any, Router};
 use crate::{
     context::args::Args,
     serve::{
-        error::ResponseError, proxy::ext::RequestExt, proxy::ext::SendRequestExt,
-        proxy::resp::response_convert,
+        error::ResponseError,
+        proxy::ext::RequestExt,
+        proxy::ext::SendRequestExt,
+        proxy::resp::{hold_until_body_end, response_convert},
     },
     with_context,
 };
@@ -22,8 +24,9 @@ pub(super) fn config(router: Router, args: &Args) -> Router {
 async fn proxy(mut req: RequestExt) -> Result<impl IntoResponse, ResponseError> {
     req.trim_start_path("/files")?;
     req.append_haeder(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")?;
-    let resp = with_context!(api_client)
+    let client = with_context!(api_client).await?;
+    let resp = client
         .send_request("https://files.oaiusercontent.com", req)
         .await?;
-    response_convert(resp).await
+    Ok(hold_until_body_end(response_convert(resp).await?, client))
 }
@@ -3,6 +3,8 @@ mod token;
 use crate::context::args::Args;
 use crate::context::arkose::har;
 use crate::serve::error::{ProxyError, ResponseError};
+use crate::serve::middleware::admin::admin_ip_middleware;
+use crate::serve::middleware::replica::replica_middleware;
 use crate::{arkose, warn, with_context};
 use axum::body::Body;
 use axum::extract::{Multipart, Query};
@@ -27,12 +29,16 @@ const FAILED_UPLOAD_TITLE: &'static str = "Failed to upload file";
 const FAILED_AUTH_TITLE: &'static str = "Failed Authenticate";
 
 pub(super) fn config(router: Router, _: &Args) -> Router {
-    router
-        .route("/har/login", get(login).post(post_login))
-        .route("/har/upload", get(upload).post(post_upload))
-        .route("/har/list", get(get_files))
-        .route("/har/delete", post(delete_file))
-        .route("/har/rename", post(rename_file))
+    router.merge(
+        Router::new()
+            .route("/har/login", get(login).post(post_login))
+            .route("/har/upload", get(upload).post(post_upload))
+            .route("/har/list", get(get_files))
+            .route("/har/delete", post(delete_file))
+            .route("/har/rename", post(rename_file))
+            .route_layer(axum::middleware::from_fn(replica_middleware))
+            .route_layer(axum::middleware::from_fn(admin_ip_middleware)),
+    )
 }
 
 fn error_html(title: &str, error_message: &str, back: bool) -> Html<String> {
@@ -63,6 +69,45 @@ async fn check_session(jar: CookieJar) -> bool {
     false
 }
 
+/// Check the upload authorization, the bearer key takes precedence over the session.
+/// A type-scoped upload key only uploads its own type
+async fn check_upload(
+    jar: CookieJar,
+    bearer: Option<&str>,
+    _type: arkose::Type,
+) -> Result<bool, ResponseError> {
+    let key = match bearer {
+        Some(key) if with_context!(auth_key).is_some() => key,
+        _ => return Ok(check_session(jar).await),
+    };
+
+    if with_context!(auth_key) == Some(key) {
+        return Ok(true);
+    }
+    match with_context!(har_upload_key_type, key) {
+        Some(t) if t == _type => Ok(true),
+        Some(_) => Err(ResponseError::Forbidden(
+            ProxyError::HarUploadKeyTypeMismatch,
+        )),
+        None => Err(ResponseError::Forbidden(ProxyError::AuthKeyError)),
+    }
+}
+
+/// Audit the HAR management action, the session is authenticated by the auth key
+fn audit(action: &str, params: serde_json::Value, result: &anyhow::Result<()>) {
+    audit_with_key(with_context!(auth_key), action, params, result)
+}
+
+/// Audit the HAR management action of the key
+fn audit_with_key(
+    key: Option<&str>,
+    action: &str,
+    params: serde_json::Value,
+    result: &anyhow::Result<()>,
+) {
+    with_context!(audit_log).record(key, action, params, result);
+}
+
 /// Login page
 async fn login(jar: CookieJar) -> impl IntoResponse {
     if check_session(jar).await {
@@ -121,10 +166,12 @@ async fn upload(jar: CookieJar) -> impl IntoResponse {
 /// Upload file
 async fn post_upload(
     jar: CookieJar,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
     _type: TypedHeader<PlatformType>,
     mut multipart: Multipart,
 ) -> Result<impl IntoResponse, ResponseError> {
-    if !check_session(jar).await {
+    let bearer = bearer.as_ref().map(|bearer| bearer.token());
+    if !check_upload(jar, bearer, _type.0 .0).await? {
         return Ok(Redirect::temporary(LOGIN_PATH).into_response());
     }
 
@@ -153,19 +200,52 @@ async fn post_upload(
             .await
             .map_err(ResponseError::InternalServerError)?;
 
-        if let Some(err) = har::valid(&data).err() {
-            warn!("upload har file check error: {}", err);
-            return Ok(error_html(
-                FAILED_UPLOAD_TITLE,
-                "The content and format of the Har file do not meet the requirements",
-                false,
-            )
-            .into_response());
+        if let Some(max_size) = with_context!(max_har_size) {
+            if data.len() as u64 > max_size {
+                warn!(
+                    "upload har file {filename} is {} bytes, exceeds the limit of {max_size} bytes",
+                    data.len()
+                );
+                return Ok(error_html(
+                    FAILED_UPLOAD_TITLE,
+                    "The Har file exceeds the maximum size",
+                    false,
+                )
+                .into_response());
+            }
         }
 
-        har::write_file(&_type.0 .0, &filename, data)
-            .await
-            .map_err(ResponseError::InternalServerError)?;
+        match har::valid(&data) {
+            Ok(entry) => {
+                if let Some(err) = entry.check_capture_age().err() {
+                    warn!("upload har file {filename} is stale: {err}");
+                    return Ok(error_html(
+                        FAILED_UPLOAD_TITLE,
+                        "The Har file was captured too long ago, please capture a new one",
+                        false,
+                    )
+                    .into_response());
+                }
+            }
+            Err(err) => {
+                warn!("upload har file check error: {}", err);
+                return Ok(error_html(
+                    FAILED_UPLOAD_TITLE,
+                    "The content and format of the Har file do not meet the requirements",
+                    false,
+                )
+                .into_response());
+            }
+        }
+
+        let result = har::write_file(&_type.0 .0, &filename, data).await;
+        audit_with_key(
+            bearer.or(with_context!(auth_key)),
+            "har_upload",
+            serde_json::json!({ "type": _type.0 .0.to_string(), "filename": filename }),
+            &result,
+        );
+        result.map_err(ResponseError::InternalServerError)?;
     }
 
     Ok(success_html(
@@ -213,10 +293,13 @@ async fn delete_file(
     }
 
     // Try to delete file
-    if let Some(err) = har::delete_file(&_type.0 .0, &filename.filename)
-        .await
-        .err()
-    {
+    let result = har::delete_file(&_type.0 .0, &filename.filename).await;
+    audit(
+        "har_delete",
+        serde_json::json!({ "type": _type.0 .0.to_string(), "filename": filename.filename }),
+        &result,
+    );
+    if let Some(err) = result.err() {
         return Ok(error_html(
             "File deleted failed",
             &format!("Your file has been failed to delete: {err}"),
@@ -247,9 +330,17 @@ async fn rename_file(
         .as_ref()
         .ok_or(ResponseError::BadRequest(ProxyError::NewFilenameIsEmpty))?;
 
-    har::rename_file(&_type.0 .0, &filename.filename, &new_filename)
-        .await
-        .map_err(ResponseError::BadRequest)?;
+    let result = har::rename_file(&_type.0 .0, &filename.filename, &new_filename).await;
+    audit(
+        "har_rename",
+        serde_json::json!({
+            "type": _type.0 .0.to_string(),
+            "filename": filename.filename,
+            "new_filename": new_filename,
+        }),
+        &result,
+    );
+    result.map_err(ResponseError::BadRequest)?;
 
     Ok(success_html(
         "File renamed successfully",
@@ -258,7 +349,8 @@ async fn rename_file(
     .into_response())
 }
 
-use axum::headers::{Header, HeaderName, HeaderValue};
+use axum::headers::authorization::Bearer;
+use axum::headers::{Authorization, Header, HeaderName, HeaderValue};
 use axum::http::header;
 use axum_extra::extract::CookieJar;
 
@@ -287,14 +379,7 @@ impl Header for PlatformType {
     where
         E: Extend<HeaderValue>,
     {
-        let s = match self {
-            PlatformType(arkose::Type::GPT3) => "gpt3",
-            PlatformType(arkose::Type::GPT4) => "gpt4",
-            PlatformType(arkose::Type::Auth) => "auth",
-            PlatformType(arkose::Type::Platform) => "platform",
-            PlatformType(arkose::Type::SignUp) => "signup",
-        };
-        let value = HeaderValue::from_str(s).expect("invalid header value");
+        let value = HeaderValue::from_str(&self.0.to_string()).expect("invalid header value");
         values.extend(std::iter::once(value));
     }
 }
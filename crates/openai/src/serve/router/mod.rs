@@ -1,20 +1,51 @@
+mod admin;
 mod chat;
 mod files;
 mod har;
 
-use crate::context::args::Args;
+use crate::context::args::{Args, RootResponse};
 use crate::serve::error::ResponseError;
 use axum::http::header;
 use axum::http::StatusCode;
-use axum::{body::Body, extract::Path, http::Response, Router};
+use axum::response::{IntoResponse, Redirect};
+use axum::routing::get;
+use axum::{body::Body, extract::Path, http::Response, Json, Router};
 use std::collections::HashMap;
 use tokio::sync::OnceCell;
 
 pub(super) fn config(router: Router, args: &Args) -> Router {
+    // The admin endpoints listen on the admin bind address if configured
+    let router = match args.admin_bind {
+        Some(_) => router,
+        None => admin::config(router, args),
+    };
     let router = files::config(router, args);
     let router = har::config(router, args);
     let router = chat::config(router, args);
-    router
+    root(router, args)
+}
+
+/// Admin router, served on the admin bind address
+pub(super) fn admin(args: &Args) -> Router {
+    admin::config(Router::new(), args)
+}
+
+/// Configure the root response, the default is the WebUI chat page if enabled
+fn root(router: Router, args: &Args) -> Router {
+    let response = match &args.root_response {
+        RootResponse::Default => return router,
+        response => response.clone(),
+    };
+    router.route(
+        "/",
+        get(move || async move {
+            match response {
+                RootResponse::Redirect(location) => Redirect::temporary(&location).into_response(),
+                RootResponse::Json(value) => Json(value).into_response(),
+                _ => StatusCode::NOT_FOUND.into_response(),
+            }
+        }),
+    )
 }
 
 include!(concat!(env!("OUT_DIR"), "/generated.rs"));
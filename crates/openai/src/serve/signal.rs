@@ -1,6 +1,8 @@
-use crate::info;
+#[cfg(target_family = "unix")]
+use crate::context::{args::ConfigReloader, reload::ReloadSource};
+use crate::{info, warn, with_context};
 use axum_server::Handle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 #[cfg(target_family = "unix")]
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::time::sleep;
@@ -40,15 +42,41 @@ pub(super) async fn graceful_shutdown(handle: Handle) {
     }
 }
 
-async fn sending_graceful_shutdown_signal(handle: Handle, signal: &'static str) {
+pub(super) async fn sending_graceful_shutdown_signal(handle: Handle, signal: &'static str) {
     info!("{signal} received: starting graceful shutdown");
 
-    // Signal the server to shutdown using Handle.
-    handle.graceful_shutdown(Some(Duration::from_secs(3)));
+    // Signal the server to shutdown using Handle, the connections are forced to close once the grace period expired.
+    let grace = with_context!(shutdown_grace);
+    handle.graceful_shutdown(Some(grace));
 
     // Print alive connection count every second.
-    loop {
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline {
         sleep(Duration::from_secs(1)).await;
         info!("Alive connections: {}", handle.connection_count());
     }
+
+    let in_flight = with_context!(metrics).in_flight();
+    if in_flight > 0 {
+        warn!(
+            "Shutdown grace period ({}s) expired with {in_flight} in-flight requests",
+            grace.as_secs()
+        );
+    }
+}
+
+/// Reload the client proxies and the solver config on SIGUSR1, HAR is untouched
+#[cfg(target_family = "unix")]
+pub(super) async fn reload_on_sigusr1(reloader: ConfigReloader) {
+    let mut sigusr1 = signal(SignalKind::user_defined1()).expect("SIGUSR1 signal hanlde error");
+    while sigusr1.recv().await.is_some() {
+        info!("SIGUSR1 received: reloading proxies and solver config");
+        let result = reloader().and_then(|args| with_context!().reload(&args));
+        for subsystem in ["proxies", "solver"] {
+            with_context!(reload_status).record(subsystem, ReloadSource::Signal, &result);
+        }
+        if let Err(err) = result {
+            warn!("Failed to reload proxies and solver config: {err}");
+        }
+    }
 }
@@ -0,0 +1,213 @@
+use crate::arkose::Type;
+use crate::context::{args::Args, arkose::har, init};
+use crate::{info, warn, with_context, URL_CHATGPT_API};
+use std::time::Duration;
+
+/// Solver ping timeout
+const SOLVER_PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Startup check result
+struct Check {
+    name: &'static str,
+    target: String,
+    ok: bool,
+    fatal: bool,
+    detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, target: impl Into<String>, result: Result<String, String>) -> Self {
+        let (ok, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        Self {
+            name,
+            target: target.into(),
+            ok,
+            fatal: false,
+            detail,
+        }
+    }
+
+    fn fatal(mut self, fatal: bool) -> Self {
+        self.fatal = fatal;
+        self
+    }
+}
+
+/// Check the gated arkose types, the requests of a type without a usable HAR and a solver fail at
+/// runtime. Refuse to start under `strict_arkose`, otherwise warn
+pub(super) fn check_gated_types(args: &Args) -> anyhow::Result<()> {
+    if args.arkose_solver.is_some() || !args.arkose_solver_chain.is_empty() {
+        return Ok(());
+    }
+
+    // GPT-4 always requires the arkose token, GPT-3.5 only in the experiment with the solver,
+    // the mapped models require their types
+    let mut gated = vec![Type::GPT4];
+    if args.arkose_gpt3_experiment && args.arkose_gpt3_experiment_solver {
+        gated.push(Type::GPT3);
+    }
+    for typed in args.model_arkose_map.values() {
+        if !gated.contains(typed) {
+            gated.push(*typed);
+        }
+    }
+
+    let har_status = har::har_status();
+    let missing = gated
+        .into_iter()
+        .filter(|typed| {
+            !har_status
+                .get(&typed.to_string())
+                .map_or(false, |files| files.iter().any(|f| !f.stale))
+        })
+        .map(|typed| typed.to_string())
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let message = format!(
+        "Arkose type(s) {} have neither a usable HAR nor a solver configured, their requests will fail",
+        missing.join(", ")
+    );
+    if args.strict_arkose {
+        anyhow::bail!("{message}, add a HAR file or configure a solver");
+    }
+    warn!("{message}");
+    Ok(())
+}
+
+/// Validate config, probe proxies, check HAR and ping the solver, then log a report.
+/// The fatal failures abort the startup, others are warnings.
+pub(super) async fn report(args: &Args) -> anyhow::Result<()> {
+    let mut checks = Vec::new();
+
+    // Config
+    checks.push(
+        Check::new(
+            "config",
+            "bind",
+            (!args.bind.is_empty())
+                .then(|| format!("{} address", args.bind.len()))
+                .ok_or_else(|| "no bind address".to_owned()),
+        )
+        .fatal(true),
+    );
+    checks.push(
+        Check::new(
+            "config",
+            "tls",
+            match (&args.tls_cert, &args.tls_key) {
+                (Some(_), Some(_)) => Ok("enabled".to_owned()),
+                (None, None) => Ok("disabled".to_owned()),
+                _ => Err("tls cert and key must be set together".to_owned()),
+            },
+        )
+        .fatal(true),
+    );
+    checks.push(
+        Check::new(
+            "config",
+            "solver",
+            match (
+                args.arkose_solver
+                    .iter()
+                    .chain(&args.arkose_solver_chain)
+                    .map(|solver| format!("{:?}", solver.solver))
+                    .collect::<Vec<_>>(),
+                args.require_solver,
+            ) {
+                (chain, _) if !chain.is_empty() => Ok(chain.join(" -> ")),
+                (_, true) => Err("required but not configured".to_owned()),
+                (_, false) => Err("not configured".to_owned()),
+            },
+        )
+        .fatal(args.require_solver),
+    );
+
+    // Proxies
+    let probe_url = args
+        .proxy_healthcheck_url
+        .as_deref()
+        .unwrap_or(URL_CHATGPT_API);
+    for probe in with_context!().probe_proxies(probe_url).await {
+        checks.push(Check::new(
+            "proxy",
+            format!(
+                "{}#{} {}",
+                probe.kind,
+                probe.index,
+                probe.egress.as_deref().unwrap_or("direct")
+            ),
+            probe
+                .result
+                .map(|latency| format!("{}ms", latency.as_millis())),
+        ));
+    }
+
+    // HAR
+    for (typed, _) in init::HAR_TYPES {
+        checks.push(Check::new(
+            "har",
+            typed.to_string(),
+            har::get_entry(&typed)
+                .map(|_| "usable".to_owned())
+                .map_err(|err| err.to_string()),
+        ));
+    }
+
+    // Solver chain, only the primary solver is fatal
+    for (index, solver) in with_context!(arkose_solver_chain).iter().enumerate() {
+        let result = match with_context!(arkose_client).await {
+            Ok(client) => client
+                .get(solver.endpoint())
+                .timeout(SOLVER_PING_TIMEOUT)
+                .send()
+                .await
+                .map(|resp| format!("status {}", resp.status().as_u16()))
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        checks.push(
+            Check::new("solver", solver.endpoint(), result)
+                .fatal(args.require_solver && index == 0),
+        );
+    }
+
+    log_report(&checks);
+
+    let fatal = checks.iter().filter(|c| !c.ok && c.fatal).count();
+    if fatal > 0 {
+        anyhow::bail!("Startup report failed: {fatal} fatal check(s)");
+    }
+
+    Ok(())
+}
+
+fn log_report(checks: &[Check]) {
+    let target_width = checks.iter().map(|c| c.target.len()).max().unwrap_or(0);
+    info!("Startup report:");
+    for check in checks {
+        let status = match (check.ok, check.fatal) {
+            (true, _) => "OK",
+            (false, true) => "FATAL",
+            (false, false) => "WARN",
+        };
+        let line = format!(
+            "{:<6} | {:<width$} | {:<5} | {}",
+            check.name,
+            check.target,
+            status,
+            check.detail,
+            width = target_width
+        );
+        if check.ok {
+            info!("{line}");
+        } else {
+            warn!("{line}");
+        }
+    }
+}
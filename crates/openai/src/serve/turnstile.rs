@@ -1,18 +1,25 @@
-use crate::{serve::error::ProxyError, with_context};
+use crate::{context::CfTurnstile, serve::error::ProxyError, warn, with_context};
 use std::net::IpAddr;
+use std::time::Duration;
+
+/// Cloudflare Turnstile siteverify url
+const SITEVERIFY_URL: &str = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+
+/// Initial backoff of the siteverify retries, doubled per retry
+const VERIFY_BACKOFF: Duration = Duration::from_millis(500);
+
+#[derive(serde::Serialize)]
+struct CfCaptchaForm<'a> {
+    secret: &'a str,
+    response: &'a str,
+    remoteip: &'a IpAddr,
+    idempotency_key: String,
+}
 
 pub(super) async fn cf_turnstile_check(
     addr: IpAddr,
     cf_response: Option<&str>,
 ) -> Result<(), ProxyError> {
-    #[derive(serde::Serialize)]
-    struct CfCaptchaForm<'a> {
-        secret: &'a str,
-        response: &'a str,
-        remoteip: &'a IpAddr,
-        idempotency_key: String,
-    }
-
     let ctx = with_context!();
 
     if let Some(turnsile) = ctx.cf_turnstile() {
@@ -20,6 +27,7 @@ pub(super) async fn cf_turnstile_check(
             .filter(|r| !r.is_empty())
             .ok_or_else(|| ProxyError::CfMissingCaptcha)?;
 
+        // the retries share the idempotency key, so a retried verification is not a replay
         let form = CfCaptchaForm {
             secret: &turnsile.secret_key,
             response,
@@ -27,15 +35,57 @@ pub(super) async fn cf_turnstile_check(
             idempotency_key: crate::uuid::uuid(),
         };
 
-        let _ = ctx
-            .api_client()
-            .post("https://challenges.cloudflare.com/turnstile/v0/siteverify")
-            .form(&form)
-            .send()
-            .await
-            .map_err(ProxyError::RequestError)?
-            .error_for_status()
-            .map_err(ProxyError::CfError)?;
+        let mut attempt = 0;
+        loop {
+            // The wait for a client at capacity is already bounded, not retried
+            let client = with_context!(api_client)
+                .await
+                .map_err(|_| ProxyError::CfVerifyUnavailable(attempt + 1))?;
+            match siteverify(&client, turnsile, &form).await {
+                Ok(()) => break,
+                Err(err) if !is_transient(&err) => return Err(ProxyError::CfError(err)),
+                Err(err) if attempt >= turnsile.verify_retries => {
+                    warn!(
+                        "Turnstile siteverify failed after {} attempts: {err}",
+                        attempt + 1
+                    );
+                    return Err(ProxyError::CfVerifyUnavailable(attempt + 1));
+                }
+                Err(err) => {
+                    let backoff = VERIFY_BACKOFF * 2u32.pow(attempt.min(6));
+                    warn!(
+                        "Turnstile siteverify failed ({err}), retry in {}ms",
+                        backoff.as_millis()
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+            }
+        }
     }
     Ok(())
 }
+
+/// Send the siteverify request within the verify timeout
+async fn siteverify(
+    client: &reqwest::Client,
+    turnsile: &CfTurnstile,
+    form: &CfCaptchaForm<'_>,
+) -> Result<(), reqwest::Error> {
+    client
+        .post(SITEVERIFY_URL)
+        .timeout(turnsile.verify_timeout)
+        .form(form)
+        .send()
+        .await?
+        .error_for_status()
+        .map(|_| ())
+}
+
+/// Cloudflare hiccups worth a retry: timeouts, connection errors, 429 and 5xx responses
+fn is_transient(err: &reqwest::Error) -> bool {
+    match err.status() {
+        Some(status) => status.is_server_error() || status.as_u16() == 429,
+        None => err.is_timeout() || err.is_connect() || err.is_request(),
+    }
+}
@@ -1,8 +1,19 @@
 use crate::parse;
-use clap::{Args, Subcommand};
-use openai::{arkose::funcaptcha::solver::Solver, proxy};
+use clap::{parser::ValueSource, ArgMatches, Args, CommandFactory, Subcommand};
+use openai::{
+    arkose::funcaptcha::solver::{SignAlgorithm, Solver},
+    client::{BalanceStrategy, StickyKey},
+    context::{
+        args::{RootResponse, UnknownArkosePolicy},
+        preauth::PreauthPushPolicy,
+    },
+    proxy,
+};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
 
 #[cfg(all(feature = "serve", not(feature = "terminal")))]
 pub mod cmd {
@@ -70,6 +81,30 @@ pub enum ServeSubcommand {
     },
     /// Update the application
     Update,
+    /// Benchmark the client proxy pool
+    Bench(BenchArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// Client type to benchmark (api/auth/arkose)
+    #[clap(long, default_value = "api", value_parser = ["api", "auth", "arkose"])]
+    pub(super) kind: String,
+
+    /// Test request url, a harmless endpoint or a mock
+    #[clap(long, default_value = "https://chat.openai.com/robots.txt")]
+    pub(super) url: String,
+
+    /// Number of test requests
+    #[clap(short = 'n', long, default_value = "100")]
+    pub(super) requests: usize,
+
+    /// Number of concurrent test requests
+    #[clap(long, default_value = "10")]
+    pub(super) concurrency: usize,
+
+    #[clap(flatten)]
+    pub(super) serve: ServeArgs,
 }
 
 #[derive(Args, Debug, Default, Serialize, Deserialize)]
@@ -82,14 +117,44 @@ pub struct ServeArgs {
     #[clap(short = 'C', long, env = "CONFIG", value_parser = parse::parse_file_path)]
     pub(super) config: Option<PathBuf>,
 
-    /// Server bind address
-    #[clap(short, long, env = "BIND", default_value = "0.0.0.0:7999", value_parser = parse::parse_socket_addr)]
-    pub(super) bind: Option<std::net::SocketAddr>,
+    /// Server bind address, support multiple address, use ',' to separate
+    /// Default: consult the NINJA_BIND and PORT env, fallback to 0.0.0.0:7999
+    /// e.g. 0.0.0.0:7999, [::]:7999
+    #[clap(short, long, env = "BIND", value_parser = parse::parse_socket_addrs, verbatim_doc_comment)]
+    #[serde(default, deserialize_with = "parse::deserialize_socket_addrs")]
+    pub(super) bind: Option<std::vec::Vec<std::net::SocketAddr>>,
+
+    /// Admin endpoints bind address, the admin endpoints listen on it instead of the server bind address
+    /// e.g. 127.0.0.1:7998
+    #[clap(long, env = "ADMIN_BIND", value_parser = parse::parse_socket_addr, verbatim_doc_comment)]
+    pub(super) admin_bind: Option<std::net::SocketAddr>,
 
     /// Server Enforces a limit on the concurrent number of requests the underlying
     #[clap(long, default_value = "1024")]
     pub(super) concurrent_limit: usize,
 
+    /// Server request queue depth, the requests over the concurrent limit wait in the queue, respond 429 when full
+    /// Default: unbounded
+    #[clap(long, env = "QUEUE_DEPTH", verbatim_doc_comment)]
+    pub(super) queue_depth: Option<usize>,
+
+    /// Server request queue timeout (seconds), respond 429 when no slot is available in time
+    /// Default: wait until the server timeout
+    #[clap(long, env = "QUEUE_TIMEOUT", verbatim_doc_comment)]
+    pub(super) queue_timeout: Option<u64>,
+
+    /// Server end-to-end request pipeline limit (including the arkose solve step), respond 429 when full
+    #[clap(long, env = "PIPELINE_LIMIT")]
+    pub(super) pipeline_limit: Option<usize>,
+
+    /// Server active connections limit per client ip (the real ip behind the trusted proxies, of the first request of the connection), respond 429 and close the connection when over. Idle keep-alive connections count until closed
+    #[clap(long, env = "MAX_CONNS_PER_IP")]
+    pub(super) max_conns_per_ip: Option<usize>,
+
+    /// Random jitter (milliseconds) added to the Retry-After of the 429 rejections
+    #[clap(long, env = "RETRY_AFTER_JITTER_MS", default_value = "0")]
+    pub(super) retry_after_jitter_ms: u64,
+
     /// Server/Client timeout (seconds)
     #[clap(long, default_value = "360")]
     pub(super) timeout: usize,
@@ -98,14 +163,60 @@ pub struct ServeArgs {
     #[clap(long, default_value = "5")]
     pub(super) connect_timeout: usize,
 
+    /// Proxy response body read idle timeout (seconds), abort the stream if no data arrives in time
+    #[clap(long, env = "READ_IDLE_TIMEOUT")]
+    pub(super) read_idle_timeout: Option<usize>,
+
+    /// Proxy response body size limit (bytes), abort the stream past the limit, unlimited by default
+    #[clap(long, env = "MAX_UPSTREAM_BODY_SIZE")]
+    pub(super) max_upstream_body_size: Option<u64>,
+
+    /// Proxy request SLO deadline (milliseconds), cancel the upstream request and respond 504 when exceeded
+    #[clap(long, env = "SLO_MS")]
+    pub(super) slo_ms: Option<u64>,
+
+    /// Proxy request budget (milliseconds), caps the whole request (arkose solve + upstream), respond 504 when exhausted
+    #[clap(long, env = "REQUEST_BUDGET_MS")]
+    pub(super) request_budget_ms: Option<u64>,
+
+    /// Upstream error cache cooldown (seconds), after consecutive upstream failures the proxy requests fail fast
+    /// with 503 for the cooldown instead of attempting the upstream
+    #[clap(long, env = "ERROR_CACHE_TTL", verbatim_doc_comment)]
+    pub(super) error_cache_ttl: Option<u64>,
+
+    /// Consecutive upstream failures (connection errors or 5xx) to start the error cache cooldown
+    #[clap(
+        long,
+        env = "ERROR_CACHE_THRESHOLD",
+        default_value = "5",
+        requires = "error_cache_ttl"
+    )]
+    pub(super) error_cache_threshold: usize,
+
     /// Server/Client TCP keepalive (seconds)
     #[clap(long, default_value = "60")]
     pub(super) tcp_keepalive: usize,
 
+    /// Server shutdown grace period (seconds), the in-flight requests are forced to close once expired
+    #[clap(long, env = "SHUTDOWN_GRACE_SECS", default_value = "30")]
+    pub(super) shutdown_grace_secs: u64,
+
     /// No TCP keepalive (Client)
     #[clap(short = 'H', long, env = "NO_TCP_KEEPALIVE", default_value = "false")]
     pub(super) no_keepalive: bool,
 
+    /// Fresh upstream connection per request (Client), no TLS connection is reused across requests
+    #[clap(long, env = "DISABLE_CONNECTION_REUSE")]
+    pub(super) disable_connection_reuse: bool,
+
+    /// No upstream redirects following (Client), the 3xx responses are returned to the client as is
+    #[clap(long, env = "NO_FOLLOW_REDIRECTS")]
+    pub(super) no_follow_redirects: bool,
+
+    /// Maximum upstream redirects followed per request (Client)
+    #[clap(long, env = "MAX_REDIRECTS", default_value = "10")]
+    pub(super) max_redirects: usize,
+
     /// Keep the client alive on an idle socket with an optional timeout set
     #[clap(long, default_value = "90")]
     pub(super) pool_idle_timeout: usize,
@@ -117,6 +228,87 @@ pub struct ServeArgs {
     #[clap(short = 'x',long, env = "PROXIES", value_parser = parse::parse_proxies_url, verbatim_doc_comment)]
     pub(super) proxies: Option<std::vec::Vec<proxy::Proxy>>,
 
+    /// Auth client proxy, overrides the proxies for the auth client only, use ',' to separate, the proto is ignored
+    /// e.g. socks5://192.168.1.1:1080, 10.0.0.1
+    #[clap(long, env = "AUTH_PROXIES", value_parser = parse::parse_proxies_url, verbatim_doc_comment)]
+    pub(super) auth_proxies: Option<std::vec::Vec<proxy::Proxy>>,
+
+    /// Auth client bound interface, overrides the interfaces of the proxies for the auth client only
+    #[clap(long, env = "AUTH_INTERFACE")]
+    pub(super) auth_interface: Option<std::net::IpAddr>,
+
+    /// Auth client timeout (seconds), overrides the timeout for the auth client only
+    #[clap(long, env = "AUTH_TIMEOUT")]
+    pub(super) auth_timeout: Option<usize>,
+
+    /// Maximum clients built per pool, the proxies are randomly sampled if there are more
+    #[clap(long, env = "MAX_CLIENTS")]
+    pub(super) max_clients: Option<usize>,
+
+    /// Maximum simultaneous connection establishment per pool (requests of cold clients, proxy pre-checks, CONNECT tunnels, probes, health checks, bench), unlimited by default
+    #[clap(long, env = "CONNECT_CONCURRENCY")]
+    pub(super) connect_concurrency: Option<usize>,
+
+    /// Client selection strategy (round_robin/fixed[:index]), fixed is for deterministic tests/debugging
+    #[clap(long, env = "BALANCE_STRATEGY", default_value = "round_robin")]
+    pub(super) balance_strategy: BalanceStrategy,
+
+    /// Sticky sessions, the requests of the same session key keep the same client, the rotation without the key
+    #[clap(long, env = "STICKY_SESSIONS")]
+    pub(super) sticky_sessions: bool,
+
+    /// Sticky session key source (header:<name>/cookie:<name>)
+    #[clap(
+        long,
+        env = "STICKY_KEY",
+        default_value = "header:x-session-id",
+        requires = "sticky_sessions"
+    )]
+    pub(super) sticky_key: StickyKey,
+
+    /// Rotate the IPv6 subnet bound address every N requests, 0 disables the count rotation
+    #[clap(long, env = "IPV6_ROTATE_EVERY", default_value = "0")]
+    pub(super) ipv6_rotate_every: u64,
+
+    /// Rotate the IPv6 subnet bound address every N seconds, 0 disables the interval rotation
+    /// When both are 0, a new address is chosen per request
+    #[clap(
+        long,
+        env = "IPV6_ROTATE_INTERVAL",
+        default_value = "0",
+        verbatim_doc_comment
+    )]
+    pub(super) ipv6_rotate_interval: u64,
+
+    /// Client proxy health check url, unhealthy proxies are taken out of rotation
+    #[clap(long, env = "PROXY_HEALTHCHECK_URL", value_parser = parse::parse_url)]
+    pub(super) proxy_healthcheck_url: Option<String>,
+
+    /// Client proxy health check interval (seconds)
+    #[clap(long, default_value = "60", requires = "proxy_healthcheck_url")]
+    pub(super) proxy_healthcheck_interval: u64,
+
+    /// Pre-check the proxy of the client reachable (TCP connect) before the proxy request, skip to the next client if not
+    #[clap(long, env = "PRECHECK_PROXY")]
+    pub(super) precheck_proxy: bool,
+
+    /// Proxy pre-check timeout (seconds)
+    #[clap(
+        long,
+        env = "PRECHECK_TIMEOUT",
+        default_value = "1",
+        requires = "precheck_proxy"
+    )]
+    pub(super) precheck_timeout: u64,
+
+    /// Client upstream extra root certificates (PEM bundle), e.g. the CA of a TLS-inspecting proxy
+    #[clap(long, env = "UPSTREAM_CA_BUNDLE")]
+    pub(super) upstream_ca_bundle: Option<PathBuf>,
+
+    /// Client accepts invalid upstream certificates, DANGEROUS, the last resort only
+    #[clap(long, env = "DANGER_ACCEPT_INVALID_CERTS")]
+    pub(super) danger_accept_invalid_certs: bool,
+
     /// Enable direct connection
     #[clap(long, env = "ENABLE_DIRECT")]
     pub(super) enable_direct: bool,
@@ -133,6 +325,59 @@ pub struct ServeArgs {
     #[clap(long, env = "FASTEST_DNS")]
     pub(super) fastest_dns: bool,
 
+    /// Client static DNS overrides (like /etc/hosts), pinned before the DNS resolver, format: host1=ip1,host2=ip2
+    #[clap(long, env = "DNS_OVERRIDES", value_parser = parse::parse_dns_overrides)]
+    pub(super) dns_overrides: Option<std::vec::Vec<(String, std::net::IpAddr)>>,
+
+    /// Strip upstream response headers, separate multiple ones with ","
+    /// e.g. alt-svc,set-cookie
+    #[clap(long, env = "STRIP_RESPONSE_HEADERS", value_parser = parse::parse_header_names, verbatim_doc_comment)]
+    pub(super) strip_response_headers: Option<std::vec::Vec<String>>,
+
+    /// Rewrite upstream response headers, separate multiple ones with ","
+    /// e.g. cache-control:no-store,x-frame-options:DENY
+    #[clap(long, env = "REWRITE_RESPONSE_HEADERS", value_parser = parse::parse_header_pairs, verbatim_doc_comment)]
+    pub(super) rewrite_response_headers: Option<std::vec::Vec<(String, String)>>,
+
+    /// Rewrite the OpenAI API (/v1, /dashboard) upstream base url, e.g. an OpenAI-compatible backend
+    /// e.g. https://example.openai.azure.com
+    #[clap(long, env = "UPSTREAM_BASE", value_parser = parse::parse_url, verbatim_doc_comment)]
+    pub(super) upstream_base: Option<String>,
+
+    /// Rewrite the upstream path prefix, separate multiple ones with ",", the first match wins
+    /// e.g. /v1/chat/completions=/openai/deployments/gpt4/chat/completions
+    #[clap(long, env = "UPSTREAM_PATH_MAP", value_parser = parse::parse_path_map, requires = "upstream_base", verbatim_doc_comment)]
+    pub(super) upstream_path_map: Option<std::vec::Vec<(String, String)>>,
+
+    /// OpenTelemetry OTLP traces exporter endpoint (requires the `otlp` feature)
+    /// e.g. http://localhost:4317
+    #[clap(long, env = "OTLP_ENDPOINT", value_parser = parse::parse_url, verbatim_doc_comment)]
+    pub(super) otlp_endpoint: Option<String>,
+
+    /// Fraction (0.0-1.0) of the requests logging the access lines, the error responses are always logged
+    #[clap(long, env = "LOG_SAMPLE_RATE", default_value = "1.0", value_parser = parse::parse_sample_rate)]
+    pub(super) log_sample_rate: f64,
+
+    /// Slow request threshold (milliseconds), the slower requests log a warning with the phase breakdown
+    #[clap(long, env = "SLOW_REQUEST_THRESHOLD_MS")]
+    pub(super) slow_request_threshold_ms: Option<u64>,
+
+    /// Append the admin actions audit log to the file, besides the server log
+    #[clap(long, env = "AUDIT_LOG_PATH")]
+    pub(super) audit_log_path: Option<PathBuf>,
+
+    /// Re-exec on SIGUSR2 passing the listening sockets to the new process, then drain the old one (unix only)
+    #[clap(long, env = "GRACEFUL_RESTART")]
+    pub(super) graceful_restart: bool,
+
+    /// Static model list (JSON) served for `GET /v1/models` when the upstream is unreachable or fails
+    #[clap(long, env = "FALLBACK_MODELS_FILE", value_parser = parse::parse_file_path)]
+    pub(super) fallback_models_file: Option<PathBuf>,
+
+    /// Write a final snapshot (metrics, preauth cache, proxies) to the file on shutdown
+    #[clap(long, env = "SHUTDOWN_REPORT_PATH")]
+    pub(super) shutdown_report_path: Option<PathBuf>,
+
     /// TLS certificate file path
     #[clap(long, env = "TLS_CERT", requires = "tls_key")]
     pub(super) tls_cert: Option<PathBuf>,
@@ -141,6 +386,18 @@ pub struct ServeArgs {
     #[clap(long, env = "TLS_KEY", requires = "tls_cert")]
     pub(super) tls_key: Option<PathBuf>,
 
+    /// TLS ALPN protocols advertised by the server, use ',' to separate, default: h2,http/1.1
+    /// e.g. http/1.1
+    #[clap(
+        long,
+        env = "TLS_ALPN",
+        value_delimiter = ',',
+        value_parser = ["h2", "http/1.1"],
+        requires = "tls_cert",
+        verbatim_doc_comment
+    )]
+    pub(super) tls_alpn: Option<Vec<String>>,
+
     /// Cloudflare turnstile captcha site key
     #[clap(long, env = "CF_SECRET_KEY", requires = "cf_secret_key")]
     pub(super) cf_site_key: Option<String>,
@@ -149,22 +406,75 @@ pub struct ServeArgs {
     #[clap(long, env = "CF_SITE_KEY", requires = "cf_site_key")]
     pub(super) cf_secret_key: Option<String>,
 
+    /// Cloudflare turnstile siteverify timeout (seconds)
+    #[clap(long, env = "CF_VERIFY_TIMEOUT", default_value = "10")]
+    pub(super) cf_verify_timeout: u64,
+
+    /// Cloudflare turnstile siteverify retries on timeouts, connection errors and 5xx, with exponential backoff
+    #[clap(long, env = "CF_VERIFY_RETRIES", default_value = "2")]
+    pub(super) cf_verify_retries: u32,
+
     /// Login/Arkose/HAR Authentication Key
     #[clap(short = 'A', long, env = "AUTH_KEY")]
     pub(super) auth_key: Option<String>,
 
+    /// HAR upload keys scoped to a single ArkoseLabs type, sent as Authorization Bearer to /har/upload
+    /// Type: gpt3/gpt4/auth/platform/signup, separate multiple ones with ","
+    /// e.g. gpt4=key1,auth=key2
+    #[clap(long, env = "HAR_UPLOAD_KEYS", value_parser = parse::parse_har_upload_keys, requires = "auth_key", verbatim_doc_comment)]
+    pub(super) har_upload_keys: Option<HashMap<String, String>>,
+
     /// Enable WebUI
     #[clap(long, env = "ENABLE_WEBUI", requires = "arkose_endpoint")]
     pub(super) enable_webui: bool,
 
+    /// Response of the root path (default/404/redirect:<url>/json:<json>), default is the WebUI if enabled
+    #[clap(long, env = "ROOT_RESPONSE", default_value = "default")]
+    pub(super) root_response: RootResponse,
+
     /// Enable file endpoint proxy
     #[clap(short = 'F', long, env = "ENABLE_FILE_PROXY")]
     pub(super) enable_file_proxy: bool,
 
+    /// Enable HTTP CONNECT proxy, tunnel to the allowlisted hosts through the client egress, requires --auth-key
+    #[clap(long, env = "ENABLE_CONNECT_PROXY")]
+    pub(super) enable_connect_proxy: bool,
+
+    /// HTTP CONNECT proxy host allowlist, use ',' to separate, default: openai/arkose domains
+    /// e.g. openai.com, arkoselabs.com
+    #[clap(
+        long,
+        env = "CONNECT_PROXY_ALLOWLIST",
+        value_delimiter = ',',
+        requires = "enable_connect_proxy",
+        verbatim_doc_comment
+    )]
+    pub(super) connect_proxy_allowlist: Option<Vec<String>>,
+
     /// Enable arkose token endpoint proxy
     #[clap(short = 'G', long, env = "ENABLE_ARKOSE_PROXY")]
     pub(super) enable_arkose_proxy: bool,
 
+    /// Admin and HAR management endpoints ip allowlist (CIDR), use ',' to separate
+    /// e.g. 127.0.0.1, 10.0.0.0/8, ::1
+    #[clap(long, env = "ADMIN_IP_ALLOWLIST", value_parser = parse::parse_cidrs, verbatim_doc_comment)]
+    pub(super) admin_ip_allowlist: Option<std::vec::Vec<cidr::IpCidr>>,
+
+    /// Enabled admin endpoints (path after /admin/), use ',' to separate, all are enabled by default
+    /// e.g. reload/status, har/refresh, metrics
+    #[clap(
+        long,
+        env = "ADMIN_ENDPOINTS_ENABLED",
+        value_delimiter = ',',
+        verbatim_doc_comment
+    )]
+    pub(super) admin_endpoints_enabled: Option<Vec<String>>,
+
+    /// Trusted reverse proxies (CIDR), use ',' to separate, the X-Forwarded-For/X-Real-IP headers are only trusted from them
+    /// e.g. 127.0.0.1, 172.16.0.0/12
+    #[clap(long, env = "TRUSTED_PROXIES", value_parser = parse::parse_cidrs, verbatim_doc_comment)]
+    pub(super) trusted_proxies: Option<std::vec::Vec<cidr::IpCidr>>,
+
     /// Visitor email whitelist
     #[clap(short = 'W', long, env = "VISITOR_EMAIL_WHITELIST", value_parser = parse::parse_email_whitelist)]
     pub(super) visitor_email_whitelist: Option<std::vec::Vec<String>>,
@@ -173,6 +483,28 @@ pub struct ServeArgs {
     #[clap(long, value_parser = parse::parse_url)]
     pub(super) arkose_endpoint: Option<String>,
 
+    /// Arkose endpoint of each type, fallback to `arkose_endpoint`, use ',' to separate
+    /// Type: gpt3/gpt4/auth/platform/signup
+    /// e.g. auth=https://example.com, gpt4=https://gpt4.example.com
+    #[clap(long, value_parser = parse::parse_arkose_endpoints, verbatim_doc_comment)]
+    pub(super) arkose_endpoints: Option<HashMap<String, String>>,
+
+    /// Model to ArkoseLabs type map, consulted before the default model mapping
+    /// Type: gpt3/gpt4/auth/platform/signup
+    /// e.g. gpt-4o=gpt4, o1-preview=gpt4
+    #[clap(long, value_parser = parse::parse_model_arkose_map, verbatim_doc_comment)]
+    pub(super) model_arkose_map: Option<HashMap<String, String>>,
+
+    /// Handling of a model mapped to no arkose type (passthrough/reject)
+    /// passthrough proxies the request without the arkose token, reject responds 400
+    #[clap(
+        long,
+        env = "UNKNOWN_ARKOSE_POLICY",
+        default_value = "reject",
+        verbatim_doc_comment
+    )]
+    pub(super) unknown_arkose_policy: UnknownArkosePolicy,
+
     /// Enable Arkose GPT-3.5 experiment
     #[clap(short = 'E', long, default_value = "false")]
     pub(super) arkose_gpt3_experiment: bool,
@@ -185,6 +517,40 @@ pub struct ServeArgs {
     #[clap(long, value_parser = parse::parse_dir_path)]
     pub(super) arkose_har_dir: Option<PathBuf>,
 
+    /// Do not create the missing default HAR directory, e.g. read-only home directory
+    #[clap(long, env = "HAR_NO_CREATE")]
+    pub(super) har_no_create: bool,
+
+    /// Disable the HAR directory watching of the types, the upload API still reloads
+    /// Type: gpt3/gpt4/auth/platform/signup, separate multiple ones with ","
+    #[clap(long, env = "HAR_NO_WATCH", value_parser = parse::parse_arkose_types, verbatim_doc_comment)]
+    pub(super) har_no_watch: Option<std::vec::Vec<String>>,
+
+    /// HAR files parsing concurrency on startup
+    #[clap(long, env = "HAR_PARSE_CONCURRENCY", default_value = "4")]
+    pub(super) har_parse_concurrency: usize,
+
+    /// Primary node url, the node runs as a replica and forwards the HAR writes to the primary
+    /// The HAR directory must be shared with the primary
+    #[clap(long, env = "PRIMARY_URL", value_parser = parse::parse_url, verbatim_doc_comment)]
+    pub(super) primary_url: Option<String>,
+
+    /// Maximum HAR file size (MB), larger files are refused, 0 disables the limit
+    #[clap(long, env = "MAX_HAR_SIZE", default_value = "100")]
+    pub(super) max_har_size: u64,
+
+    /// Maximum HAR age (seconds) since the capture, older HAR files are skipped and fall back to the solver
+    #[clap(long, env = "HAR_MAX_AGE")]
+    pub(super) har_max_age: Option<u64>,
+
+    /// Maximum HAR capture age (seconds), the uploads, refreshes and installs of an older capture are rejected
+    #[clap(long, env = "HAR_MAX_CAPTURE_AGE")]
+    pub(super) har_max_capture_age: Option<u64>,
+
+    /// Pin the HAR of the pool by the client IP, falls back to the rotation when the pinned HAR is stale
+    #[clap(long, env = "HAR_AFFINITY")]
+    pub(super) har_affinity: bool,
+
     /// About ArkoseLabs solver platform
     #[clap(
         short = 's',
@@ -198,6 +564,31 @@ pub struct ServeArgs {
     #[clap(short = 'k', long)]
     pub(super) arkose_solver_key: Option<String>,
 
+    /// ArkoseLabs solver fallback chain, tried in order after the primary solver fails
+    /// Solver: yescaptcha/capsolver/fcsrv, separate multiple ones with ","
+    /// e.g. capsolver:key1,fcsrv:key2@http://127.0.0.1:8000/task
+    #[clap(long, env = "ARKOSE_SOLVER_CHAIN", value_parser = parse::parse_arkose_solver_chain, verbatim_doc_comment)]
+    pub(super) arkose_solver_chain: Option<std::vec::Vec<(Solver, String, Option<String>)>>,
+
+    /// ArkoseLabs solver route of the easy challenges, with at most `waves` waves, to the solver `index` of the chain
+    /// (the primary solver is 0), the hard challenges keep the chain order
+    /// Type: gpt3/gpt4/auth/platform/signup, separate multiple ones with ","
+    /// e.g. gpt4=2:1,auth=1:1
+    #[clap(long, env = "ARKOSE_SOLVER_DIFFICULTY", value_parser = parse::parse_arkose_solver_difficulty, verbatim_doc_comment)]
+    pub(super) arkose_solver_difficulty: Option<HashMap<String, (usize, usize)>>,
+
+    /// ArkoseLabs pre-solved token pool size of each type, refilled in the background
+    /// Type: gpt3/gpt4/auth/platform/signup, separate multiple ones with ","
+    /// e.g. auth=2,platform=1
+    #[clap(long, env = "ARKOSE_WARM_POOL_SIZE", value_parser = parse::parse_arkose_warm_pool_size, verbatim_doc_comment)]
+    pub(super) arkose_warm_pool_size: Option<HashMap<String, usize>>,
+
+    /// ArkoseLabs pre-solved token assumed TTL (seconds) of each type, the pooled tokens are discarded once reached, default 90
+    /// Type: gpt3/gpt4/auth/platform/signup, separate multiple ones with ","
+    /// e.g. auth=120,platform=60
+    #[clap(long, env = "ARKOSE_TOKEN_TTL", value_parser = parse::parse_arkose_token_ttl, verbatim_doc_comment)]
+    pub(super) arkose_token_ttl: Option<HashMap<String, u64>>,
+
     /// About the solver client endpoint by ArkoseLabs
     #[clap(long, value_parser = parse::parse_url, requires = "arkose_solver_key")]
     pub(super) arkose_solver_endpoint: Option<String>,
@@ -206,6 +597,19 @@ pub struct ServeArgs {
     #[clap(long, default_value = "1", requires = "arkose_solver_key")]
     pub(super) arkose_solver_limit: usize,
 
+    /// ArkoseLabs solver request signing key, the solve payload is signed in the `X-Signature` header
+    #[clap(long, env = "ARKOSE_SOLVER_SIGN_KEY")]
+    pub(super) arkose_solver_sign_key: Option<String>,
+
+    /// ArkoseLabs solver request signature algorithm (hmac-sha256/hmac-sha512)
+    #[clap(
+        long,
+        env = "ARKOSE_SOLVER_SIGN_ALGORITHM",
+        default_value = "hmac-sha256",
+        requires = "arkose_solver_sign_key"
+    )]
+    pub(super) arkose_solver_sign_algorithm: SignAlgorithm,
+
     /// About the solver tguess endpoint by ArkoseLabs
     #[clap(long, value_parser = parse::parse_url)]
     pub(super) arkose_solver_tguess_endpoint: Option<String>,
@@ -214,6 +618,43 @@ pub struct ServeArgs {
     #[clap(long, value_parser = parse::parse_dir_path)]
     pub(super) arkose_solver_image_dir: Option<PathBuf>,
 
+    /// About the simultaneous challenge image encodings by ArkoseLabs, the only local solver work,
+    /// offloaded from the request workers to the blocking pool. Solving itself runs on the remote solver
+    #[clap(long, env = "SOLVER_WORKERS", verbatim_doc_comment)]
+    pub(super) solver_workers: Option<usize>,
+
+    /// Solver local work queue timeout (seconds), the work waiting longer for a solver worker is rejected
+    #[clap(long, env = "SOLVER_QUEUE_TIMEOUT", requires = "solver_workers")]
+    pub(super) solver_queue_timeout: Option<u64>,
+
+    /// Coalesce the concurrent solves of the same type by ArkoseLabs, a single solve is in flight per type
+    /// and the others queue behind it, the arkose token is single-use so only a failed solve is shared
+    #[clap(long, env = "ARKOSE_SOLVE_COALESCE", verbatim_doc_comment)]
+    pub(super) arkose_solve_coalesce: bool,
+
+    /// Require the ArkoseLabs solver, fail fast on startup if it is not configured
+    #[clap(long, env = "REQUIRE_SOLVER")]
+    pub(super) require_solver: bool,
+
+    /// Refuse to start if a gated arkose type (e.g. gpt4) has neither a usable HAR nor a solver,
+    /// otherwise a warning is logged
+    #[clap(long, env = "STRICT_ARKOSE")]
+    pub(super) strict_arkose: bool,
+
+    /// Validate config, probe proxies, check HAR and ping the solver on startup, then log a report
+    #[clap(long, env = "STARTUP_REPORT")]
+    pub(super) startup_report: bool,
+
+    /// Emit the arkose type, HAR index, proxy index, egress kind and solver of the request in the `X-Ninja-*` response headers,
+    /// only for the requests with the `X-Ninja-Debug` header (the auth key), requires --auth-key
+    #[clap(long, env = "DEBUG_HEADERS", verbatim_doc_comment)]
+    pub(super) debug_headers: bool,
+
+    /// Emit the `Server-Timing` response header with the phases (solve, upstream, total),
+    /// only for the requests with the `X-Ninja-Debug` header (the auth key), requires --auth-key
+    #[clap(long, env = "SERVER_TIMING", verbatim_doc_comment)]
+    pub(super) server_timing: bool,
+
     /// Enable token bucket flow limitation
     #[clap(short = 'T', long)]
     #[cfg(feature = "limit")]
@@ -234,6 +675,16 @@ pub struct ServeArgs {
     #[cfg(feature = "limit")]
     pub(super) tb_fill_rate: u32,
 
+    /// Token bucket burst capacity, a burst bucket in series with the sustained bucket
+    #[clap(long, requires = "tb_enable")]
+    #[cfg(feature = "limit")]
+    pub(super) tb_burst_capacity: Option<u32>,
+
+    /// Token bucket burst fill rate
+    #[clap(long, default_value = "1", requires = "tb_burst_capacity")]
+    #[cfg(feature = "limit")]
+    pub(super) tb_burst_rate: u32,
+
     /// Token bucket expired (seconds)
     #[clap(long, default_value = "86400", requires = "tb_enable")]
     #[cfg(feature = "limit")]
@@ -260,6 +711,19 @@ pub struct ServeArgs {
     )]
     pub(super) pupstream: Option<String>,
 
+    /// Preauth MITM server upstream connect and request timeout (seconds), independent of `timeout`
+    #[clap(
+        long,
+        env = "PREAUTH_TIMEOUT",
+        default_value = "30",
+        requires = "pbind"
+    )]
+    pub(super) preauth_timeout: u64,
+
+    /// Preauth MITM server simultaneous intercepted connections, the extra ones wait in the accept backlog, unlimited by default
+    #[clap(long, env = "PREAUTH_CONCURRENCY", requires = "pbind")]
+    pub(super) preauth_concurrency: Option<usize>,
+
     /// Preauth MITM server CA certificate file path
     #[clap(long, default_value = "ca/cert.crt", requires = "pbind")]
     pub(super) pcert: PathBuf,
@@ -267,4 +731,137 @@ pub struct ServeArgs {
     /// Preauth MITM server CA private key file path
     #[clap(long, default_value = "ca/key.pem", requires = "pbind")]
     pub(super) pkey: PathBuf,
+
+    /// Include the cookie value in preauth capture events
+    #[clap(long, env = "PREAUTH_EVENT_VALUE", requires = "pbind")]
+    pub(super) preauth_event_value: bool,
+
+    /// Preauth cookie push policy of a cached device id (keep_first/overwrite)
+    #[clap(
+        long,
+        env = "PREAUTH_PUSH_POLICY",
+        default_value = "overwrite",
+        requires = "pbind"
+    )]
+    pub(super) preauth_push_policy: PreauthPushPolicy,
+
+    /// Fraction (0.0-1.0) of the Apple logins taking the preauth path, the rest fall back to the web
+    /// login, overridden per request by the `X-Use-Preauth` header
+    #[clap(
+        long,
+        env = "PREAUTH_RATIO",
+        default_value = "1.0",
+        value_parser = parse::parse_sample_rate,
+        requires = "pbind"
+    )]
+    pub(super) preauth_ratio: f64,
+}
+
+/// Layers of the serve args below the config file, merged by the precedence:
+/// CLI > env > file > default
+#[derive(Clone, Debug, Default)]
+pub(super) struct ConfigLayers {
+    /// Values set on the command line or by the environment
+    explicit: toml::Table,
+    /// Default values
+    defaults: toml::Table,
+}
+
+impl ConfigLayers {
+    /// Split the parsed args into the explicit and default layers by the ids set on the command
+    /// line or by the environment. The feature-gated args are absent from both the args and the ids
+    pub(super) fn new(args: &ServeArgs, explicit_ids: &HashSet<String>) -> anyhow::Result<Self> {
+        let table = match toml::Value::try_from(args)? {
+            toml::Value::Table(table) => table,
+            _ => anyhow::bail!("Serve args are not a table"),
+        };
+        let (explicit, defaults) = table
+            .into_iter()
+            .partition(|(key, _)| explicit_ids.contains(key));
+        Ok(Self { explicit, defaults })
+    }
+
+    /// Read the config file and merge it between the explicit and default layers
+    pub(super) fn resolve(&self, config_path: &Path) -> anyhow::Result<ServeArgs> {
+        let data = std::fs::read_to_string(config_path)?;
+        self.merge(toml::from_str(&data)?)
+    }
+
+    fn merge(&self, file: toml::Table) -> anyhow::Result<ServeArgs> {
+        let mut merged = self.defaults.clone();
+        merged.extend(file);
+        merged.extend(self.explicit.clone());
+        Ok(toml::Value::Table(merged).try_into()?)
+    }
+}
+
+/// Ids of the serve args set on the command line or by the environment
+pub(super) fn explicit_ids() -> HashSet<String> {
+    fn collect(matches: &ArgMatches, ids: &mut HashSet<String>) {
+        ids.extend(
+            matches
+                .ids()
+                .filter(|id| {
+                    matches!(
+                        matches.value_source(id.as_str()),
+                        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                    )
+                })
+                .map(|id| id.to_string()),
+        );
+        if let Some((_, matches)) = matches.subcommand() {
+            collect(matches, ids)
+        }
+    }
+
+    let mut ids = HashSet::new();
+    collect(&cmd::Opt::command().get_matches(), &mut ids);
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigLayers, ServeArgs};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_config_layers_precedence() {
+        let args = ServeArgs {
+            level: "debug".to_owned(),
+            timeout: 600,
+            connect_timeout: 60,
+            ..Default::default()
+        };
+        let explicit = HashSet::from(["level".to_owned()]);
+        let layers = ConfigLayers::new(&args, &explicit).unwrap();
+
+        let file = toml::from_str("level = \"warn\"\ntimeout = 300\n").unwrap();
+        let merged = layers.merge(file).unwrap();
+        // CLI/env over the file
+        assert_eq!(merged.level, "debug");
+        // File over the default
+        assert_eq!(merged.timeout, 300);
+        // Default if absent from the file
+        assert_eq!(merged.connect_timeout, 60);
+    }
+
+    #[test]
+    fn test_config_bind_one_or_many() {
+        let layers = ConfigLayers::new(&ServeArgs::default(), &HashSet::new()).unwrap();
+
+        // The single address format of the older config files
+        let file = toml::from_str("bind = \"0.0.0.0:7999\"\n").unwrap();
+        let merged = layers.merge(file).unwrap();
+        assert_eq!(merged.bind, Some(vec!["0.0.0.0:7999".parse().unwrap()]));
+
+        let file = toml::from_str("bind = [\"0.0.0.0:7999\", \"[::]:7999\"]\n").unwrap();
+        let merged = layers.merge(file).unwrap();
+        assert_eq!(
+            merged.bind,
+            Some(vec![
+                "0.0.0.0:7999".parse().unwrap(),
+                "[::]:7999".parse().unwrap()
+            ])
+        );
+    }
 }
@@ -1,26 +1,65 @@
 #[cfg(target_family = "unix")]
 use crate::utils;
 use crate::{
-    args::{self, ServeArgs},
+    args::{self, ConfigLayers, ServeArgs},
     utils::unix::fix_relative_path,
 };
 use clap::CommandFactory;
-use openai::{arkose::funcaptcha::solver::ArkoseSolver, context::args::Args, proxy, serve::Serve};
+use openai::{
+    arkose::{
+        self,
+        funcaptcha::solver::{ArkoseSolver, DifficultyRoute, SolverSigning},
+    },
+    context::args::{Args, ConfigReloader},
+    proxy,
+    serve::{bench::Bench, Serve},
+};
 use reqwest::impersonate::Impersonate;
-use std::{net::IpAddr, ops::Not, path::PathBuf, str::FromStr};
+use std::{collections::HashMap, net::IpAddr, ops::Not, path::PathBuf, str::FromStr, sync::Arc};
 use url::Url;
 
-pub(super) fn serve(mut args: ServeArgs, relative_path: bool) -> anyhow::Result<()> {
+pub(super) fn serve(args: ServeArgs, relative_path: bool) -> anyhow::Result<()> {
+    Serve::new(build_args(args, relative_path)?).run()
+}
+
+pub(super) fn bench(args: args::BenchArgs) -> anyhow::Result<()> {
+    Bench::new(
+        build_args(args.serve, false)?,
+        args.kind,
+        args.url,
+        args.requests,
+        args.concurrency,
+    )
+    .run()
+}
+
+/// Build the context args from the serve args, the config file is merged by the precedence:
+/// CLI > env > file > default
+fn build_args(mut args: ServeArgs, relative_path: bool) -> anyhow::Result<Args> {
     if relative_path {
         fix_relative_path(&mut args);
     }
 
-    if let Some(config_path) = args.config {
-        let bytes = std::fs::read(config_path)?;
-        let data = String::from_utf8(bytes)?;
-        args = toml::from_str::<ServeArgs>(&data)?;
+    let layers = ConfigLayers::new(&args, &args::explicit_ids())?;
+
+    // Re-read the config file on reload, on top of the same CLI/env/default layers
+    let config_reloader = args.config.clone().map(|config_path| -> ConfigReloader {
+        let layers = layers.clone();
+        Arc::new(move || build_context_args(layers.resolve(&config_path)?, None))
+    });
+
+    if let Some(config_path) = args.config.as_ref() {
+        args = layers.resolve(config_path)?;
     }
 
+    build_context_args(args, config_reloader)
+}
+
+/// Build the context args from the resolved serve args
+fn build_context_args(
+    args: ServeArgs,
+    config_reloader: Option<ConfigReloader>,
+) -> anyhow::Result<Args> {
     let arkose_solver = match args.arkose_solver_key.as_ref() {
         Some(client_key) => Some(ArkoseSolver::new(
             args.arkose_solver,
@@ -30,6 +69,14 @@ pub(super) fn serve(mut args: ServeArgs, relative_path: bool) -> anyhow::Result<
         )),
         None => None,
     };
+    let arkose_solver_chain = args
+        .arkose_solver_chain
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(solver, client_key, endpoint)| {
+            ArkoseSolver::new(solver, client_key, endpoint, args.arkose_solver_limit)
+        })
+        .collect::<Vec<_>>();
 
     #[cfg(target_os = "linux")]
     if let Some(ref proxies) = args.proxies {
@@ -52,36 +99,176 @@ pub(super) fn serve(mut args: ServeArgs, relative_path: bool) -> anyhow::Result<
     std::env::set_var("RUST_LOG", args.level);
 
     let builder = Args::builder()
-        .bind(args.bind)
+        .bind(match args.bind {
+            Some(bind) => bind,
+            None => openai::context::args::default_bind()?,
+        })
+        .admin_bind(args.admin_bind)
+        .queue_depth(args.queue_depth)
+        .queue_timeout(args.queue_timeout)
         .fastest_dns(args.fastest_dns)
+        .dns_overrides(args.dns_overrides.unwrap_or_default())
         .proxies(args.proxies.unwrap_or_default())
+        .auth_proxies(args.auth_proxies)
+        .auth_interface(args.auth_interface)
+        .auth_timeout(args.auth_timeout)
+        .max_clients(args.max_clients)
+        .connect_concurrency(args.connect_concurrency)
+        .balance_strategy(args.balance_strategy)
+        .sticky_sessions(args.sticky_sessions)
+        .sticky_key(args.sticky_key)
+        .ipv6_rotate_every(args.ipv6_rotate_every)
+        .ipv6_rotate_interval(args.ipv6_rotate_interval)
+        .proxy_healthcheck_url(args.proxy_healthcheck_url)
+        .proxy_healthcheck_interval(args.proxy_healthcheck_interval)
+        .precheck_proxy(args.precheck_proxy)
+        .precheck_timeout(args.precheck_timeout)
+        .upstream_ca_bundle(args.upstream_ca_bundle)
+        .danger_accept_invalid_certs(args.danger_accept_invalid_certs)
         .enable_direct(args.enable_direct)
         .cookie_store(args.cookie_store)
         .tcp_keepalive(args.tcp_keepalive)
+        .shutdown_grace_secs(args.shutdown_grace_secs)
         .no_keepalive(args.no_keepalive)
+        .disable_connection_reuse(args.disable_connection_reuse)
+        .follow_redirects(!args.no_follow_redirects)
+        .max_redirects(args.max_redirects)
         .pool_idle_timeout(args.pool_idle_timeout)
+        .pipeline_limit(args.pipeline_limit)
+        .max_conns_per_ip(args.max_conns_per_ip)
+        .retry_after_jitter_ms(args.retry_after_jitter_ms)
         .timeout(args.timeout)
         .connect_timeout(args.connect_timeout)
+        .read_idle_timeout(args.read_idle_timeout)
+        .max_upstream_body_size(args.max_upstream_body_size)
+        .slo_ms(args.slo_ms)
+        .request_budget_ms(args.request_budget_ms)
+        .error_cache_ttl(args.error_cache_ttl)
+        .error_cache_threshold(args.error_cache_threshold)
         .concurrent_limit(args.concurrent_limit)
+        .strip_response_headers(args.strip_response_headers.unwrap_or_default())
+        .rewrite_response_headers(args.rewrite_response_headers.unwrap_or_default())
+        .upstream_base(args.upstream_base)
+        .otlp_endpoint(args.otlp_endpoint)
+        .log_sample_rate(args.log_sample_rate)
+        .slow_request_threshold_ms(args.slow_request_threshold_ms)
+        .upstream_path_map(args.upstream_path_map.unwrap_or_default())
+        .audit_log_path(args.audit_log_path)
+        .fallback_models_file(args.fallback_models_file)
+        .shutdown_report_path(args.shutdown_report_path)
+        .graceful_restart(args.graceful_restart)
         .tls_cert(args.tls_cert)
         .tls_key(args.tls_key)
+        .tls_alpn(args.tls_alpn.unwrap_or_default())
         .auth_key(args.auth_key)
+        .har_upload_keys(
+            args.har_upload_keys
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(key, t)| Ok((key, arkose::Type::from_str(&t)?)))
+                .collect::<anyhow::Result<HashMap<_, _>>>()?,
+        )
         .visitor_email_whitelist(args.visitor_email_whitelist)
+        .admin_ip_allowlist(args.admin_ip_allowlist.unwrap_or_default())
+        .admin_endpoints_enabled(args.admin_endpoints_enabled)
+        .trusted_proxies(args.trusted_proxies.unwrap_or_default())
         .cf_site_key(args.cf_site_key)
         .cf_secret_key(args.cf_secret_key)
+        .cf_verify_timeout(args.cf_verify_timeout)
+        .cf_verify_retries(args.cf_verify_retries)
         .enable_webui(args.enable_webui)
+        .root_response(args.root_response)
         .arkose_endpoint(args.arkose_endpoint)
+        .arkose_endpoints(
+            args.arkose_endpoints
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(t, endpoint)| Ok((arkose::Type::from_str(&t)?, endpoint)))
+                .collect::<anyhow::Result<HashMap<_, _>>>()?,
+        )
+        .model_arkose_map(
+            args.model_arkose_map
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(model, t)| Ok((model, arkose::Type::from_str(&t)?)))
+                .collect::<anyhow::Result<HashMap<_, _>>>()?,
+        )
+        .unknown_arkose_policy(args.unknown_arkose_policy)
         .arkose_gpt3_experiment(args.arkose_gpt3_experiment)
         .arkose_gpt3_experiment_solver(args.arkose_gpt3_experiment_solver)
         .arkose_solver(arkose_solver)
+        .arkose_solver_chain(arkose_solver_chain)
+        .arkose_solver_difficulty(
+            args.arkose_solver_difficulty
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(t, (max_waves, solver_index))| {
+                    Ok((
+                        arkose::Type::from_str(&t)?,
+                        DifficultyRoute {
+                            max_waves,
+                            solver_index,
+                        },
+                    ))
+                })
+                .collect::<anyhow::Result<HashMap<_, _>>>()?,
+        )
+        .arkose_warm_pool_size(
+            args.arkose_warm_pool_size
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(t, size)| Ok((arkose::Type::from_str(&t)?, size)))
+                .collect::<anyhow::Result<HashMap<_, _>>>()?,
+        )
+        .arkose_token_ttl(
+            args.arkose_token_ttl
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(t, ttl)| Ok((arkose::Type::from_str(&t)?, ttl)))
+                .collect::<anyhow::Result<HashMap<_, _>>>()?,
+        )
+        .arkose_solver_signing(args.arkose_solver_sign_key.map(|key| SolverSigning {
+            key,
+            algorithm: args.arkose_solver_sign_algorithm,
+        }))
         .arkose_solver_tguess_endpoint(args.arkose_solver_tguess_endpoint)
         .arkose_solver_image_dir(args.arkose_solver_image_dir)
+        .solver_workers(args.solver_workers)
+        .solver_queue_timeout(args.solver_queue_timeout)
+        .arkose_solve_coalesce(args.arkose_solve_coalesce)
+        .require_solver(args.require_solver)
+        .strict_arkose(args.strict_arkose)
+        .har_no_create(args.har_no_create)
+        .har_parse_concurrency(args.har_parse_concurrency)
+        .max_har_size(args.max_har_size)
+        .har_max_age(args.har_max_age)
+        .har_max_capture_age(args.har_max_capture_age)
+        .har_affinity(args.har_affinity)
+        .primary_url(args.primary_url)
+        .config_reloader(config_reloader)
+        .har_no_watch(
+            args.har_no_watch
+                .unwrap_or_default()
+                .iter()
+                .map(|t| arkose::Type::from_str(t))
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        )
+        .startup_report(args.startup_report)
+        .debug_headers(args.debug_headers)
+        .server_timing(args.server_timing)
         .enable_file_proxy(args.enable_file_proxy)
+        .enable_connect_proxy(args.enable_connect_proxy)
+        .connect_proxy_allowlist(args.connect_proxy_allowlist.unwrap_or_default())
         .enable_arkose_proxy(args.enable_arkose_proxy)
         .pbind(args.pbind)
         .pupstream(args.pupstream)
+        .preauth_timeout(args.preauth_timeout)
+        .preauth_concurrency(args.preauth_concurrency)
         .pcert(args.pcert)
-        .pkey(args.pkey);
+        .pkey(args.pkey)
+        .preauth_event_value(args.preauth_event_value)
+        .preauth_push_policy(args.preauth_push_policy)
+        .preauth_ratio(args.preauth_ratio);
 
     #[cfg(feature = "limit")]
     let builder = builder
@@ -89,6 +276,8 @@ pub(super) fn serve(mut args: ServeArgs, relative_path: bool) -> anyhow::Result<
         .tb_strategy(args.tb_strategy)
         .tb_capacity(args.tb_capacity)
         .tb_fill_rate(args.tb_fill_rate)
+        .tb_burst_capacity(args.tb_burst_capacity)
+        .tb_burst_rate(args.tb_burst_rate)
         .tb_expired(args.tb_expired);
 
     // Parse the impersonate user agents
@@ -110,10 +299,9 @@ pub(super) fn serve(mut args: ServeArgs, relative_path: bool) -> anyhow::Result<
             }
         }
 
-        let args = builder.impersonate_uas(impersonate_uas).build();
-        Serve::new(args).run()
+        Ok(builder.impersonate_uas(impersonate_uas).build())
     } else {
-        Serve::new(builder.build()).run()
+        Ok(builder.build())
     }
 }
 
@@ -273,22 +461,33 @@ pub(super) fn generate_template(out: Option<PathBuf>) -> anyhow::Result<()> {
     };
 
     let args = args::ServeArgs {
-        bind: Some("0.0.0.0:7999".parse()?),
+        bind: Some(vec!["0.0.0.0:7999".parse()?]),
         concurrent_limit: 65535,
         timeout: 600,
         connect_timeout: 60,
         tcp_keepalive: 60,
+        shutdown_grace_secs: 30,
+        error_cache_threshold: 5,
         tb_strategy: "mem".to_string(),
         tb_enable: false,
         tb_capacity: 60,
         tb_fill_rate: 1,
+        tb_burst_rate: 1,
         tb_expired: 86400,
         cookie_store: true,
         pool_idle_timeout: 90,
+        max_redirects: 10,
+        cf_verify_timeout: 10,
+        cf_verify_retries: 2,
+        proxy_healthcheck_interval: 60,
+        precheck_timeout: 1,
         arkose_solver_limit: 3,
         level: "info".to_owned(),
+        log_sample_rate: 1.0,
         pcert: PathBuf::from("ca/cert.crt"),
         pkey: PathBuf::from("ca/key.pem"),
+        preauth_timeout: 30,
+        preauth_ratio: 1.0,
         arkose_gpt3_experiment: false,
         enable_file_proxy: false,
         proxies: Some(vec![
@@ -36,6 +36,7 @@ fn main() -> anyhow::Result<()> {
             args::ServeSubcommand::UA => print_ua_help(),
             args::ServeSubcommand::GT { out } => daemon::generate_template(out)?,
             args::ServeSubcommand::Update => update::update()?,
+            args::ServeSubcommand::Bench(args) => daemon::bench(args)?,
         }
     }
 
@@ -61,6 +62,7 @@ fn main() -> anyhow::Result<()> {
                 args::ServeSubcommand::UA => print_ua_help(),
                 args::ServeSubcommand::GT { out } => daemon::generate_template(out)?,
                 args::ServeSubcommand::Update => update::update()?,
+                args::ServeSubcommand::Bench(args) => daemon::bench(args)?,
             },
             SubCommands::Terminal => {
                 let runtime = tokio::runtime::Builder::new_multi_thread()
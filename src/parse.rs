@@ -1,5 +1,6 @@
 use anyhow::Context;
-use openai::proxy;
+use openai::{arkose, proxy};
+use std::collections::HashMap;
 use std::net::IpAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -12,6 +13,49 @@ pub fn parse_socket_addr(s: &str) -> anyhow::Result<std::net::SocketAddr> {
     Ok(addr)
 }
 
+// parse multiple socket addresses, format: addr1,addr2,addr3
+pub fn parse_socket_addrs(s: &str) -> anyhow::Result<Vec<std::net::SocketAddr>> {
+    let split = s.split(',');
+    let mut addrs: Vec<_> = vec![];
+
+    for ele in split {
+        let addr = ele.trim();
+        if addr.is_empty() {
+            continue;
+        }
+
+        addrs.push(parse_socket_addr(addr)?);
+    }
+
+    Ok(addrs)
+}
+
+// deserialize the socket addresses of the config file, either a single address
+// (supports the `addr1,addr2` format) or an array of addresses
+pub fn deserialize_socket_addrs<'de, D>(
+    deserializer: D,
+) -> Result<Option<Vec<std::net::SocketAddr>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<std::net::SocketAddr>),
+    }
+
+    Ok(
+        match <Option<OneOrMany> as serde::Deserialize>::deserialize(deserializer)? {
+            Some(OneOrMany::One(s)) => {
+                Some(parse_socket_addrs(&s).map_err(serde::de::Error::custom)?)
+            }
+            Some(OneOrMany::Many(addrs)) => Some(addrs),
+            None => None,
+        },
+    )
+}
+
 // url parse
 pub fn parse_url(s: &str) -> anyhow::Result<String> {
     let url = url::Url::parse(s)
@@ -120,3 +164,301 @@ pub fn parse_impersonate_uas(s: &str) -> anyhow::Result<Vec<String>> {
 
     Ok(uas)
 }
+
+// parse header names, format: name1,name2,name3
+pub fn parse_header_names(s: &str) -> anyhow::Result<Vec<String>> {
+    let split = s.split(',');
+    let mut names: Vec<_> = vec![];
+
+    for ele in split {
+        let name = ele.trim();
+        if name.is_empty() {
+            continue;
+        }
+
+        reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Invalid header name: {}", name))?;
+        names.push(name.to_lowercase());
+    }
+
+    Ok(names)
+}
+
+// parse header pairs, format: name1:value1,name2:value2
+pub fn parse_header_pairs(s: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let split = s.split(',');
+    let mut pairs: Vec<_> = vec![];
+
+    for ele in split {
+        if ele.trim().is_empty() {
+            continue;
+        }
+
+        let (name, value) = ele
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("Invalid header format: {}", ele))?;
+        let (name, value) = (name.trim(), value.trim());
+
+        reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Invalid header name: {}", name))?;
+        reqwest::header::HeaderValue::from_str(value)
+            .map_err(|_| anyhow::anyhow!("Invalid header value: {}", value))?;
+        pairs.push((name.to_lowercase(), value.to_owned()));
+    }
+
+    Ok(pairs)
+}
+
+// parse path map, format: from1=to1,from2=to2, the paths must start with '/'
+pub fn parse_path_map(s: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let split = s.split(',');
+    let mut pairs: Vec<_> = vec![];
+
+    for ele in split {
+        if ele.trim().is_empty() {
+            continue;
+        }
+
+        let (from, to) = ele
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid path map format: {}", ele))?;
+        let (from, to) = (from.trim(), to.trim());
+        if !from.starts_with('/') || !to.starts_with('/') {
+            anyhow::bail!("Invalid path map, the path must start with '/': {}", ele)
+        }
+        pairs.push((from.to_owned(), to.to_owned()));
+    }
+
+    Ok(pairs)
+}
+
+// parse arkose endpoints, format: type1=url1,type2=url2, support type: gpt3/gpt4/auth/platform/signup
+pub fn parse_arkose_endpoints(s: &str) -> anyhow::Result<HashMap<String, String>> {
+    let split = s.split(',');
+    let mut endpoints = HashMap::new();
+
+    for ele in split {
+        if ele.trim().is_empty() {
+            continue;
+        }
+
+        let (typed, url) = ele
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid arkose endpoint format: {}", ele))?;
+        let typed = arkose::Type::from_str(typed.trim())?;
+        endpoints.insert(typed.to_string(), parse_url(url.trim())?);
+    }
+
+    Ok(endpoints)
+}
+
+// parse arkose types, format: type1,type2, support type: gpt3/gpt4/auth/platform/signup
+pub fn parse_arkose_types(s: &str) -> anyhow::Result<Vec<String>> {
+    let mut types = vec![];
+    for ele in s.split(',') {
+        if ele.trim().is_empty() {
+            continue;
+        }
+        types.push(arkose::Type::from_str(ele.trim())?.to_string());
+    }
+    Ok(types)
+}
+
+// parse model to arkose type map, format: model1=type1,model2=type2, support type: gpt3/gpt4/auth/platform/signup
+pub fn parse_model_arkose_map(s: &str) -> anyhow::Result<HashMap<String, String>> {
+    let split = s.split(',');
+    let mut map = HashMap::new();
+
+    for ele in split {
+        if ele.trim().is_empty() {
+            continue;
+        }
+
+        let (model, typed) = ele
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid model arkose map format: {}", ele))?;
+        let typed = arkose::Type::from_str(typed.trim())?;
+        map.insert(model.trim().to_owned(), typed.to_string());
+    }
+
+    Ok(map)
+}
+
+// parse arkose solver chain, format: solver1:key1,solver2:key2@endpoint2, support solver: yescaptcha/capsolver/fcsrv
+pub fn parse_arkose_solver_chain(
+    s: &str,
+) -> anyhow::Result<Vec<(arkose::funcaptcha::solver::Solver, String, Option<String>)>> {
+    let split = s.split(',');
+    let mut chain = vec![];
+
+    for ele in split {
+        if ele.trim().is_empty() {
+            continue;
+        }
+
+        let (solver, key) = ele
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("Invalid arkose solver chain format: {}", ele))?;
+        let solver = arkose::funcaptcha::solver::Solver::from_str(solver.trim())?;
+        let (key, endpoint) = match key.split_once('@') {
+            Some((key, endpoint)) => (key, Some(parse_url(endpoint.trim())?)),
+            None => (key, None),
+        };
+        chain.push((solver, key.trim().to_owned(), endpoint));
+    }
+
+    Ok(chain)
+}
+
+// parse arkose solver difficulty routes, format: type1=waves1:index1,type2=waves2:index2, support type: gpt3/gpt4/auth/platform/signup
+pub fn parse_arkose_solver_difficulty(s: &str) -> anyhow::Result<HashMap<String, (usize, usize)>> {
+    let split = s.split(',');
+    let mut routes = HashMap::new();
+
+    for ele in split {
+        if ele.trim().is_empty() {
+            continue;
+        }
+
+        let (typed, route) = ele
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid arkose solver difficulty format: {}", ele))?;
+        let (waves, index) = route
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("Invalid arkose solver difficulty format: {}", ele))?;
+        let typed = arkose::Type::from_str(typed.trim())?;
+        routes.insert(
+            typed.to_string(),
+            (
+                waves.trim().parse::<usize>()?,
+                index.trim().parse::<usize>()?,
+            ),
+        );
+    }
+
+    Ok(routes)
+}
+
+// parse arkose warm pool sizes, format: type1=size1,type2=size2, support type: gpt3/gpt4/auth/platform/signup
+pub fn parse_arkose_warm_pool_size(s: &str) -> anyhow::Result<HashMap<String, usize>> {
+    let split = s.split(',');
+    let mut sizes = HashMap::new();
+
+    for ele in split {
+        if ele.trim().is_empty() {
+            continue;
+        }
+
+        let (typed, size) = ele
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid arkose warm pool size format: {}", ele))?;
+        let typed = arkose::Type::from_str(typed.trim())?;
+        sizes.insert(typed.to_string(), size.trim().parse::<usize>()?);
+    }
+
+    Ok(sizes)
+}
+
+// parse arkose token TTLs (seconds), format: type1=ttl1,type2=ttl2, support type: gpt3/gpt4/auth/platform/signup
+pub fn parse_arkose_token_ttl(s: &str) -> anyhow::Result<HashMap<String, u64>> {
+    let split = s.split(',');
+    let mut ttls = HashMap::new();
+
+    for ele in split {
+        if ele.trim().is_empty() {
+            continue;
+        }
+
+        let (typed, ttl) = ele
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid arkose token ttl format: {}", ele))?;
+        let typed = arkose::Type::from_str(typed.trim())?;
+        let ttl = ttl.trim().parse::<u64>()?;
+        if ttl == 0 {
+            anyhow::bail!("Arkose token ttl of {typed} must be greater than 0");
+        }
+        ttls.insert(typed.to_string(), ttl);
+    }
+
+    Ok(ttls)
+}
+
+// parse type-scoped HAR upload keys, format: type1=key1,type2=key2, support type: gpt3/gpt4/auth/platform/signup
+pub fn parse_har_upload_keys(s: &str) -> anyhow::Result<HashMap<String, String>> {
+    let split = s.split(',');
+    let mut keys = HashMap::new();
+
+    for ele in split {
+        if ele.trim().is_empty() {
+            continue;
+        }
+
+        let (typed, key) = ele
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid HAR upload key format: {}", ele))?;
+        let (typed, key) = (arkose::Type::from_str(typed.trim())?, key.trim());
+        if key.is_empty() {
+            anyhow::bail!("HAR upload key is empty: {}", ele)
+        }
+        keys.insert(key.to_owned(), typed.to_string());
+    }
+
+    Ok(keys)
+}
+
+// parse static DNS overrides, format: host1=ip1,host2=ip2
+pub fn parse_dns_overrides(s: &str) -> anyhow::Result<Vec<(String, IpAddr)>> {
+    let split = s.split(',');
+    let mut overrides = vec![];
+
+    for ele in split {
+        if ele.trim().is_empty() {
+            continue;
+        }
+
+        let (host, ip) = ele
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid DNS override format: {}", ele))?;
+        let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+        if host.is_empty() {
+            anyhow::bail!("DNS override host is empty: {}", ele)
+        }
+        overrides.push((host, ip.trim().parse::<IpAddr>()?));
+    }
+
+    Ok(overrides)
+}
+
+// parse sample rate, format: 0.0-1.0
+pub fn parse_sample_rate(s: &str) -> anyhow::Result<f64> {
+    let rate = s
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| anyhow::anyhow!("`{}` isn't a sample rate", s))?;
+    if !(0.0..=1.0).contains(&rate) {
+        anyhow::bail!("Sample rate must be between 0.0 and 1.0: {}", s)
+    }
+    Ok(rate)
+}
+
+// parse cidr list, format: cidr1,cidr2, a single address is a host cidr
+pub fn parse_cidrs(s: &str) -> anyhow::Result<Vec<cidr::IpCidr>> {
+    let split = s.split(',');
+    let mut cidrs = vec![];
+
+    for ele in split {
+        let ele = ele.trim();
+        if ele.is_empty() {
+            continue;
+        }
+
+        let cidr = match ele.parse::<IpAddr>() {
+            Ok(ip) => cidr::IpCidr::new_host(ip),
+            Err(_) => cidr::IpCidr::from_str(ele)
+                .map_err(|err| anyhow::anyhow!("Invalid CIDR: {ele} ({err})"))?,
+        };
+        cidrs.push(cidr);
+    }
+
+    Ok(cidrs)
+}